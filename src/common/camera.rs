@@ -1,8 +1,9 @@
 use glam::EulerRot;
 
 use crate::{
-    Quaternion, Vector3,
+    Matrix4, Quaternion, Vector2, Vector3,
     common::transform::{to_gl_pos, to_gl_rot},
+    state::CameraState,
 };
 
 #[derive(Clone, Copy, Debug)]
@@ -91,3 +92,24 @@ pub fn set_camera(camera: &mut Camera<f32>, pos: Vector3, angle: Vector3) {
     camera.up = (rotation * Vector3::Y).into();
     camera.forward = (rotation * Vector3::Z).into();
 }
+
+/// Ray through `ndc` (normalized device coordinates) seen from `state`,
+/// as its origin and unit direction in world coordinates.
+pub fn view_ray(state: &CameraState, aspect_ratio: f32, ndc: Vector2) -> (Vector3, Vector3) {
+    let mut camera = create_camera();
+    set_camera(&mut camera, state.pos, state.rot);
+    let projection = Matrix4::from_cols_array_2d(
+        &CameraPerspective {
+            fov: state.fov,
+            near_clip: state.near_clip,
+            far_clip: state.far_clip,
+            aspect_ratio,
+        }
+        .projection(),
+    );
+    let view = Matrix4::from_cols_array_2d(&camera.orthogonal());
+    let inv = (projection * view).inverse();
+    let near = inv.project_point3(Vector3::new(ndc.x, ndc.y, -1.));
+    let far = inv.project_point3(Vector3::new(ndc.x, ndc.y, 1.));
+    (to_gl_pos(near), to_gl_pos(far - near).normalize())
+}
@@ -1,3 +1,12 @@
+/// Decodes an sRGB encoded component into linear light.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
 pub trait Color {
     fn rgba(&self) -> [f32; 4];
 }
@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 const INFERNO_DATA: [[u8; 3]; 256] = [
     [0, 0, 4],
     [1, 0, 5],
@@ -269,3 +271,46 @@ pub fn inferno_color_map(values: impl IntoIterator<Item = f64>) -> Vec<[f32; 3]>
         })
         .collect()
 }
+
+/// Color map the normalized pressure of the slice is looked up in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMap {
+    #[default]
+    Inferno,
+    Jet,
+    Gray,
+}
+
+impl ColorMap {
+    pub const ALL: [Self; 3] = [Self::Inferno, Self::Jet, Self::Gray];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Inferno => "inferno",
+            Self::Jet => "jet",
+            Self::Gray => "gray",
+        }
+    }
+
+    /// Returns the RGB colors of `values` in `[0, 1]`.
+    pub fn colors(self, values: impl IntoIterator<Item = f64>) -> Vec<[f32; 3]> {
+        match self {
+            Self::Inferno => inferno_color_map(values),
+            Self::Jet => values
+                .into_iter()
+                .map(|v| {
+                    let v = v.clamp(0.0, 1.0) as f32;
+                    let f = |c: f32| (1.5 - (4. * v - c).abs()).clamp(0., 1.);
+                    [f(3.), f(2.), f(1.)]
+                })
+                .collect(),
+            Self::Gray => values
+                .into_iter()
+                .map(|v| {
+                    let v = v.clamp(0.0, 1.0) as f32;
+                    [v, v, v]
+                })
+                .collect(),
+        }
+    }
+}
@@ -1,5 +1,9 @@
+mod preview;
 mod transducers;
 
+pub use preview::modulation_frames;
+pub use transducers::TransState;
+
 use std::{
     f32::consts::PI,
     sync::{Arc, RwLock},
@@ -18,19 +22,34 @@ pub struct Emulator<'a> {
     pub cpu: &'a mut CPUEmulator,
     pub transducers: &'a mut [transducers::TransState],
     pub visible: &'a mut bool,
+    /// Whether the device contributes to the slice field.
     pub enable: &'a mut bool,
+    /// Transducers disabled individually, regardless of the device enable.
+    pub disabled: &'a mut [bool],
     pub thermal: &'a mut bool,
     pub drive_buffer: &'a mut [Drive],
     pub phase_buffer: &'a mut [Phase],
     pub output_mask_buffer: &'a mut [bool],
 }
 
+impl Emulator<'_> {
+    /// Writes the device enable and the individual overrides into the transducer states.
+    pub fn apply_enable(&mut self) {
+        let enable = *self.enable;
+        self.transducers
+            .iter_mut()
+            .zip(self.disabled.iter())
+            .for_each(|(s, &disabled)| s.enable = if enable && !disabled { 1. } else { 0. });
+    }
+}
+
 pub struct EmulatorWrapper {
     cpus: Vec<CPUEmulator>,
     transducers: transducers::Transducers,
     rx_buf: Arc<RwLock<Vec<RxMessage>>>,
     visible: Vec<bool>,
     enable: Vec<bool>,
+    disabled: Vec<Vec<bool>>,
     thermal: Vec<bool>,
     drive_buffer: Vec<Vec<Drive>>,
     phase_buffer: Vec<Vec<Phase>>,
@@ -45,6 +64,7 @@ impl EmulatorWrapper {
             rx_buf,
             visible: Default::default(),
             enable: Default::default(),
+            disabled: Vec::new(),
             thermal: Default::default(),
             drive_buffer: Vec::new(),
             phase_buffer: Vec::new(),
@@ -56,15 +76,24 @@ impl EmulatorWrapper {
         !self.cpus.is_empty()
     }
 
+    pub fn num_devices(&self) -> usize {
+        self.cpus.len()
+    }
+
     pub fn transducers(&self) -> &transducers::Transducers {
         &self.transducers
     }
 
+    pub fn rx_messages(&self) -> Vec<RxMessage> {
+        self.rx_buf.read().unwrap().clone()
+    }
+
     pub fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = Emulator<'a>> {
         self.cpus
             .iter_mut()
             .zip(self.visible.iter_mut())
             .zip(self.enable.iter_mut())
+            .zip(self.disabled.iter_mut())
             .zip(self.thermal.iter_mut())
             .zip(self.transducers.devices())
             .zip(self.drive_buffer.iter_mut())
@@ -73,7 +102,10 @@ impl EmulatorWrapper {
             .map(
                 |(
                     (
-                        (((((cpu, visible), enable), thermal), transducers), drive_buffer),
+                        (
+                            (((((cpu, visible), enable), disabled), thermal), transducers),
+                            drive_buffer,
+                        ),
                         phase_buffer,
                     ),
                     output_mask_buffer,
@@ -82,6 +114,7 @@ impl EmulatorWrapper {
                     transducers,
                     visible,
                     enable,
+                    disabled,
                     thermal,
                     drive_buffer,
                     phase_buffer,
@@ -162,6 +195,11 @@ impl EmulatorWrapper {
         *self.rx_buf.write().unwrap() = self.cpus.iter().map(|cpu| cpu.rx()).collect();
         self.visible = vec![true; self.cpus.len()];
         self.enable = vec![true; self.cpus.len()];
+        self.disabled = self
+            .cpus
+            .iter()
+            .map(|cpu| vec![false; cpu.num_transducers()])
+            .collect();
         self.thermal = vec![false; self.cpus.len()];
         self.drive_buffer = self
             .cpus
@@ -180,6 +218,17 @@ impl EmulatorWrapper {
             .collect();
     }
 
+    /// Whether `geometry` has the same devices with the same numbers of transducers as the emulated one,
+    /// so that it can be applied with [`Self::update_geometry`] keeping the firmware state.
+    pub fn same_layout(&self, geometry: &Geometry) -> bool {
+        self.cpus.len() == geometry.num_devices()
+            && self
+                .cpus
+                .iter()
+                .zip(geometry.iter())
+                .all(|(cpu, dev)| cpu.num_transducers() == dev.num_transducers())
+    }
+
     pub fn update_geometry(&mut self, geometry: &Geometry) {
         self.transducers.update_geometry(geometry);
     }
@@ -203,6 +252,7 @@ impl EmulatorWrapper {
         self.transducers.clear();
         self.visible.clear();
         self.enable.clear();
+        self.disabled.clear();
         self.thermal.clear();
         self.drive_buffer.clear();
         self.phase_buffer.clear();
@@ -0,0 +1,69 @@
+use std::{fs::File, io::BufReader, num::NonZeroU16, path::Path};
+
+use autd3_core::{
+    datagram::Datagram,
+    derive::*,
+    link::{MsgId, TxMessage},
+};
+use autd3_driver::firmware::operation::{OperationGenerator, OperationHandler};
+use serde::Deserialize;
+
+use crate::error::SimulatorError;
+
+/// Modulation file read by [`modulation_frames`].
+///
+/// `data` is the modulation pattern and `divide` the sampling divide of the ultrasound frequency.
+#[derive(Deserialize)]
+struct ModulationFile {
+    data: Vec<u8>,
+    divide: u16,
+}
+
+#[derive(Modulation, Debug)]
+struct FileModulation {
+    data: Vec<u8>,
+    config: SamplingConfig,
+}
+
+impl Modulation for FileModulation {
+    fn calc(self) -> Result<Vec<u8>, ModulationError> {
+        Ok(self.data)
+    }
+
+    fn sampling_config(&self) -> SamplingConfig {
+        self.config
+    }
+}
+
+/// Reads a modulation from a JSON file and packs it into the frames a controller would send for `geometry`.
+pub fn modulation_frames(
+    path: impl AsRef<Path>,
+    geometry: &Geometry,
+) -> crate::error::Result<Vec<Vec<TxMessage>>> {
+    let file: ModulationFile = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let divide = NonZeroU16::new(file.divide)
+        .ok_or_else(|| SimulatorError::InvalidModulation("divide must not be zero".to_string()))?;
+    let modulation = FileModulation {
+        data: file.data,
+        config: SamplingConfig::Divide(divide),
+    };
+
+    let mut generator = modulation
+        .operation_generator(geometry, &Environment::default(), &DeviceMask::AllEnabled)
+        .map_err(|e| SimulatorError::InvalidModulation(e.to_string()))?;
+    let mut operations = geometry
+        .iter()
+        .map(|dev| generator.generate(dev))
+        .collect::<Vec<_>>();
+
+    let mut frames = Vec::new();
+    let mut msg_id = MsgId::new(0);
+    while !OperationHandler::is_done(&operations) {
+        let mut tx = vec![TxMessage::new(); geometry.num_devices()];
+        OperationHandler::pack(msg_id, &mut operations, geometry, &mut tx, false)
+            .map_err(|e| SimulatorError::InvalidModulation(e.to_string()))?;
+        frames.push(tx);
+        msg_id.increment();
+    }
+    Ok(frames)
+}
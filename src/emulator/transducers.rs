@@ -1,13 +1,18 @@
-use autd3_driver::geometry::Geometry;
+use std::f32::consts::PI;
+
+use autd3_core::common::T4010A1_AMPLITUDE;
+use autd3_driver::{common::ULTRASOUND_FREQ, geometry::Geometry};
 use bytemuck::{Pod, Zeroable};
 
 use crate::{
-    Quaternion, Vector3, Vector4,
+    Quaternion, Vector2, Vector3, Vector4,
     common::transform::{to_gl_pos, to_gl_rot},
 };
 
+const P0: f32 = T4010A1_AMPLITUDE / (4. * PI);
+
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Zeroable, Pod)]
 pub struct TransState {
     pub amp: f32,
     pub phase: f32,
@@ -49,6 +54,45 @@ impl Transducers {
         &self.states
     }
 
+    /// Returns the complex acoustic pressure at `point` as `(re, im)` in Pa, in the same model as the slice.
+    pub fn field_at(&self, point: Vector3, sound_speed: f32) -> Vector2 {
+        let point = to_gl_pos(point);
+        let wavenum = 2. * PI * ULTRASOUND_FREQ.hz() as f32 / sound_speed;
+        self.positions
+            .iter()
+            .zip(self.states.iter())
+            .fold(Vector2::ZERO, |acc, (pos, state)| {
+                let r = pos.truncate().distance(point);
+                let p = -state.phase - wavenum * r;
+                let a = state.enable * P0 * state.amp / r;
+                acc + Vector2::new(a * p.cos(), a * p.sin())
+            })
+    }
+
+    /// Returns the mean position of all transducers, or `None` if there are none.
+    pub fn centroid(&self) -> Option<Vector3> {
+        if self.positions.is_empty() {
+            return None;
+        }
+        let sum = self
+            .positions
+            .iter()
+            .fold(Vector3::ZERO, |acc, p| acc + p.truncate());
+        Some(to_gl_pos(sum / self.positions.len() as f32))
+    }
+
+    /// Returns the position of the first transducer of device `dev`, i.e., the origin of the device.
+    pub fn device_origin(&self, dev: usize) -> Option<Vector3> {
+        let idx = *self.body_pointer.get(dev)?;
+        let pos = self.positions.get(idx)?;
+        Some(to_gl_pos(pos.truncate()))
+    }
+
+    /// Returns the acoustic pressure amplitude at `point` in Pa.
+    pub fn pressure_at(&self, point: Vector3, sound_speed: f32) -> f32 {
+        self.field_at(point, sound_speed).length()
+    }
+
     pub fn clear(&mut self) {
         self.positions.clear();
         self.rotations.clear();
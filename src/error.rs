@@ -1,5 +1,49 @@
 use std::{error::Error, fmt};
 
+/// Violation of the remote protocol by a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    HandshakeRequired,
+    HandshakeAlreadyCompleted,
+    /// Data was sent or requested before the geometry was configured.
+    GeometryRequired,
+    UnsupportedVersion(u16),
+    InvalidMagic,
+    UnknownMessage(u8),
+    /// The payload ended or timed out before all expected bytes arrived.
+    Truncated {
+        expected: usize,
+        received: usize,
+    },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HandshakeRequired => write!(f, "Handshake is required before sending commands"),
+            Self::HandshakeAlreadyCompleted => write!(f, "Handshake already completed"),
+            Self::GeometryRequired => {
+                write!(
+                    f,
+                    "Geometry must be configured before sending or reading data"
+                )
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported protocol version: {}", version)
+            }
+            Self::InvalidMagic => write!(f, "Invalid client magic"),
+            Self::UnknownMessage(msg) => write!(f, "Unknown message type: {}", msg),
+            Self::Truncated { expected, received } => write!(
+                f,
+                "Payload truncated: received {} of {} bytes",
+                received, expected
+            ),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
 #[derive(Debug)]
 pub enum SimulatorError {
     OsError(winit::error::OsError),
@@ -10,10 +54,18 @@ pub enum SimulatorError {
     RequestAdapterError(wgpu::RequestAdapterError),
     ImageError(image::ImageError),
     IoError(std::io::Error),
+    JsonError(serde_json::Error),
     NoSuitableFormat,
     ServerError(String),
+    Protocol(ProtocolError),
+    /// The simulator event loop has been closed.
+    Disconnected,
+    InvalidGeometry(String),
+    InvalidModulation(String),
     SurfaceLost,
     SurfaceValidation,
+    BufferAsyncError(wgpu::BufferAsyncError),
+    PollError(wgpu::PollError),
 }
 
 impl SimulatorError {
@@ -33,10 +85,17 @@ impl fmt::Display for SimulatorError {
             Self::RequestAdapterError(e) => write!(f, "{}", e),
             Self::ImageError(e) => write!(f, "{}", e),
             Self::IoError(e) => write!(f, "{}", e),
+            Self::JsonError(e) => write!(f, "{}", e),
             Self::NoSuitableFormat => write!(f, "Failed to select proper surface texture format"),
             Self::ServerError(e) => write!(f, "{}", e),
+            Self::Protocol(e) => write!(f, "{}", e),
+            Self::Disconnected => write!(f, "Simulator is closed"),
+            Self::InvalidGeometry(e) => write!(f, "Invalid geometry: {}", e),
+            Self::InvalidModulation(e) => write!(f, "Invalid modulation: {}", e),
             Self::SurfaceLost => write!(f, "The surface has been lost and needs to be recreated"),
             Self::SurfaceValidation => write!(f, "The surface encountered a validation error"),
+            Self::BufferAsyncError(e) => write!(f, "Failed to read back from the GPU: {}", e),
+            Self::PollError(e) => write!(f, "{}", e),
         }
     }
 }
@@ -52,10 +111,17 @@ impl Error for SimulatorError {
             Self::RequestAdapterError(e) => Some(e),
             Self::ImageError(e) => Some(e),
             Self::IoError(e) => Some(e),
+            Self::JsonError(e) => Some(e),
             Self::NoSuitableFormat => None,
             Self::ServerError(_) => None,
+            Self::Protocol(e) => Some(e),
+            Self::Disconnected => None,
+            Self::InvalidGeometry(_) => None,
+            Self::InvalidModulation(_) => None,
             Self::SurfaceLost => None,
             Self::SurfaceValidation => None,
+            Self::BufferAsyncError(e) => Some(e),
+            Self::PollError(e) => Some(e),
         }
     }
 }
@@ -108,4 +174,28 @@ impl From<std::io::Error> for SimulatorError {
     }
 }
 
+impl From<serde_json::Error> for SimulatorError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::JsonError(e)
+    }
+}
+
+impl From<wgpu::BufferAsyncError> for SimulatorError {
+    fn from(e: wgpu::BufferAsyncError) -> Self {
+        Self::BufferAsyncError(e)
+    }
+}
+
+impl From<wgpu::PollError> for SimulatorError {
+    fn from(e: wgpu::PollError) -> Self {
+        Self::PollError(e)
+    }
+}
+
+impl From<ProtocolError> for SimulatorError {
+    fn from(e: ProtocolError) -> Self {
+        Self::Protocol(e)
+    }
+}
+
 pub type Result<T> = std::result::Result<T, SimulatorError>;
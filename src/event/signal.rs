@@ -2,19 +2,28 @@ use autd3_core::link::TxMessage;
 use autd3_driver::geometry::Geometry;
 
 pub enum Signal {
+    /// A client has completed the handshake.
+    Connected,
     ConfigGeometry(Geometry),
     UpdateGeometry(Geometry),
     Send(Vec<TxMessage>),
+    /// System time of the client in ns, which the simulator time is aligned to.
+    SetTimeBase(u64),
     Close,
+    /// The client has disconnected, whether or not it closed the link first.
+    Disconnected,
 }
 
 impl std::fmt::Debug for Signal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Signal::Connected => write!(f, "Connected"),
             Signal::ConfigGeometry(_) => write!(f, "ConfigGeometry"),
             Signal::UpdateGeometry(_) => write!(f, "UpdateGeometry"),
             Signal::Send(tx) => write!(f, "Send({tx:?})"),
+            Signal::SetTimeBase(t) => write!(f, "SetTimeBase({t})"),
             Signal::Close => write!(f, "Close"),
+            Signal::Disconnected => write!(f, "Disconnected"),
         }
     }
 }
@@ -0,0 +1,69 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{error::Result, state::SliceState};
+
+/// Parameters the slice field was sampled with, written next to exported data
+/// so that it can be reproduced.
+#[derive(Serialize)]
+pub struct SamplingParams<'a> {
+    pub slice: &'a SliceState,
+    /// Number of field samples along the width and height of the slice.
+    pub resolution: (u32, u32),
+    pub sound_speed: f32,
+    /// System time in ns the field was computed at, in the time base set by the client if any.
+    pub system_time: u64,
+    pub color_map: &'static str,
+}
+
+impl<'a> SamplingParams<'a> {
+    pub fn new(state: &'a crate::State, resolution: (u32, u32)) -> Self {
+        Self {
+            slice: &state.slice,
+            resolution,
+            sound_speed: state.sound_speed,
+            system_time: state.system_time().sys_time(),
+            color_map: state.slice.color_map.name(),
+        }
+    }
+
+    /// Writes the parameters as pretty-printed JSON to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    /// Writes the parameters next to the data exported to `path`, with its extension replaced by `.json`.
+    pub fn save_beside(&self, path: &Path) -> Result<()> {
+        self.save(path.with_extension("json"))
+    }
+}
+
+/// Writes `data` as a NumPy `.npy` array of little-endian f32 in C order with `shape` (rows, columns).
+pub fn save_npy(path: impl AsRef<Path>, shape: (u32, u32), data: &[f32]) -> Result<()> {
+    const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+        shape.0, shape.1
+    );
+    // The header is padded with spaces and ends with a newline so that the data is 64-byte aligned.
+    let len = MAGIC.len() + size_of::<u16>() + header.len() + 1;
+    header.extend(std::iter::repeat_n(' ', len.next_multiple_of(64) - len));
+    header.push('\n');
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    data.iter()
+        .try_for_each(|v| writer.write_all(&v.to_le_bytes()))?;
+    writer.flush()?;
+    Ok(())
+}
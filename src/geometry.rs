@@ -0,0 +1,60 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use autd3_core::{
+    devices::AUTD3,
+    geometry::{Device, Point3, UnitQuaternion},
+};
+use autd3_driver::geometry::Geometry;
+use serde::Deserialize;
+
+use crate::error::{Result, SimulatorError};
+
+/// Position and rotation of a single device.
+///
+/// The rotation is a quaternion in `[w, i, j, k]` order, the same as in the remote protocol.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct DeviceConfig {
+    pub pos: [f32; 3],
+    pub rot: [f32; 4],
+}
+
+impl DeviceConfig {
+    /// Checks that the position is finite and the rotation is a finite, non-zero quaternion.
+    pub fn validate(&self) -> Result<()> {
+        if !self.pos.iter().all(|v| v.is_finite()) {
+            return Err(SimulatorError::InvalidGeometry(format!(
+                "non-finite position {:?}",
+                self.pos
+            )));
+        }
+        if !self.rot.iter().all(|v| v.is_finite()) || self.rot.iter().all(|&v| v == 0.) {
+            return Err(SimulatorError::InvalidGeometry(format!(
+                "invalid rotation {:?}",
+                self.rot
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl From<DeviceConfig> for Device {
+    fn from(config: DeviceConfig) -> Self {
+        let [x, y, z] = config.pos;
+        let [w, i, j, k] = config.rot;
+        AUTD3 {
+            pos: Point3::new(x, y, z),
+            rot: UnitQuaternion { w, i, j, k },
+        }
+        .into()
+    }
+}
+
+/// Loads a geometry from a JSON file containing an array of [`DeviceConfig`].
+pub fn load_geometry(path: impl AsRef<Path>) -> Result<Geometry> {
+    let file = File::open(path)?;
+    let devices: Vec<DeviceConfig> = serde_json::from_reader(BufReader::new(file))?;
+    devices.iter().try_for_each(DeviceConfig::validate)?;
+    Ok(Geometry::new(
+        devices.into_iter().map(Device::from).collect(),
+    ))
+}
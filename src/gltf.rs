@@ -0,0 +1,244 @@
+use std::{fs::File, io::Write, path::Path};
+
+use autd3_core::devices::AUTD3;
+use autd3_driver::common::mm;
+use serde_json::{Value, json};
+
+use crate::{
+    Vector3,
+    common::{color::srgb_to_linear, transform::to_gl_pos},
+    emulator::EmulatorWrapper,
+    error::Result,
+    renderer::trans_color,
+    state::State,
+};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: &[u8; 4] = b"JSON";
+const CHUNK_BIN: &[u8; 4] = b"BIN\0";
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+/// Binary buffer and the JSON arrays referring to it, built up one view at a time.
+#[derive(Default)]
+struct Builder {
+    bin: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl Builder {
+    fn view(&mut self, data: &[u8], target: Option<u32>) -> usize {
+        self.bin.resize(self.bin.len().next_multiple_of(4), 0);
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": data.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.bin.extend_from_slice(data);
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+
+    fn accessor(&mut self, data: &[f32], ty: &str, components: usize) -> usize {
+        let view = self.view(bytemuck::cast_slice(data), Some(ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": data.len() / components,
+            "type": ty,
+        }));
+        self.accessors.len() - 1
+    }
+
+    /// Positions also need their bounds.
+    fn positions(&mut self, data: &[Vector3]) -> usize {
+        let min = data.iter().fold(Vector3::INFINITY, |acc, p| acc.min(*p));
+        let max = data
+            .iter()
+            .fold(Vector3::NEG_INFINITY, |acc, p| acc.max(*p));
+        let idx = self.accessor(bytemuck::cast_slice(data), "VEC3", 3);
+        self.accessors[idx]["min"] = json!(min.to_array());
+        self.accessors[idx]["max"] = json!(max.to_array());
+        idx
+    }
+
+    fn indices(&mut self, data: &[u32]) -> usize {
+        let view = self.view(bytemuck::cast_slice(data), Some(ELEMENT_ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": data.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+}
+
+/// Indices of the two triangles of each quad of four vertices.
+fn quad_indices(quads: usize) -> Vec<u32> {
+    (0..quads as u32)
+        .flat_map(|i| [0, 1, 2, 0, 2, 3].map(|j| 4 * i + j))
+        .collect()
+}
+
+/// Writes the visible transducers, and the slice if `slice` holds its RGBA8 image with the top
+/// row first, as a binary glTF file.
+///
+/// Each transducer is a quad colored by its vertex colors, with its phase in radians in the
+/// custom `_PHASE` attribute. The scene is converted to the Y-up axes and meters of glTF.
+pub fn save_glb(
+    path: impl AsRef<Path>,
+    state: &State,
+    emulator: &EmulatorWrapper,
+    slice: Option<((u32, u32), &[u8])>,
+) -> Result<()> {
+    let mut builder = Builder::default();
+    let mut meshes = Vec::new();
+    let mut materials = Vec::new();
+
+    let transducers = emulator.transducers();
+    let visible = transducers
+        .positions()
+        .iter()
+        .zip(transducers.rotations())
+        .zip(transducers.states())
+        .filter(|(_, s)| s.alpha > 0.)
+        .collect::<Vec<_>>();
+    if !visible.is_empty() {
+        let half = AUTD3::TRANS_SPACING / 2.;
+        let corners = [(-half, -half), (half, -half), (half, half), (-half, half)];
+        let positions = visible
+            .iter()
+            .flat_map(|((p, r), _)| {
+                corners.map(|(x, y)| p.truncate() + **r * Vector3::new(x, y, 0.))
+            })
+            .collect::<Vec<_>>();
+        let colors = visible
+            .iter()
+            .flat_map(|(_, s)| {
+                let [r, g, b, a] = trans_color(state, s.phase, s.amp, s.alpha);
+                [[srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]; 4]
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+        let phases = visible
+            .iter()
+            .flat_map(|(_, s)| [s.phase - state.phase_offset.to_radians(); 4])
+            .collect::<Vec<_>>();
+
+        let position = builder.positions(&positions);
+        let color = builder.accessor(&colors, "VEC4", 4);
+        let phase = builder.accessor(&phases, "SCALAR", 1);
+        let indices = builder.indices(&quad_indices(visible.len()));
+        materials.push(json!({
+            "name": "Transducers",
+            "alphaMode": "BLEND",
+            "doubleSided": true,
+            "pbrMetallicRoughness": { "metallicFactor": 0.0 },
+            "extensions": { "KHR_materials_unlit": {} },
+        }));
+        meshes.push(json!({
+            "name": "Transducers",
+            "primitives": [{
+                "attributes": { "POSITION": position, "COLOR_0": color, "_PHASE": phase },
+                "indices": indices,
+                "material": materials.len() - 1,
+            }],
+        }));
+    }
+
+    let mut images = Vec::new();
+    if let Some(((width, height), data)) = slice {
+        let mut png = Vec::new();
+        image::write_buffer_with_format(
+            &mut std::io::Cursor::new(&mut png),
+            data,
+            width,
+            height,
+            image::ExtendedColorType::Rgba8,
+            image::ImageFormat::Png,
+        )?;
+        let image_view = builder.view(&png, None);
+        images.push(json!({ "bufferView": image_view, "mimeType": "image/png" }));
+
+        let positions = state.slice.corners().map(to_gl_pos);
+        let position = builder.positions(&positions);
+        let uv = builder.accessor(&[0., 1., 1., 1., 1., 0., 0., 0.], "VEC2", 2);
+        let indices = builder.indices(&quad_indices(1));
+        materials.push(json!({
+            "name": "Slice",
+            "doubleSided": true,
+            "pbrMetallicRoughness": {
+                "baseColorTexture": { "index": 0 },
+                "metallicFactor": 0.0,
+            },
+            "extensions": { "KHR_materials_unlit": {} },
+        }));
+        meshes.push(json!({
+            "name": "Slice",
+            "primitives": [{
+                "attributes": { "POSITION": position, "TEXCOORD_0": uv },
+                "indices": indices,
+                "material": materials.len() - 1,
+            }],
+        }));
+    }
+
+    // The root rotates the Z-up axes of the simulator to Y-up and scales its units to meters.
+    let scale = 1. / (1000. * mm);
+    let mut nodes = vec![json!({
+        "name": "AUTD3",
+        "rotation": [-std::f32::consts::FRAC_1_SQRT_2, 0.0, 0.0, std::f32::consts::FRAC_1_SQRT_2],
+        "scale": [scale, scale, scale],
+        "children": (1..=meshes.len()).collect::<Vec<_>>(),
+    })];
+    nodes.extend(
+        meshes
+            .iter()
+            .enumerate()
+            .map(|(i, mesh)| json!({ "name": mesh["name"], "mesh": i })),
+    );
+
+    let mut gltf = json!({
+        "asset": { "version": "2.0", "generator": "AUTD3 Simulator" },
+        "extensionsUsed": ["KHR_materials_unlit"],
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": materials,
+        "buffers": [{ "byteLength": builder.bin.len() }],
+        "bufferViews": builder.buffer_views,
+        "accessors": builder.accessors,
+    });
+    if !images.is_empty() {
+        gltf["images"] = json!(images);
+        gltf["samplers"] = json!([{}]);
+        gltf["textures"] = json!([{ "source": 0, "sampler": 0 }]);
+    }
+
+    let mut json = serde_json::to_vec(&gltf)?;
+    json.resize(json.len().next_multiple_of(4), b' ');
+    let mut bin = builder.bin;
+    bin.resize(bin.len().next_multiple_of(4), 0);
+
+    let chunk_header = |len: usize, ty: &[u8; 4]| [(len as u32).to_le_bytes(), *ty].concat();
+    let total = 12 + 8 + json.len() + 8 + bin.len();
+    let mut file = File::create(path)?;
+    file.write_all(GLB_MAGIC)?;
+    file.write_all(&GLB_VERSION.to_le_bytes())?;
+    file.write_all(&(total as u32).to_le_bytes())?;
+    file.write_all(&chunk_header(json.len(), CHUNK_JSON))?;
+    file.write_all(&json)?;
+    file.write_all(&chunk_header(bin.len(), CHUNK_BIN))?;
+    file.write_all(&bin)?;
+    Ok(())
+}
@@ -3,14 +3,23 @@ mod emulator;
 mod error;
 mod event;
 mod executor;
+mod export;
+mod geometry;
+#[cfg(feature = "gltf")]
+mod gltf;
+mod log;
+pub mod protocol;
 mod renderer;
 mod server;
 mod simulator;
 mod state;
 mod update_flag;
 
-pub use simulator::Simulator;
-pub use state::State;
+pub use error::{ProtocolError, SimulatorError};
+pub use geometry::{DeviceConfig, load_geometry};
+pub use log::{LogLevel, set_level as set_log_level};
+pub use simulator::{SendHook, Simulator};
+pub use state::{PresentMode, State, Tab};
 
 pub type Vector2 = glam::Vec2;
 pub type Vector3 = glam::Vec3;
@@ -24,5 +33,8 @@ pub(crate) const ZPARITY: f32 = -1.;
 #[cfg(not(feature = "left_handed"))]
 pub(crate) const ZPARITY: f32 = 1.;
 
+/// Smallest window size accepted on startup, guarding against corrupt settings.
+pub(crate) const MIN_WINDOW_SIZE: u32 = 64;
+
 pub(crate) const ULTRASOUND_PERIOD_COUNT: usize =
     1 << autd3_core::firmware::ULTRASOUND_PERIOD_COUNT_BITS;
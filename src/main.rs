@@ -1,12 +1,12 @@
 use std::{
     env,
     error::Error,
-    fs::{self, File, OpenOptions},
-    io::{BufReader, Write},
+    fs::{self, OpenOptions},
+    io::Write,
     path::Path,
 };
 
-use autd3_simulator::{Simulator, State};
+use autd3_simulator::{LogLevel, Simulator, State, Tab, load_geometry, set_log_level};
 
 fn parse_key_val<T, U>(s: &str) -> Result<(T, U), Box<dyn Error + Send + Sync + 'static>>
 where
@@ -23,22 +23,40 @@ where
 
 struct Args {
     window_size: Option<(u32, u32)>,
+    title: Option<String>,
     port: Option<u16>,
+    stream_port: Option<u16>,
     vsync: Option<bool>,
     setting_dir: Option<String>,
     setting_file: String,
+    geometry: Option<String>,
     debug: bool,
+    validation: bool,
+    gpu_validation: bool,
+    log_level: Option<LogLevel>,
+    deterministic: bool,
+    tab: Option<Tab>,
+    idle_timeout: Option<u64>,
 }
 
 impl Args {
     fn parse() -> Result<Self, Box<dyn Error>> {
         let mut args = env::args().skip(1);
         let mut window_size = None;
+        let mut title = None;
         let mut port = None;
+        let mut stream_port = None;
         let mut vsync = None;
         let mut setting_dir = None;
         let mut setting_file = String::from("settings.json");
+        let mut geometry = None;
         let mut debug = false;
+        let mut validation = false;
+        let mut gpu_validation = false;
+        let mut log_level = None;
+        let mut deterministic = false;
+        let mut tab = None;
+        let mut idle_timeout = None;
 
         while let Some(arg) = args.next() {
             match arg.as_str() {
@@ -48,6 +66,9 @@ impl Args {
                         .ok_or("--window_size requires a value (Width,Height)")?;
                     window_size = Some(parse_key_val(&val).map_err(|e| e.to_string())?);
                 }
+                "-t" | "--title" => {
+                    title = Some(args.next().ok_or("--title requires a value")?);
+                }
                 "-p" | "--port" => {
                     let val = args.next().ok_or("--port requires a value")?;
                     port = Some(
@@ -55,6 +76,13 @@ impl Args {
                             .map_err(|e: std::num::ParseIntError| e.to_string())?,
                     );
                 }
+                "--stream_port" => {
+                    let val = args.next().ok_or("--stream_port requires a value")?;
+                    stream_port = Some(
+                        val.parse()
+                            .map_err(|e: std::num::ParseIntError| e.to_string())?,
+                    );
+                }
                 "-v" | "--vsync" => {
                     let val = args.next().ok_or("--vsync requires a value")?;
                     vsync = Some(
@@ -68,9 +96,36 @@ impl Args {
                 "-s" | "--setting_file" => {
                     setting_file = args.next().ok_or("--setting_file requires a value")?;
                 }
+                "-g" | "--geometry" => {
+                    geometry = Some(args.next().ok_or("--geometry requires a value")?);
+                }
                 "-d" | "--debug" => {
                     debug = true;
                 }
+                "--validation" => {
+                    validation = true;
+                }
+                "--gpu-validation" => {
+                    gpu_validation = true;
+                }
+                "--log-level" => {
+                    let val = args.next().ok_or("--log-level requires a value")?;
+                    log_level = Some(val.parse()?);
+                }
+                "--deterministic" => {
+                    deterministic = true;
+                }
+                "--tab" => {
+                    let val = args.next().ok_or("--tab requires a value")?;
+                    tab = Some(val.parse()?);
+                }
+                "--idle-timeout" => {
+                    let val = args.next().ok_or("--idle-timeout requires a value")?;
+                    idle_timeout = Some(
+                        val.parse()
+                            .map_err(|e: std::num::ParseIntError| e.to_string())?,
+                    );
+                }
                 "-h" | "--help" => {
                     Self::print_help();
                     std::process::exit(0);
@@ -87,11 +142,20 @@ impl Args {
 
         Ok(Self {
             window_size,
+            title,
             port,
+            stream_port,
             vsync,
             setting_dir,
             setting_file,
+            geometry,
             debug,
+            validation,
+            gpu_validation,
+            log_level,
+            deterministic,
+            tab,
+            idle_timeout,
         })
     }
 
@@ -104,16 +168,41 @@ impl Args {
         println!("OPTIONS:");
         println!("    -w, --window_size <Width,Height>");
         println!("            Windows Size (Optional, if set, overrides settings from file)\n");
+        println!("    -t, --title <TITLE>");
+        println!("            Window title (Optional, if set, overrides settings from file)\n");
         println!("    -p, --port <PORT>");
         println!("            Port (Optional, if set, overrides settings from file)\n");
+        println!("    --stream_port <PORT>");
+        println!("            Stream computed slice images to viewers on this port (Optional)\n");
         println!("    -v, --vsync <VSYNC>");
         println!("            Vsync (Optional, if set, overrides settings from file)\n");
         println!("    --setting_dir <DIR>");
         println!("            Setting file dir\n");
         println!("    -s, --setting_file <FILE>");
         println!("            Setting file name [default: settings.json]\n");
+        println!("    -g, --geometry <FILE>");
+        println!("            Geometry file loaded on startup (Optional)");
+        println!("            JSON array of {{\"pos\": [x, y, z], \"rot\": [w, i, j, k]}}\n");
         println!("    -d, --debug");
-        println!("            Debug mode\n");
+        println!("            Debug mode, also enables --validation\n");
+        println!("    --validation");
+        println!("            Enable the graphics API validation layer\n");
+        println!("    --gpu-validation");
+        println!("            Enable the GPU-based validation as well (slow)\n");
+        println!("    --log-level <LEVEL>");
+        println!(
+            "            quiet, normal or verbose (Optional, if set, overrides settings from file)\n"
+        );
+        println!("    --deterministic");
+        println!("            Fixed start time, advanced by the time step every frame\n");
+        println!("    --tab <TAB>");
+        println!(
+            "            slice, camera, config or info (Optional, if set, overrides settings from file)\n"
+        );
+        println!("    --idle-timeout <SECS>");
+        println!(
+            "            Exit when no client is connected and no input is received for SECS seconds (Optional)\n"
+        );
         println!("    -h, --help");
         println!("            Print help\n");
         println!("    --version");
@@ -126,43 +215,49 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let port = arg.port;
     let window_size = arg.window_size;
+    let title = arg.title;
     let settings_path = if let Some(path) = &arg.setting_dir {
         Path::new(path).join(&arg.setting_file)
     } else {
         Path::new(&arg.setting_file).to_owned()
     };
+    let stream_port = arg.stream_port;
     let vsync = arg.vsync;
     let debug = arg.debug;
+    let log_level = arg.log_level;
+    let deterministic = arg.deterministic;
+    let tab = arg.tab;
+    let idle_timeout = arg.idle_timeout;
+    let geometry = arg.geometry.as_ref().map(load_geometry).transpose()?;
 
-    let mut state: State = if settings_path.exists() {
-        let file = File::open(&settings_path)?;
-        let reader = BufReader::new(file);
-        match serde_json::from_reader(reader) {
-            Ok(state) => state,
-            Err(e) => {
-                eprintln!(
-                    "Failed to parse settings file ({}): {}, using default settings.",
-                    settings_path.display(),
-                    e
-                );
-                Default::default()
-            }
-        }
-    } else {
-        eprintln!(
-            "Settings file ({}) not found, using default settings.",
-            settings_path.display()
-        );
-        Default::default()
-    };
+    if let Some(log_level) = log_level {
+        set_log_level(log_level);
+    }
+    let mut state = State::load_or_default(&settings_path);
 
     state.debug = debug;
+    state.validation = arg.validation;
+    state.gpu_validation = arg.gpu_validation;
+    state = state.with_deterministic(deterministic);
+    if let Some(log_level) = log_level {
+        state.log_level = log_level;
+    }
+    if let Some(tab) = tab {
+        state.tab = tab;
+    }
+    state.idle_timeout = idle_timeout;
     if let Some(port) = port {
         state.port = port;
     }
     if let Some(window_size) = window_size {
         state.window_size = window_size;
     }
+    if let Some(stream_port) = stream_port {
+        state.stream_port = Some(stream_port);
+    }
+    if let Some(title) = title {
+        state.window_title = Some(title);
+    }
     if let Some(vsync) = vsync {
         state.vsync = vsync;
     }
@@ -171,7 +266,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     let event_loop = winit::event_loop::EventLoop::with_user_event().build()?;
-    let state = Simulator::run(event_loop, state)?;
+    let state = Simulator::run_with_geometry(event_loop, state, geometry)?;
 
     {
         let settings_str = serde_json::to_string_pretty(&state)?;
@@ -0,0 +1,298 @@
+//! Wire format of the remote link protocol.
+//!
+//! # Protocol Specification
+//!
+//! ## Message Types
+//!
+//! - `0x01`: Configure Geometry
+//! - `0x02`: Update Geometry
+//! - `0x03`: Send Data
+//! - `0x04`: Read Data
+//! - `0x05`: Close
+//! - `0x07`: Send and Read Data (protocol version 2 or later)
+//! - `0x08`: Set Time Base (protocol version 3 or later)
+//! - `0x10`: Hello (handshake)
+//!
+//! ## Response Status Codes
+//!
+//! - `0x00`: OK
+//! - `0xFF`: Error
+//!
+//! ## Message Formats
+//!
+//! ### Hello (Handshake)
+//! Request:
+//! - 1 byte: message type (0x10)
+//! - 2 bytes: protocol version (u16, little-endian), 1 to 3
+//! - 11 bytes: magic string `AUTD3REMOTE`
+//!
+//! Response (Success):
+//! - 1 byte: status (0x00 = OK)
+//!
+//! ### Configure/Update Geometry
+//! Request:
+//! - 1 byte: message type (0x01 or 0x02)
+//! - 4 bytes: number of devices (u32, little-endian)
+//! - For each device:
+//!   - 12 bytes: position (3x f32, little-endian)
+//!   - 16 bytes: rotation quaternion (w, i, j, k as f32, little-endian)
+//!
+//! Response (Success):
+//! - 1 byte: status (0x00 = OK)
+//!
+//! ### Send Data
+//! Request:
+//! - 1 byte: message type (0x03)
+//! - Raw TxMessage data for each device (626 bytes each)
+//!
+//! Response (Success):
+//! - 1 byte: status (0x00 = OK)
+//!
+//! ### Read Data
+//! Request:
+//! - 1 byte: message type (0x04)
+//!
+//! Response (Success):
+//! - 1 byte: status (0x00 = OK)
+//! - Raw RxMessage data for each device (2 bytes each)
+//!
+//! ### Send and Read Data
+//! Request:
+//! - 1 byte: message type (0x07)
+//! - Raw TxMessage data for each device (626 bytes each)
+//!
+//! Response (Success):
+//! - 1 byte: status (0x00 = OK)
+//! - Raw RxMessage data for each device (2 bytes each)
+//!
+//! The number of devices is the one of the last Configure Geometry request,
+//! which must precede any data request.
+//! The raw messages are copied as they are in memory, so the simulator only builds on
+//! little-endian targets.
+//!
+//! The rx data is the latest available when the request is handled,
+//! so it does not yet reflect the tx data sent in the same request.
+//!
+//! ### Set Time Base
+//! Request:
+//! - 1 byte: message type (0x08)
+//! - 8 bytes: system time of the client in ns since the DC epoch (u64, little-endian)
+//!
+//! Response (Success):
+//! - 1 byte: status (0x00 = OK)
+//!
+//! The simulator offsets its own system time so that it equals the client's at the time of
+//! the request, and keeps the offset until the client closes the link.
+//!
+//! ### Close
+//! Request:
+//! - 1 byte: message type (0x05)
+//!
+//! Response (Success):
+//! - 1 byte: status (0x00 = OK)
+//!
+//! ### Error Response
+//! - 1 byte: status (0xFF = Error)
+//! - 4 bytes: error message length (u32, little-endian)
+//! - N bytes: error message (UTF-8 string)
+
+use autd3_core::link::{RxMessage, TxMessage};
+
+use crate::geometry::DeviceConfig;
+
+/// Configure Geometry request.
+pub const MSG_CONFIG_GEOMETRY: u8 = 0x01;
+/// Update Geometry request.
+pub const MSG_UPDATE_GEOMETRY: u8 = 0x02;
+/// Send Data request.
+pub const MSG_SEND_DATA: u8 = 0x03;
+/// Read Data request.
+pub const MSG_READ_DATA: u8 = 0x04;
+/// Close request.
+pub const MSG_CLOSE: u8 = 0x05;
+/// Send and Read Data request, available from protocol version 2.
+pub const MSG_SEND_AND_READ_DATA: u8 = 0x07;
+/// Set Time Base request, available from protocol version 3.
+pub const MSG_SET_TIME_BASE: u8 = 0x08;
+/// Hello (handshake) request.
+pub const MSG_HELLO: u8 = 0x10;
+
+/// Status of a successful response.
+pub const MSG_OK: u8 = 0x00;
+/// Status of an error response.
+pub const MSG_ERROR: u8 = 0xFF;
+
+/// Latest protocol version supported by the simulator.
+pub const REMOTE_PROTOCOL_VERSION: u16 = 3;
+/// Oldest protocol version accepted by the simulator.
+pub const MIN_REMOTE_PROTOCOL_VERSION: u16 = 1;
+/// Magic string sent with the handshake.
+pub const REMOTE_PROTOCOL_MAGIC: &[u8; 11] = b"AUTD3REMOTE";
+
+/// Size of a device entry in a geometry message.
+pub const DEVICE_CONFIG_SIZE: usize = 7 * size_of::<f32>();
+
+/// Size of the raw TxMessage of a device.
+pub const TX_MESSAGE_SIZE: usize = 626;
+/// Size of the raw RxMessage of a device.
+pub const RX_MESSAGE_SIZE: usize = 2;
+
+// The raw copies of the server rely on the layout of the messages of `autd3_core`.
+const _: () = assert!(size_of::<TxMessage>() == TX_MESSAGE_SIZE);
+const _: () = assert!(size_of::<RxMessage>() == RX_MESSAGE_SIZE);
+const _: () = assert!(
+    cfg!(target_endian = "little"),
+    "the remote protocol copies little-endian messages as they are in memory"
+);
+
+/// Name of a request message type for logs, or `"Unknown"`.
+pub fn message_name(msg: u8) -> &'static str {
+    match msg {
+        MSG_CONFIG_GEOMETRY => "Configure Geometry",
+        MSG_UPDATE_GEOMETRY => "Update Geometry",
+        MSG_SEND_DATA => "Send Data",
+        MSG_READ_DATA => "Read Data",
+        MSG_CLOSE => "Close",
+        MSG_SEND_AND_READ_DATA => "Send and Read Data",
+        MSG_SET_TIME_BASE => "Set Time Base",
+        MSG_HELLO => "Hello",
+        _ => "Unknown",
+    }
+}
+
+/// Encodes a Hello request for the given protocol version.
+pub fn encode_hello(version: u16) -> Vec<u8> {
+    let mut buffer =
+        Vec::with_capacity(size_of::<u8>() + size_of::<u16>() + REMOTE_PROTOCOL_MAGIC.len());
+    buffer.push(MSG_HELLO);
+    buffer.extend_from_slice(&version.to_le_bytes());
+    buffer.extend_from_slice(REMOTE_PROTOCOL_MAGIC);
+    buffer
+}
+
+/// Encodes a Configure or Update Geometry request, `msg` being [`MSG_CONFIG_GEOMETRY`] or [`MSG_UPDATE_GEOMETRY`].
+pub fn encode_geometry(msg: u8, devices: &[DeviceConfig]) -> Vec<u8> {
+    let mut buffer =
+        Vec::with_capacity(size_of::<u8>() + size_of::<u32>() + devices.len() * DEVICE_CONFIG_SIZE);
+    buffer.push(msg);
+    buffer.extend_from_slice(&(devices.len() as u32).to_le_bytes());
+    devices.iter().for_each(|dev| {
+        dev.pos
+            .iter()
+            .chain(dev.rot.iter())
+            .for_each(|v| buffer.extend_from_slice(&v.to_le_bytes()));
+    });
+    buffer
+}
+
+/// Encodes a Set Time Base request with the system time of the client in ns.
+pub fn encode_set_time_base(sys_time: u64) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(size_of::<u8>() + size_of::<u64>());
+    buffer.push(MSG_SET_TIME_BASE);
+    buffer.extend_from_slice(&sys_time.to_le_bytes());
+    buffer
+}
+
+/// Decodes a device entry of a geometry message.
+pub fn decode_device(buf: &[u8; DEVICE_CONFIG_SIZE]) -> DeviceConfig {
+    let v =
+        |i: usize| f32::from_le_bytes([buf[4 * i], buf[4 * i + 1], buf[4 * i + 2], buf[4 * i + 3]]);
+    DeviceConfig {
+        pos: [v(0), v(1), v(2)],
+        rot: [v(3), v(4), v(5), v(6)],
+    }
+}
+
+/// Encodes an error response.
+pub fn encode_error(message: &str) -> Vec<u8> {
+    let bytes = message.as_bytes();
+    let mut buffer = Vec::with_capacity(size_of::<u8>() + size_of::<u32>() + bytes.len());
+    buffer.push(MSG_ERROR);
+    buffer.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(bytes);
+    buffer
+}
+
+/// Decodes the payload of an error response following the status byte.
+///
+/// Returns `None` if the payload is truncated or the message is not valid UTF-8.
+pub fn decode_error(payload: &[u8]) -> Option<String> {
+    let (len, message) = payload.split_first_chunk::<{ size_of::<u32>() }>()?;
+    let message = message.get(..u32::from_le_bytes(*len) as usize)?;
+    String::from_utf8(message.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hello() {
+        let buffer = encode_hello(REMOTE_PROTOCOL_VERSION);
+        assert_eq!(buffer[0], MSG_HELLO);
+        assert_eq!(
+            u16::from_le_bytes([buffer[1], buffer[2]]),
+            REMOTE_PROTOCOL_VERSION
+        );
+        assert_eq!(&buffer[3..], REMOTE_PROTOCOL_MAGIC);
+    }
+
+    #[test]
+    fn geometry_round_trip() {
+        let devices = [
+            DeviceConfig {
+                pos: [0., 0., 0.],
+                rot: [1., 0., 0., 0.],
+            },
+            DeviceConfig {
+                pos: [192., -1.5, 3.25],
+                rot: [0.5, -0.5, 0.5, -0.5],
+            },
+        ];
+        let buffer = encode_geometry(MSG_CONFIG_GEOMETRY, &devices);
+        assert_eq!(buffer[0], MSG_CONFIG_GEOMETRY);
+        assert_eq!(
+            u32::from_le_bytes(buffer[1..5].try_into().unwrap()),
+            devices.len() as u32
+        );
+        let entries = buffer[5..].chunks_exact(DEVICE_CONFIG_SIZE);
+        assert!(entries.remainder().is_empty());
+        assert_eq!(entries.len(), devices.len());
+        entries.zip(&devices).for_each(|(entry, dev)| {
+            let decoded = decode_device(entry.try_into().unwrap());
+            assert_eq!(decoded.pos, dev.pos);
+            assert_eq!(decoded.rot, dev.rot);
+        });
+    }
+
+    #[test]
+    fn set_time_base() {
+        let buffer = encode_set_time_base(0x0123_4567_89AB_CDEF);
+        assert_eq!(buffer[0], MSG_SET_TIME_BASE);
+        assert_eq!(
+            u64::from_le_bytes(buffer[1..].try_into().unwrap()),
+            0x0123_4567_89AB_CDEF
+        );
+    }
+
+    #[test]
+    fn error_round_trip() {
+        let buffer = encode_error("Port 8080 is already in use");
+        assert_eq!(buffer[0], MSG_ERROR);
+        assert_eq!(
+            decode_error(&buffer[1..]).as_deref(),
+            Some("Port 8080 is already in use")
+        );
+        assert_eq!(decode_error(&encode_error("")[1..]).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn error_truncated() {
+        let buffer = encode_error("error");
+        // Within the length
+        assert_eq!(decode_error(&buffer[1..3]), None);
+        // Within the message
+        assert_eq!(decode_error(&buffer[1..buffer.len() - 1]), None);
+        assert_eq!(decode_error(&[]), None);
+    }
+}
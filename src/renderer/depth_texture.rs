@@ -1,13 +1,37 @@
 use wgpu::{Device, SurfaceConfiguration};
 
+use crate::state::DepthFormat;
+
 pub struct DepthTexture {
     view: wgpu::TextureView,
+    format: wgpu::TextureFormat,
 }
 
-impl DepthTexture {
-    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+/// Texture format of the depth buffer in `format`.
+pub fn texture_format(format: DepthFormat) -> wgpu::TextureFormat {
+    match format {
+        DepthFormat::Depth32Float => wgpu::TextureFormat::Depth32Float,
+        DepthFormat::Depth24Plus => wgpu::TextureFormat::Depth24Plus,
+    }
+}
+
+/// Depth test of the pipelines drawing into a depth buffer of `format`.
+pub fn depth_stencil_state(format: wgpu::TextureFormat) -> wgpu::DepthStencilState {
+    wgpu::DepthStencilState {
+        format,
+        depth_write_enabled: Some(true),
+        depth_compare: Some(wgpu::CompareFunction::Less),
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    }
+}
 
-    pub fn new(device: &Device, surface_config: &SurfaceConfiguration) -> Self {
+impl DepthTexture {
+    pub fn new(
+        device: &Device,
+        surface_config: &SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+    ) -> Self {
         let size = wgpu::Extent3d {
             width: surface_config.width.max(1),
             height: surface_config.height.max(1),
@@ -19,7 +43,7 @@ impl DepthTexture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         };
@@ -27,10 +51,14 @@ impl DepthTexture {
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        Self { view }
+        Self { view, format }
     }
 
     pub fn view(&self) -> &wgpu::TextureView {
         &self.view
     }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
 }
@@ -2,13 +2,10 @@ use std::time::Instant;
 use std::{collections::BTreeMap, sync::Arc};
 
 use autd3_core::firmware::Segment;
-use autd3_driver::{
-    common::{METER, ULTRASOUND_FREQ, ULTRASOUND_PERIOD, mm},
-    ethercat::DcSysTime,
-};
+use autd3_driver::common::{METER, ULTRASOUND_FREQ, ULTRASOUND_PERIOD, mm};
 use egui::{
-    ClippedPrimitive, DragValue, FullOutput, InputState, PointerButton, ViewportId, ViewportInfo,
-    ViewportOutput, color_picker::color_picker_color32, epaint::textures,
+    ClippedPrimitive, DragValue, FullOutput, InputState, Key, PointerButton, ViewportId,
+    ViewportInfo, ViewportOutput, color_picker::color_picker_color32, epaint::textures,
 };
 use egui_plot::{GridMark, Line, PlotPoints};
 use egui_wgpu::{
@@ -23,14 +20,77 @@ use glam::{EulerRot, Quat};
 use wgpu::{Device, Queue, SurfaceConfiguration};
 use winit::{event_loop::EventLoopProxy, window::Window};
 
+use crate::common::camera::view_ray;
+use crate::common::color::{Color as _, Hsv};
+use crate::common::color_map::ColorMap;
 use crate::emulator::EmulatorWrapper;
 use crate::event::{EventResult, UserEvent};
-use crate::state::Tab;
+use crate::log;
+use crate::server::ServerLog;
+use crate::state::{
+    DepthFormat, FieldQuantity, MARKER_RESOLUTION_RANGE, MAX_CONTOURS, MIN_COLOR_MAP_RANGE,
+    PresentMode, SPL_RANGE, SPL_REFERENCE, SlicePick, SliceState, Tab,
+};
 use crate::update_flag::UpdateFlag;
-use crate::{ULTRASOUND_PERIOD_COUNT, Vector3, ZPARITY, error::Result};
+use crate::{ULTRASOUND_PERIOD_COUNT, Vector2, Vector3, ZPARITY, error::Result};
 
 const MIN_COL_WIDTH: f32 = 120.;
 const SPACING: [f32; 2] = [2.0, 4.0];
+// Range of the camera RX that keeps it from tumbling past vertical
+#[cfg(not(feature = "unity"))]
+const CAMERA_PITCH_RANGE: std::ops::RangeInclusive<f32> = 1.0..=179.0;
+#[cfg(feature = "unity")]
+const CAMERA_PITCH_RANGE: std::ops::RangeInclusive<f32> = -89.0..=89.0;
+// Samples per side of the slice searched for the peak pressure, and the headroom added to it
+const AUTO_PRESSURE_SAMPLES: usize = 64;
+const AUTO_PRESSURE_MARGIN: f32 = 1.1;
+// Upper bound of the overlay line width in points
+const MAX_LINE_WIDTH: f32 = 8.;
+const MAX_FADE_DURATION: f32 = 2.;
+const SLICE_PRESET_KEYS: [Key; 9] = [
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+];
+// Checkboxes per row in the transducer override grid
+const OVERRIDE_COLUMNS: usize = 18;
+// Upper bound of the egui layout passes per frame offered in the UI
+const MAX_PASSES_LIMIT: u32 = 8;
+// Forward jumps of the system time larger than this are reported as discontinuities
+const TIME_JUMP_THRESHOLD_NS: u64 = 60_000_000_000;
+const TIME_JUMP_NOTE_DURATION: std::time::Duration = std::time::Duration::from_secs(3);
+// Size in points of the color strip of the legend, the segments it is drawn with and its ticks
+const LEGEND_SIZE: [f32; 2] = [16., 160.];
+const LEGEND_SEGMENTS: usize = 64;
+const LEGEND_TICKS: usize = 5;
+// Texels along the longer side of the slice offered in the UI
+const SLICE_RESOLUTIONS: [u32; 4] = [256, 512, 1024, 2048];
+
+/// A discontinuity of `real_time`, from the first to the second value.
+#[derive(Clone, Copy)]
+struct TimeJump {
+    at: Instant,
+    from: u64,
+    to: u64,
+}
+
+/// Adapter and surface in use, shown in the Info tab for bug reports.
+struct GpuInfo {
+    adapter: wgpu::AdapterInfo,
+    format: wgpu::TextureFormat,
+    present_mode: wgpu::PresentMode,
+    /// Present modes supported by the surface.
+    present_modes: Vec<wgpu::PresentMode>,
+    shader_f16: bool,
+    /// Names and sizes in bytes of the GPU buffers allocated for the geometry.
+    buffer_sizes: Vec<(&'static str, u64)>,
+}
 
 pub struct EguiRenderer {
     beginning: Instant,
@@ -43,16 +103,24 @@ pub struct EguiRenderer {
     close: bool,
     is_first_frame: bool,
     initial_state: String,
+    last_real_time: u64,
+    time_jump: Option<TimeJump>,
+    gpu_info: GpuInfo,
+    server_log: ServerLog,
 }
 
 impl EguiRenderer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         state: &crate::State,
         device: &Device,
         event_loop_proxy: EventLoopProxy<UserEvent>,
         egui_ctx: egui::Context,
         window: Arc<Window>,
+        server_log: ServerLog,
         surface_config: &SurfaceConfiguration,
+        adapter_info: wgpu::AdapterInfo,
+        present_modes: Vec<wgpu::PresentMode>,
     ) -> Self {
         {
             egui_ctx.set_request_repaint_callback(move |info| {
@@ -103,18 +171,34 @@ impl EguiRenderer {
             close: false,
             is_first_frame: true,
             initial_state: serde_json::to_string(state).unwrap(),
+            last_real_time: state.real_time,
+            time_jump: None,
+            gpu_info: GpuInfo {
+                adapter: adapter_info,
+                format: surface_config.format,
+                present_mode: surface_config.present_mode,
+                present_modes,
+                shader_f16: device.features().contains(wgpu::Features::SHADER_F16),
+                buffer_sizes: Vec::new(),
+            },
+            server_log,
         }
     }
 
-    pub fn create_egui_context() -> egui::Context {
+    pub fn create_egui_context(state: &crate::State) -> egui::Context {
         let egui_ctx = egui::Context::default();
         egui_ctx.set_embed_viewports(false);
-        egui_ctx.options_mut(|o| {
-            o.max_passes = 2.try_into().unwrap();
-        });
+        Self::set_max_passes(&egui_ctx, state.max_passes);
         egui_ctx
     }
 
+    fn set_max_passes(ctx: &egui::Context, max_passes: u32) {
+        let max_passes = max_passes.clamp(1, MAX_PASSES_LIMIT);
+        ctx.options_mut(|o| {
+            o.max_passes = (max_passes as usize).try_into().unwrap();
+        });
+    }
+
     pub fn context(&self) -> &egui::Context {
         self.egui_winit.egui_ctx()
     }
@@ -123,6 +207,14 @@ impl EguiRenderer {
         self.close
     }
 
+    pub fn set_buffer_sizes(&mut self, buffer_sizes: Vec<(&'static str, u64)>) {
+        self.gpu_info.buffer_sizes = buffer_sizes;
+    }
+
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        self.gpu_info.present_mode = present_mode;
+    }
+
     pub fn info(&mut self) -> &mut ViewportInfo {
         &mut self.info
     }
@@ -137,11 +229,28 @@ impl EguiRenderer {
     ) -> FullOutput {
         raw_input.time = Some(self.beginning.elapsed().as_secs_f64());
 
+        if state.real_time < self.last_real_time
+            || state.real_time - self.last_real_time > TIME_JUMP_THRESHOLD_NS
+        {
+            self.time_jump = Some(TimeJump {
+                at: Instant::now(),
+                from: self.last_real_time,
+                to: state.real_time,
+            });
+        }
+        self.last_real_time = state.real_time;
+        if self
+            .time_jump
+            .is_some_and(|jump| jump.at.elapsed() > TIME_JUMP_NOTE_DURATION)
+        {
+            self.time_jump = None;
+        }
+
         let close_requested = raw_input.viewport().close_requested();
 
         let full_output = self.egui_winit.egui_ctx().run_ui(raw_input, |egui_ctx| {
             if waiting {
-                self._waiting(egui_ctx);
+                self._waiting(egui_ctx, state);
             } else {
                 self._update(egui_ctx, state, emulator, update_flag);
             }
@@ -356,6 +465,57 @@ impl EguiRenderer {
         }
     }
 
+    fn translate_camera(state: &mut crate::State, trans: Vector3) {
+        let [lock_x, lock_y, lock_z] = state.camera_pos_lock;
+        if !lock_x {
+            state.camera.pos.x += trans.x;
+        }
+        if !lock_y {
+            state.camera.pos.y += trans.y;
+        }
+        if !lock_z {
+            state.camera.pos.z += trans.z;
+        }
+    }
+
+    fn translate_slice(state: &mut crate::State, trans: Vector3) {
+        let [lock_x, lock_y, lock_z] = state.slice_pos_lock;
+        if !lock_x {
+            state.slice.pos.x += trans.x;
+        }
+        if !lock_y {
+            state.slice.pos.y += trans.y;
+        }
+        if !lock_z {
+            state.slice.pos.z += trans.z;
+        }
+    }
+
+    /// Arrow keys move the slice within its plane, Page Up/Down along its normal.
+    fn nudge_slice_by_keyboard(
+        input: &InputState,
+        state: &mut crate::State,
+        update_flag: &mut UpdateFlag,
+    ) {
+        let rotation = state.slice.rotation();
+        let trans = [
+            (Key::ArrowRight, Vector3::X),
+            (Key::ArrowLeft, -Vector3::X),
+            (Key::ArrowUp, Vector3::Y),
+            (Key::ArrowDown, -Vector3::Y),
+            (Key::PageUp, Vector3::Z),
+            (Key::PageDown, -Vector3::Z),
+        ]
+        .into_iter()
+        .filter(|(key, _)| input.key_pressed(*key))
+        .map(|(_, dir)| rotation * dir * state.slice_nudge_step)
+        .sum::<Vector3>();
+        if trans != Vector3::ZERO {
+            Self::translate_slice(state, trans);
+            update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
+        }
+    }
+
     fn update_camera_by_mouse(
         input: &InputState,
         state: &mut crate::State,
@@ -371,8 +531,14 @@ impl EguiRenderer {
             egui::Event::MouseWheel { delta, .. } => Some(*delta),
             _ => None,
         }) {
-            let trans = -f * mouse_wheel.y * state.camera.move_speed * 10. * ZPARITY;
-            state.camera.pos += trans;
+            let direction = if state.camera.invert_zoom { -1. } else { 1. };
+            let trans = -f
+                * mouse_wheel.y
+                * state.camera.move_speed
+                * state.camera.zoom_speed
+                * direction
+                * ZPARITY;
+            Self::translate_camera(state, trans);
             update_flag.set(UpdateFlag::UPDATE_CAMERA, true);
         }
 
@@ -383,9 +549,7 @@ impl EguiRenderer {
                     let delta_x = mouse_delta[0] * state.camera.move_speed;
                     let delta_y = mouse_delta[1] * state.camera.move_speed;
                     let trans = -r * delta_x + u * delta_y;
-                    state.camera.pos.x += trans.x;
-                    state.camera.pos.y += trans.y;
-                    state.camera.pos.z += trans.z;
+                    Self::translate_camera(state, trans);
                     update_flag.set(UpdateFlag::UPDATE_CAMERA, true);
                 } else {
                     let delta_x = -mouse_delta[0] * state.camera.move_speed / METER * ZPARITY;
@@ -397,6 +561,7 @@ impl EguiRenderer {
                     state.camera.rot.x = rx.to_degrees();
                     state.camera.rot.y = ry.to_degrees();
                     state.camera.rot.z = rz.to_degrees();
+                    Self::clamp_camera_pitch(state);
                     update_flag.set(UpdateFlag::UPDATE_CAMERA, true);
                 }
             }
@@ -410,55 +575,414 @@ impl EguiRenderer {
         emulator: &mut EmulatorWrapper,
         update_flag: &mut crate::update_flag::UpdateFlag,
     ) {
-        egui::Window::new("Control panel")
-            .resizable(true)
-            .vscroll(true)
-            .default_open(true)
-            .show(ctx, |ui| {
-                ui.horizontal(|ui| {
-                    ui.selectable_value(&mut state.tab, Tab::Slice, "Slice");
-                    ui.selectable_value(&mut state.tab, Tab::Camera, "Camera");
-                    ui.selectable_value(&mut state.tab, Tab::Config, "Config");
-                    ui.selectable_value(&mut state.tab, Tab::Info, "Info");
+        let cursor_pos =
+            Self::cursor_on_slice(ctx, state).map(|p| p - Self::display_origin(state, emulator));
+        let response = Self::control_panel(state).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut state.tab, Tab::Slice, "Slice");
+                ui.selectable_value(&mut state.tab, Tab::Camera, "Camera");
+                ui.selectable_value(&mut state.tab, Tab::Config, "Config");
+                ui.selectable_value(&mut state.tab, Tab::Info, "Info");
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    Self::origin_selector(ui, state, emulator);
                 });
-                ui.separator();
-                match state.tab {
-                    Tab::Slice => Self::slice_tab(ui, state, update_flag),
-                    Tab::Camera => Self::camera_tab(ui, state, update_flag),
-                    Tab::Config => Self::config_tab(ui, state, emulator, update_flag),
-                    Tab::Info => Self::info_tab(ui, state, emulator, update_flag),
+            });
+            ui.separator();
+            match state.tab {
+                Tab::Slice => {
+                    Self::slice_tab(ui, state, emulator, &self.initial_state, update_flag)
                 }
+                Tab::Camera => {
+                    Self::camera_tab(ui, state, emulator, &self.initial_state, update_flag)
+                }
+                Tab::Config => Self::config_tab(ui, state, emulator, &self.gpu_info, update_flag),
+                Tab::Info => Self::info_tab(
+                    ui,
+                    state,
+                    emulator,
+                    &self.gpu_info,
+                    &self.server_log,
+                    self.time_jump,
+                    update_flag,
+                ),
+            }
 
-                ui.separator();
+            ui.separator();
 
-                ui.horizontal(|ui| {
-                    if ui.small_button("Default").clicked() {
-                        state.merge(crate::State::default());
-                        *update_flag = UpdateFlag::all();
-                    }
+            ui.horizontal(|ui| {
+                if ui.small_button("Default").clicked() {
+                    state.merge(crate::State::default());
+                    Self::set_max_passes(ctx, state.max_passes);
+                    *update_flag = UpdateFlag::all();
+                }
 
-                    if ui.small_button("Reset").clicked() {
-                        let initial_state: crate::State =
-                            serde_json::from_str(&self.initial_state).unwrap();
-                        state.merge(initial_state);
-                        *update_flag = UpdateFlag::all();
-                    }
-                });
+                if ui.small_button("Reset").clicked() {
+                    let initial_state: crate::State =
+                        serde_json::from_str(&self.initial_state).unwrap();
+                    state.merge(initial_state);
+                    Self::set_max_passes(ctx, state.max_passes);
+                    *update_flag = UpdateFlag::all();
+                }
             });
 
+            if state.show_cursor_pos {
+                ui.separator();
+                ui.label(match cursor_pos {
+                    Some(p) => format!("Cursor: ({:.3}, {:.3}, {:.3})", p.x, p.y, p.z),
+                    None => "Cursor: \u{2014}".to_string(),
+                });
+            }
+        });
+        Self::store_panel_rect(ctx, state, response);
+
+        // Phase coloring does not use the color map
+        if state.show_legend && state.slice.quantity != FieldQuantity::PhasePressure {
+            Self::legend(ctx, &state.slice);
+        }
+
         if !ctx.egui_wants_pointer_input() {
             ctx.input(|input| {
                 Self::update_camera_by_mouse(input, state, update_flag);
             });
+            if ctx.input(|input| input.pointer.primary_clicked()) {
+                state.slice_pick = Self::pointer_on_slice(ctx, state).and_then(|point| {
+                    Some(SlicePick {
+                        point,
+                        uv: state.slice.uv(point)?,
+                        pressure: None,
+                    })
+                });
+            }
+        }
+
+        if !ctx.egui_wants_keyboard_input() && ctx.input(|input| input.key_pressed(Key::Home)) {
+            state.camera = crate::State::default().camera;
+            update_flag.set(UpdateFlag::UPDATE_CAMERA, true);
+        }
+
+        if !ctx.egui_wants_keyboard_input()
+            && let Some(i) = ctx.input(|input| {
+                SLICE_PRESET_KEYS
+                    .iter()
+                    .position(|key| input.key_pressed(*key))
+            })
+        {
+            Self::recall_slice_preset(state, i, update_flag);
         }
 
-        if state.auto_play {
+        if state.tab == Tab::Slice && !ctx.egui_wants_keyboard_input() {
+            ctx.input(|input| Self::nudge_slice_by_keyboard(input, state, update_flag));
+        }
+
+        // While recording, the clock is advanced by the recording step after each captured frame
+        if state.auto_play && state.recording.is_none() {
             update_flag.set(UpdateFlag::UPDATE_TRANS_STATE, true);
-            state.real_time = (DcSysTime::now().sys_time() as f64 * state.time_scale as f64) as _;
+            state.real_time = if state.deterministic {
+                state.real_time.wrapping_add(state.effective_time_step())
+            } else {
+                state.clock_time()
+            };
+        }
+    }
+
+    /// Editor of the pressures at which contour lines are drawn over the slice.
+    fn contours(ui: &mut egui::Ui, state: &mut crate::State, update_flag: &mut UpdateFlag) {
+        let mut remove = None;
+        let mut changed = false;
+        egui::Grid::new("slice_contours_grid")
+            .num_columns(2)
+            .min_col_width(MIN_COL_WIDTH)
+            .spacing(SPACING)
+            .striped(true)
+            .show(ui, |ui| {
+                state
+                    .slice
+                    .contours
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(i, level)| {
+                        ui.label("Pressure [Pa]:");
+                        ui.horizontal(|ui| {
+                            changed |= ui
+                                .add(DragValue::new(level).speed(100.).range(0.0..=f32::MAX))
+                                .changed();
+                            if ui.small_button("Remove").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                        ui.end_row();
+                    });
+            });
+        if let Some(i) = remove {
+            state.slice.contours.remove(i);
+            changed = true;
+        }
+        if ui
+            .add_enabled(
+                state.slice.contours.len() < MAX_CONTOURS,
+                egui::Button::new("Add"),
+            )
+            .on_hover_text("Draw a contour line at half the max pressure, then edit its level")
+            .clicked()
+        {
+            state.slice.contours.push(state.slice.pressure_max / 2.);
+            changed = true;
+        }
+        if changed {
+            update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+        }
+    }
+
+    /// Color map of the slice with the values at evenly spaced positions, at the bottom right.
+    fn legend(ctx: &egui::Context, slice: &SliceState) {
+        egui::Area::new(egui::Id::new("legend"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-8., -8.))
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.label(slice.quantity.name());
+                    let labels = (0..LEGEND_TICKS)
+                        .map(|i| Self::legend_label(slice, i as f32 / (LEGEND_TICKS - 1) as f32))
+                        .collect::<Vec<_>>();
+                    let font = egui::TextStyle::Small.resolve(ui.style());
+                    let label_width = labels
+                        .iter()
+                        .map(|label| {
+                            ui.fonts_mut(|fonts| {
+                                fonts
+                                    .layout_no_wrap(
+                                        label.clone(),
+                                        font.clone(),
+                                        egui::Color32::WHITE,
+                                    )
+                                    .size()
+                                    .x
+                            })
+                        })
+                        .fold(0., f32::max);
+                    let [width, height] = LEGEND_SIZE;
+                    let spacing = ui.spacing().item_spacing.x;
+                    let (rect, _) = ui.allocate_exact_size(
+                        egui::vec2(width + spacing + label_width, height),
+                        egui::Sense::hover(),
+                    );
+                    // Leaves room for the top and bottom labels
+                    let strip = egui::Rect::from_min_size(
+                        rect.min + egui::vec2(0., font.size / 2.),
+                        egui::vec2(width, height - font.size),
+                    );
+
+                    let colors = slice.colors(
+                        slice.color_map,
+                        (0..=LEGEND_SEGMENTS).map(|i| i as f64 / LEGEND_SEGMENTS as f64),
+                    );
+                    let mut mesh = egui::Mesh::default();
+                    colors.into_iter().enumerate().for_each(|(i, [r, g, b])| {
+                        let y = strip.bottom() - strip.height() * i as f32 / LEGEND_SEGMENTS as f32;
+                        let color = egui::Color32::from_rgb(
+                            (r * 255.) as u8,
+                            (g * 255.) as u8,
+                            (b * 255.) as u8,
+                        );
+                        mesh.colored_vertex(egui::pos2(strip.left(), y), color);
+                        mesh.colored_vertex(egui::pos2(strip.right(), y), color);
+                        if i > 0 {
+                            let k = 2 * i as u32;
+                            mesh.add_triangle(k - 2, k - 1, k);
+                            mesh.add_triangle(k - 1, k + 1, k);
+                        }
+                    });
+                    let painter = ui.painter();
+                    painter.add(egui::Shape::mesh(mesh));
+
+                    let stroke = ui.visuals().widgets.noninteractive.fg_stroke;
+                    labels.into_iter().enumerate().for_each(|(i, label)| {
+                        let y =
+                            strip.bottom() - strip.height() * i as f32 / (LEGEND_TICKS - 1) as f32;
+                        painter.hline(strip.right()..=strip.right() + spacing / 2., y, stroke);
+                        painter.text(
+                            egui::pos2(strip.right() + spacing, y),
+                            egui::Align2::LEFT_CENTER,
+                            label,
+                            font.clone(),
+                            stroke.color,
+                        );
+                    });
+                });
+            });
+    }
+
+    /// Value of the field quantity at position `t` of the color map, the inverse of the mapping
+    /// in the slice shader.
+    fn legend_label(slice: &SliceState, t: f32) -> String {
+        let u = t.powf(1. / slice.color_gamma);
+        let value = |min: f32, max: f32| {
+            if slice.log_scale {
+                (1. + min) * ((1. + max) / (1. + min)).powf(u) - 1.
+            } else {
+                min + u * (max - min)
+            }
+        };
+        let min = slice.clamped_pressure_min();
+        match slice.quantity {
+            FieldQuantity::Pressure => format!("{:.0} Pa", value(min, slice.pressure_max)),
+            FieldQuantity::Phase | FieldQuantity::PhasePressure => {
+                format!("{:.0}\u{b0}", t * 360.)
+            }
+            FieldQuantity::Intensity => {
+                let max = slice.pressure_max * slice.pressure_max;
+                format!(
+                    "{:.2}",
+                    value(min * min, max) / max.max(MIN_COLOR_MAP_RANGE)
+                )
+            }
+            FieldQuantity::Spl => {
+                let spl_max = 20. * (slice.pressure_max.max(SPL_REFERENCE) / SPL_REFERENCE).log10();
+                format!("{:.0} dB", spl_max - (1. - u) * SPL_RANGE)
+            }
+        }
+    }
+
+    /// Point of the slice under the mouse cursor, unless the pointer is over the UI.
+    fn cursor_on_slice(ctx: &egui::Context, state: &crate::State) -> Option<Vector3> {
+        if !state.show_cursor_pos || ctx.egui_wants_pointer_input() {
+            return None;
+        }
+        Self::pointer_on_slice(ctx, state)
+    }
+
+    fn pointer_on_slice(ctx: &egui::Context, state: &crate::State) -> Option<Vector3> {
+        let pos = ctx.input(|input| input.pointer.hover_pos())?;
+        let rect = ctx.viewport_rect();
+        if rect.width() <= 0. || rect.height() <= 0. {
+            return None;
+        }
+        let ndc = Vector2::new(
+            2. * (pos.x - rect.min.x) / rect.width() - 1.,
+            1. - 2. * (pos.y - rect.min.y) / rect.height(),
+        );
+        let (origin, dir) = view_ray(&state.camera, rect.width() / rect.height(), ndc);
+        state.slice.intersect(origin, dir)
+    }
+
+    fn phase_wheel(ui: &mut egui::Ui, size: f32) {
+        const SEGMENTS: u32 = 64;
+
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(size, size), egui::Sense::hover());
+        let center = rect.center();
+        let radius = size / 2.;
+        let color = |t: f32| {
+            let [r, g, b, _] = Hsv {
+                h: t,
+                s: 1.,
+                v: 1.,
+                a: 1.,
+            }
+            .rgba();
+            egui::Color32::from_rgb((r * 255.) as u8, (g * 255.) as u8, (b * 255.) as u8)
+        };
+        let point = |t: f32| {
+            let theta = 2. * std::f32::consts::PI * t;
+            center + radius * egui::vec2(theta.cos(), -theta.sin())
+        };
+
+        let mut mesh = egui::Mesh::default();
+        (0..SEGMENTS).for_each(|i| {
+            let t0 = i as f32 / SEGMENTS as f32;
+            let t1 = (i + 1) as f32 / SEGMENTS as f32;
+            let idx = mesh.vertices.len() as u32;
+            mesh.colored_vertex(center, color(t0));
+            mesh.colored_vertex(point(t0), color(t0));
+            mesh.colored_vertex(point(t1), color(t1));
+            mesh.add_triangle(idx, idx + 1, idx + 2);
+        });
+        ui.painter().add(mesh);
+    }
+
+    /// Returns whether the selection changed.
+    fn color_map_selector(ui: &mut egui::Ui, id: &str, color_map: &mut ColorMap) -> bool {
+        let before = *color_map;
+        egui::ComboBox::from_id_salt(id)
+            .selected_text(color_map.name())
+            .show_ui(ui, |ui| {
+                ColorMap::ALL.into_iter().for_each(|c| {
+                    ui.selectable_value(color_map, c, c.name());
+                });
+            });
+        *color_map != before
+    }
+
+    /// Origin the positions in the UI are shown relative to.
+    fn display_origin(state: &crate::State, emulator: &EmulatorWrapper) -> Vector3 {
+        state
+            .origin_device
+            .and_then(|dev| emulator.transducers().device_origin(dev))
+            .unwrap_or(Vector3::ZERO)
+    }
+
+    fn origin_selector(ui: &mut egui::Ui, state: &mut crate::State, emulator: &EmulatorWrapper) {
+        let label = |dev: Option<usize>| match dev {
+            Some(dev) => format!("Device {dev}"),
+            None => "World".to_string(),
+        };
+        egui::ComboBox::from_id_salt("origin_device")
+            .selected_text(label(state.origin_device))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut state.origin_device, None, label(None));
+                (0..emulator.num_devices()).for_each(|dev| {
+                    ui.selectable_value(&mut state.origin_device, Some(dev), label(Some(dev)));
+                });
+            })
+            .response
+            .on_hover_text("Origin of the positions shown in the UI");
+    }
+
+    /// Peak of the displayed quantity over a coarse grid on the slice.
+    fn slice_peak_pressure(state: &crate::State, emulator: &EmulatorWrapper) -> f32 {
+        let rotation = state.slice.rotation();
+        let transducers = emulator.transducers();
+        let peak = (0..AUTO_PRESSURE_SAMPLES)
+            .flat_map(|i| (0..AUTO_PRESSURE_SAMPLES).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                let x = (i as f32 + 0.5) / AUTO_PRESSURE_SAMPLES as f32 - 0.5;
+                let y = (j as f32 + 0.5) / AUTO_PRESSURE_SAMPLES as f32 - 0.5;
+                let point = state.slice.pos
+                    + rotation * Vector3::new(x * state.slice.size.x, y * state.slice.size.y, 0.);
+                transducers.pressure_at(point, state.sound_speed)
+            })
+            .fold(0., f32::max);
+        if state.slice.rms {
+            peak / std::f32::consts::SQRT_2
+        } else {
+            peak
+        }
+    }
+
+    fn clamp_camera_pitch(state: &mut crate::State) {
+        if state.camera_pitch_clamp {
+            let (min, max) = CAMERA_PITCH_RANGE.into_inner();
+            state.camera.rot.x = state.camera.rot.x.clamp(min, max);
         }
     }
 
-    fn slice_tab(ui: &mut egui::Ui, state: &mut crate::State, update_flag: &mut UpdateFlag) {
+    fn locked_drag_value(ui: &mut egui::Ui, value: &mut f32, lock: &mut bool) -> egui::Response {
+        ui.horizontal(|ui| {
+            let response = ui.add_enabled(!*lock, DragValue::new(value).speed(1. * mm));
+            ui.checkbox(lock, "Lock");
+            response
+        })
+        .inner
+    }
+
+    fn slice_tab(
+        ui: &mut egui::Ui,
+        state: &mut crate::State,
+        emulator: &EmulatorWrapper,
+        initial_state: &str,
+        update_flag: &mut UpdateFlag,
+    ) {
+        let origin = Self::display_origin(state, emulator);
+        let mut pos = state.slice.pos - origin;
         ui.label("Position");
         if egui::Grid::new("slice_pos_grid")
             .num_columns(2)
@@ -466,18 +990,27 @@ impl EguiRenderer {
             .spacing(SPACING)
             .striped(true)
             .show(ui, |ui| {
+                let [lock_x, lock_y, lock_z] = &mut state.slice_pos_lock;
+
                 ui.label("X:");
-                let response = ui.add(DragValue::new(&mut state.slice.pos.x).speed(1. * mm));
+                let response = Self::locked_drag_value(ui, &mut pos.x, lock_x);
                 ui.end_row();
 
                 ui.label("Y:");
-                let response =
-                    response.union(ui.add(DragValue::new(&mut state.slice.pos.y).speed(1. * mm)));
+                let response = response.union(Self::locked_drag_value(ui, &mut pos.y, lock_y));
                 ui.end_row();
 
                 ui.label("Z:");
-                let response =
-                    response.union(ui.add(DragValue::new(&mut state.slice.pos.z).speed(1. * mm)));
+                let response = response.union(Self::locked_drag_value(ui, &mut pos.z, lock_z));
+                ui.end_row();
+
+                ui.label("Nudge step:");
+                ui.add(
+                    DragValue::new(&mut state.slice_nudge_step)
+                        .speed(0.01 * mm)
+                        .range(0.01 * mm..=100. * mm),
+                )
+                .on_hover_text("Distance moved per arrow key (in plane) or Page Up/Down (normal)");
                 ui.end_row();
 
                 response
@@ -485,6 +1018,7 @@ impl EguiRenderer {
             .inner
             .changed()
         {
+            state.slice.pos = pos + origin;
             update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
         }
 
@@ -544,7 +1078,7 @@ impl EguiRenderer {
             .striped(true)
             .show(ui, |ui| {
                 ui.label("Width:");
-                let response = ui.add(
+                let width = ui.add(
                     DragValue::new(&mut state.slice.size.x)
                         .speed(1. * mm)
                         .range(1.0 * mm..=1024. * mm),
@@ -552,16 +1086,30 @@ impl EguiRenderer {
                 ui.end_row();
 
                 ui.label("Height:");
-                let response = response.union(
-                    ui.add(
-                        DragValue::new(&mut state.slice.size.y)
-                            .speed(1. * mm)
-                            .range(1.0 * mm..=1024. * mm),
-                    ),
+                let height = ui.add(
+                    DragValue::new(&mut state.slice.size.y)
+                        .speed(1. * mm)
+                        .range(1.0 * mm..=1024. * mm),
                 );
                 ui.end_row();
 
-                response
+                ui.label("Lock aspect:");
+                if ui.checkbox(&mut state.slice_aspect_lock, "").changed() {
+                    state.slice_aspect = state.slice.size.x / state.slice.size.y;
+                }
+                ui.end_row();
+
+                if state.slice_aspect_lock {
+                    if width.changed() {
+                        state.slice.size.y =
+                            (state.slice.size.x / state.slice_aspect).clamp(1.0 * mm, 1024. * mm);
+                    } else if height.changed() {
+                        state.slice.size.x =
+                            (state.slice.size.y * state.slice_aspect).clamp(1.0 * mm, 1024. * mm);
+                    }
+                }
+
+                width.union(height)
             })
             .inner
             .changed()
@@ -569,6 +1117,53 @@ impl EguiRenderer {
             update_flag.set(UpdateFlag::UPDATE_SLICE_SIZE, true);
         }
 
+        ui.horizontal(|ui| {
+            ui.label("Resolution:");
+            let name = |resolution: Option<u32>| {
+                resolution.map_or("Auto (1 mm)".to_string(), |r| format!("{r} texels"))
+            };
+            let before = state.slice.resolution;
+            egui::ComboBox::from_id_salt("slice_resolution")
+                .selected_text(name(state.slice.resolution))
+                .show_ui(ui, |ui| {
+                    std::iter::once(None)
+                        .chain(SLICE_RESOLUTIONS.map(Some))
+                        .for_each(|resolution| {
+                            ui.selectable_value(
+                                &mut state.slice.resolution,
+                                resolution,
+                                name(resolution),
+                            );
+                        });
+                })
+                .response
+                .on_hover_text(
+                    "Texels along the longer side of the slice, \
+                     limited by the maximum texture size of the GPU",
+                );
+            if state.slice.resolution != before {
+                update_flag.set(UpdateFlag::UPDATE_SLICE_RESOLUTION, true);
+            }
+        });
+
+        ui.collapsing("Corners", |ui| {
+            egui::Grid::new("slice_corners_grid")
+                .num_columns(2)
+                .min_col_width(MIN_COL_WIDTH)
+                .spacing(SPACING)
+                .striped(true)
+                .show(ui, |ui| {
+                    ["Bottom left", "Bottom right", "Top right", "Top left"]
+                        .into_iter()
+                        .zip(state.slice.corners())
+                        .for_each(|(label, p)| {
+                            ui.label(format!("{label}:"));
+                            ui.label(format!("({:.3}, {:.3}, {:.3})", p.x, p.y, p.z));
+                            ui.end_row();
+                        });
+                });
+        });
+
         ui.separator();
         ui.label("Color state");
 
@@ -579,71 +1174,544 @@ impl EguiRenderer {
             .striped(true)
             .show(ui, |ui| {
                 ui.label("Max pressure [Pa]:");
+                ui.horizontal(|ui| {
+                    if ui
+                        .add(
+                            DragValue::new(&mut state.slice.pressure_max)
+                                .speed(100.)
+                                .range(0.0..=f32::MAX),
+                        )
+                        .changed()
+                    {
+                        update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                    }
+                    if ui
+                        .add_enabled(emulator.initialized(), egui::Button::new("Auto"))
+                        .on_hover_text("Set to the peak pressure on the slice plus a margin")
+                        .clicked()
+                    {
+                        let peak = Self::slice_peak_pressure(state, emulator);
+                        if peak > 0. {
+                            state.slice.pressure_max = peak * AUTO_PRESSURE_MARGIN;
+                            update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                        }
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Min pressure [Pa]:");
                 if ui
                     .add(
-                        DragValue::new(&mut state.slice.pressure_max)
+                        DragValue::new(&mut state.slice.pressure_min)
                             .speed(100.)
                             .range(0.0..=f32::MAX),
                     )
+                    .on_hover_text(
+                        "Pressures up to this are at the low end of the color map. \
+                         Kept just below the max pressure. Not applied to SPL.",
+                    )
                     .changed()
                 {
                     update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
                 }
                 ui.end_row();
-            });
 
-        ui.separator();
-        ui.horizontal(|ui| {
-            if ui.button("xy").clicked() {
-                state.slice.rot.x = 0.;
-                state.slice.rot.y = 0.;
-                state.slice.rot.z = 0.;
-                update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
-            }
+                ui.label("Color map:");
+                if Self::color_map_selector(ui, "slice_color_map", &mut state.slice.color_map) {
+                    update_flag.set(UpdateFlag::UPDATE_SLICE_COLOR_MAP, true);
+                }
+                ui.end_row();
 
-            if ui.button("yz").clicked() {
-                state.slice.rot.x = 0.;
-                state.slice.rot.y = 90.;
-                state.slice.rot.z = 0.;
-                update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
-            }
+                ui.label("Reversed:");
+                if ui.checkbox(&mut state.slice.reversed, "").changed() {
+                    update_flag.set(UpdateFlag::UPDATE_SLICE_COLOR_MAP, true);
+                }
+                ui.end_row();
 
-            if ui.button("zx").clicked() {
-                state.slice.rot.x = 90.;
-                state.slice.rot.y = 0.;
-                state.slice.rot.z = 0.;
-                update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
-            }
-        });
-    }
+                ui.label("Quantity:");
+                let before = state.slice.quantity;
+                egui::ComboBox::from_id_salt("slice_quantity")
+                    .selected_text(state.slice.quantity.name())
+                    .show_ui(ui, |ui| {
+                        FieldQuantity::ALL.into_iter().for_each(|q| {
+                            ui.selectable_value(&mut state.slice.quantity, q, q.name());
+                        });
+                    })
+                    .response
+                    .on_hover_text(
+                        "Intensity is relative to that at the max pressure. \
+                         SPL spans 60 dB below that of the max pressure. \
+                         Phase and pressure shows the phase as the hue and the pressure as the brightness.",
+                    );
+                if state.slice.quantity != before {
+                    update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                }
+                ui.end_row();
 
-    fn camera_tab(ui: &mut egui::Ui, state: &mut crate::State, update_flag: &mut UpdateFlag) {
-        ui.label("Position");
-        if egui::Grid::new("camera_pos_grid")
-            .num_columns(2)
-            .min_col_width(MIN_COL_WIDTH)
-            .spacing(SPACING)
-            .striped(true)
-            .show(ui, |ui| {
-                ui.label("X:");
-                let response = ui.add(DragValue::new(&mut state.camera.pos.x).speed(1. * mm));
+                ui.label("Split view:");
+                if ui
+                    .checkbox(&mut state.slice.split, "")
+                    .on_hover_text("Show the right half with another color map and max pressure")
+                    .changed()
+                {
+                    update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                }
                 ui.end_row();
 
-                ui.label("Y:");
-                let response =
-                    response.union(ui.add(DragValue::new(&mut state.camera.pos.y).speed(1. * mm)));
+                if state.slice.split {
+                    ui.label("Right color map:");
+                    if Self::color_map_selector(
+                        ui,
+                        "slice_split_color_map",
+                        &mut state.slice.split_color_map,
+                    ) {
+                        update_flag.set(UpdateFlag::UPDATE_SLICE_COLOR_MAP, true);
+                    }
+                    ui.end_row();
+
+                    ui.label("Right max pressure [Pa]:");
+                    if ui
+                        .add(
+                            DragValue::new(&mut state.slice.split_pressure_max)
+                                .speed(100.)
+                                .range(0.0..=f32::MAX),
+                        )
+                        .changed()
+                    {
+                        update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                    }
+                    ui.end_row();
+                }
+
+                ui.label("RMS:");
+                if ui.checkbox(&mut state.slice.rms, "").changed() {
+                    update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                }
                 ui.end_row();
 
-                ui.label("Z:");
-                let response =
-                    response.union(ui.add(DragValue::new(&mut state.camera.pos.z).speed(1. * mm)));
+                ui.label("Gamma:");
+                if ui
+                    .add(
+                        egui::Slider::new(&mut state.slice.color_gamma, 0.1..=5.0)
+                            .logarithmic(true),
+                    )
+                    .changed()
+                {
+                    update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                }
                 ui.end_row();
 
-                response
-            })
-            .inner
-            .changed()
-        {
+                ui.label("Log scale:");
+                if ui
+                    .checkbox(&mut state.slice.log_scale, "")
+                    .on_hover_text("Map log10(1 + p) up to log10(1 + max pressure)")
+                    .changed()
+                {
+                    update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                }
+                ui.end_row();
+
+                ui.label("Persistence:");
+                if ui
+                    .add(egui::Slider::new(&mut state.slice.persistence, 0.0..=0.99))
+                    .on_hover_text("Blend each frame with the previous ones to show trails")
+                    .changed()
+                {
+                    update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                }
+                ui.end_row();
+            });
+
+        if state.slice.quantity == FieldQuantity::PhasePressure {
+            ui.horizontal(|ui| {
+                Self::phase_wheel(ui, 64.);
+                ui.label("Phase, counter-clockwise from 0 on the right");
+            });
+        }
+
+        ui.collapsing("Contours", |ui| {
+            Self::contours(ui, state, update_flag);
+        });
+
+        ui.separator();
+        if ui
+            .checkbox(&mut state.slice.mirror, "Mirror difference")
+            .changed()
+        {
+            update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+        }
+        if state.slice.mirror
+            && egui::Grid::new("slice_mirror_grid")
+                .num_columns(2)
+                .min_col_width(MIN_COL_WIDTH)
+                .spacing(SPACING)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Plane X:");
+                    let response =
+                        ui.add(DragValue::new(&mut state.slice.mirror_pos.x).speed(1. * mm));
+                    ui.end_row();
+
+                    ui.label("Plane Y:");
+                    let response = response.union(
+                        ui.add(DragValue::new(&mut state.slice.mirror_pos.y).speed(1. * mm)),
+                    );
+                    ui.end_row();
+
+                    ui.label("Plane Z:");
+                    let response = response.union(
+                        ui.add(DragValue::new(&mut state.slice.mirror_pos.z).speed(1. * mm)),
+                    );
+                    ui.end_row();
+
+                    ui.label("Normal X:");
+                    let response = response.union(
+                        ui.add(DragValue::new(&mut state.slice.mirror_normal.x).speed(0.01)),
+                    );
+                    ui.end_row();
+
+                    ui.label("Normal Y:");
+                    let response = response.union(
+                        ui.add(DragValue::new(&mut state.slice.mirror_normal.y).speed(0.01)),
+                    );
+                    ui.end_row();
+
+                    ui.label("Normal Z:");
+                    let response = response.union(
+                        ui.add(DragValue::new(&mut state.slice.mirror_normal.z).speed(0.01)),
+                    );
+                    ui.end_row();
+
+                    response
+                })
+                .inner
+                .changed()
+        {
+            update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+        }
+
+        ui.separator();
+        Self::probe_grid(ui, state, emulator);
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("xy").clicked() {
+                state.slice.rot.x = 0.;
+                state.slice.rot.y = 0.;
+                state.slice.rot.z = 0.;
+                update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
+            }
+
+            if ui.button("yz").clicked() {
+                state.slice.rot.x = 0.;
+                state.slice.rot.y = 90.;
+                state.slice.rot.z = 0.;
+                update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
+            }
+
+            if ui.button("zx").clicked() {
+                state.slice.rot.x = 90.;
+                state.slice.rot.y = 0.;
+                state.slice.rot.z = 0.;
+                update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
+            }
+
+            if ui.button("flip").clicked() {
+                let rot = state.slice.rotation() * Quat::from_rotation_y(std::f32::consts::PI);
+                let (rx, ry, rz) = rot.to_euler(EulerRot::XYZ);
+                state.slice.rot.x = rx.to_degrees();
+                state.slice.rot.y = ry.to_degrees();
+                state.slice.rot.z = rz.to_degrees();
+                update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
+            }
+
+            if ui
+                .button("center")
+                .on_hover_text("Move the slice to the center of the array")
+                .clicked()
+                && let Some(centroid) = emulator.transducers().centroid()
+            {
+                state.slice.pos = centroid;
+                update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
+            }
+        });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("Snapshot reference").clicked() {
+                state.slice_reference = true;
+                update_flag.set(UpdateFlag::UPDATE_REFERENCE, true);
+            }
+            if ui
+                .add_enabled(state.slice_reference, egui::Button::new("Clear reference"))
+                .clicked()
+            {
+                state.slice_reference = false;
+                state.slice_show_reference = false;
+                update_flag.set(UpdateFlag::UPDATE_REFERENCE, true);
+            }
+        });
+        ui.add_enabled(
+            state.slice_reference,
+            egui::Checkbox::new(&mut state.slice_show_reference, "Show reference"),
+        );
+
+        ui.separator();
+        Self::slice_presets(ui, state, update_flag);
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("Copy as JSON").clicked() {
+                ui.ctx()
+                    .copy_text(serde_json::to_string_pretty(&state.slice).unwrap());
+            }
+            if ui
+                .button("Export .npy")
+                .on_hover_text("Save the pressure on the slice as a NumPy array")
+                .clicked()
+                && let Some(path) = rfd::FileDialog::new()
+                    .add_filter("NumPy", &["npy"])
+                    .set_file_name("slice.npy")
+                    .save_file()
+            {
+                state.export_npy = Some(path);
+            }
+            if ui
+                .button("Export .csv")
+                .on_hover_text(
+                    "Save the displayed pressure of each texel with its position in mm as CSV",
+                )
+                .clicked()
+                && let Some(path) = rfd::FileDialog::new()
+                    .add_filter("CSV", &["csv"])
+                    .set_file_name("slice.csv")
+                    .save_file()
+            {
+                state.export_csv = Some(path);
+            }
+            if ui
+                .button("Export .png")
+                .on_hover_text("Save the colored slice without the UI as an image")
+                .clicked()
+                && let Some(path) = rfd::FileDialog::new()
+                    .add_filter("PNG", &["png"])
+                    .set_file_name("slice.png")
+                    .save_file()
+            {
+                state.export_png = Some(path);
+            }
+
+            let slice = if ui
+                .small_button("Default")
+                .on_hover_text("Restore the default slice only")
+                .clicked()
+            {
+                Some(crate::State::default().slice)
+            } else if ui
+                .small_button("Reset")
+                .on_hover_text("Restore the slice from startup only")
+                .clicked()
+            {
+                Some(
+                    serde_json::from_str::<crate::State>(initial_state)
+                        .unwrap()
+                        .slice,
+                )
+            } else {
+                None
+            };
+            if let Some(slice) = slice {
+                state.slice = slice;
+                update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
+                update_flag.set(UpdateFlag::UPDATE_SLICE_SIZE, true);
+                update_flag.set(UpdateFlag::UPDATE_SLICE_COLOR_MAP, true);
+                update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+            }
+        });
+    }
+
+    fn slice_presets(ui: &mut egui::Ui, state: &mut crate::State, update_flag: &mut UpdateFlag) {
+        ui.collapsing(format!("Presets ({})", state.slice_presets.len()), |ui| {
+            let id = ui.id().with("slice_preset_name");
+            let mut name = ui.data_mut(|data| data.get_temp::<String>(id).unwrap_or_default());
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut name).desired_width(MIN_COL_WIDTH));
+                if ui
+                    .add_enabled(!name.trim().is_empty(), egui::Button::new("Save"))
+                    .on_hover_text("Save the current slice, replacing a preset of the same name")
+                    .clicked()
+                {
+                    let name = name.trim().to_string();
+                    match state.slice_presets.iter_mut().find(|(n, _)| *n == name) {
+                        Some((_, slice)) => *slice = state.slice.clone(),
+                        None => state.slice_presets.push((name, state.slice.clone())),
+                    }
+                }
+            });
+
+            let mut recall = None;
+            let mut remove = None;
+            egui::Grid::new("slice_presets_grid")
+                .num_columns(2)
+                .min_col_width(MIN_COL_WIDTH)
+                .spacing(SPACING)
+                .striped(true)
+                .show(ui, |ui| {
+                    state
+                        .slice_presets
+                        .iter()
+                        .enumerate()
+                        .for_each(|(i, (n, _))| {
+                            let response = ui.button(n);
+                            let response = if i < SLICE_PRESET_KEYS.len() {
+                                response.on_hover_text(format!("Key {}", i + 1))
+                            } else {
+                                response
+                            };
+                            if response.clicked() {
+                                recall = Some(i);
+                            }
+                            if ui.small_button("Remove").clicked() {
+                                remove = Some(i);
+                            }
+                            ui.end_row();
+                        });
+                });
+            if let Some(i) = recall {
+                Self::recall_slice_preset(state, i, update_flag);
+            }
+            if let Some(i) = remove {
+                state.slice_presets.remove(i);
+            }
+
+            ui.data_mut(|data| data.insert_temp(id, name));
+        });
+    }
+
+    fn recall_slice_preset(state: &mut crate::State, i: usize, update_flag: &mut UpdateFlag) {
+        let Some((_, slice)) = state.slice_presets.get(i) else {
+            return;
+        };
+        state.slice = slice.clone();
+        update_flag.set(UpdateFlag::UPDATE_SLICE_POS, true);
+        update_flag.set(UpdateFlag::UPDATE_SLICE_SIZE, true);
+        update_flag.set(UpdateFlag::UPDATE_SLICE_COLOR_MAP, true);
+        update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+    }
+
+    fn probe_grid(ui: &mut egui::Ui, state: &mut crate::State, emulator: &EmulatorWrapper) {
+        ui.checkbox(&mut state.probe.show_grid, "Field inspector");
+        if !state.probe.show_grid {
+            return;
+        }
+
+        egui::Grid::new("slice_probe_grid")
+            .num_columns(2)
+            .min_col_width(MIN_COL_WIDTH)
+            .spacing(SPACING)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Probe X:");
+                ui.add(DragValue::new(&mut state.probe.pos.x).speed(1. * mm));
+                ui.end_row();
+
+                ui.label("Probe Y:");
+                ui.add(DragValue::new(&mut state.probe.pos.y).speed(1. * mm));
+                ui.end_row();
+
+                ui.label("Probe Z:");
+                ui.add(DragValue::new(&mut state.probe.pos.z).speed(1. * mm));
+                ui.end_row();
+
+                ui.label("Samples:");
+                ui.add(DragValue::new(&mut state.probe.grid_size).range(1..=15));
+                ui.end_row();
+
+                ui.label("Spacing:");
+                ui.add(
+                    DragValue::new(&mut state.probe.grid_spacing)
+                        .speed(0.1 * mm)
+                        .range(0.0..=f32::MAX),
+                );
+                ui.end_row();
+            });
+
+        // Samples are taken on the slice plane, centered on the probe point
+        let rotation = state.slice.rotation();
+        let right = rotation * Vector3::X;
+        let up = rotation * Vector3::Y;
+        let n = state.probe.grid_size as i32;
+        let half = (n - 1) as f32 / 2.;
+        egui::Grid::new("slice_probe_values_grid")
+            .num_columns(n as _)
+            .spacing(SPACING)
+            .show(ui, |ui| {
+                (0..n).rev().for_each(|j| {
+                    (0..n).for_each(|i| {
+                        let point = state.probe.pos
+                            + right * (i as f32 - half) * state.probe.grid_spacing
+                            + up * (j as f32 - half) * state.probe.grid_spacing;
+                        let p = emulator.transducers().pressure_at(point, state.sound_speed);
+                        let v = state.slice.color_map_pos(p) as f64;
+                        let [r, g, b] = state.slice.colors(state.slice.color_map, [v])[0];
+                        let background = egui::Color32::from_rgb(
+                            (r * 255.) as u8,
+                            (g * 255.) as u8,
+                            (b * 255.) as u8,
+                        );
+                        let text = if 0.299 * r + 0.587 * g + 0.114 * b > 0.5 {
+                            egui::Color32::BLACK
+                        } else {
+                            egui::Color32::WHITE
+                        };
+                        ui.label(
+                            egui::RichText::new(format!("{p:.0}"))
+                                .monospace()
+                                .color(text)
+                                .background_color(background),
+                        );
+                    });
+                    ui.end_row();
+                });
+            });
+    }
+
+    fn camera_tab(
+        ui: &mut egui::Ui,
+        state: &mut crate::State,
+        emulator: &EmulatorWrapper,
+        initial_state: &str,
+        update_flag: &mut UpdateFlag,
+    ) {
+        let origin = Self::display_origin(state, emulator);
+        let mut pos = state.camera.pos - origin;
+        ui.label("Position");
+        if egui::Grid::new("camera_pos_grid")
+            .num_columns(2)
+            .min_col_width(MIN_COL_WIDTH)
+            .spacing(SPACING)
+            .striped(true)
+            .show(ui, |ui| {
+                let [lock_x, lock_y, lock_z] = &mut state.camera_pos_lock;
+
+                ui.label("X:");
+                let response = Self::locked_drag_value(ui, &mut pos.x, lock_x);
+                ui.end_row();
+
+                ui.label("Y:");
+                let response = response.union(Self::locked_drag_value(ui, &mut pos.y, lock_y));
+                ui.end_row();
+
+                ui.label("Z:");
+                let response = response.union(Self::locked_drag_value(ui, &mut pos.z, lock_z));
+                ui.end_row();
+
+                response
+            })
+            .inner
+            .changed()
+        {
+            state.camera.pos = pos + origin;
             update_flag.set(UpdateFlag::UPDATE_CAMERA, true);
         }
 
@@ -659,7 +1727,11 @@ impl EguiRenderer {
                 let response = ui.add(
                     DragValue::new(&mut state.camera.rot.x)
                         .speed(1.)
-                        .range(-180.0..=180.0)
+                        .range(if state.camera_pitch_clamp {
+                            CAMERA_PITCH_RANGE
+                        } else {
+                            -180.0..=180.0
+                        })
                         .suffix("°"),
                 );
                 ui.end_row();
@@ -686,11 +1758,16 @@ impl EguiRenderer {
                 );
                 ui.end_row();
 
+                ui.label("Clamp pitch:");
+                let response = response.union(ui.checkbox(&mut state.camera_pitch_clamp, ""));
+                ui.end_row();
+
                 response
             })
             .inner
             .changed()
         {
+            Self::clamp_camera_pitch(state);
             update_flag.set(UpdateFlag::UPDATE_CAMERA, true);
         }
 
@@ -708,139 +1785,811 @@ impl EguiRenderer {
                         .range(1. * mm..=10.0 * mm),
                 );
                 ui.end_row();
+
+                ui.label("Zoom speed:");
+                ui.add(
+                    DragValue::new(&mut state.camera.zoom_speed)
+                        .speed(0.1)
+                        .range(0.1..=100.0),
+                );
+                ui.end_row();
+
+                ui.label("Invert zoom:");
+                ui.checkbox(&mut state.camera.invert_zoom, "");
+                ui.end_row();
             });
 
-        ui.separator();
-        ui.label("Perspective");
-        if egui::Grid::new("camera_pers_grid")
+        ui.separator();
+        ui.label("Perspective");
+        if egui::Grid::new("camera_pers_grid")
+            .num_columns(2)
+            .min_col_width(MIN_COL_WIDTH)
+            .spacing(SPACING)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("FOV:");
+                let response = ui.add(
+                    DragValue::new(&mut state.camera.fov)
+                        .speed(1.)
+                        .range(0.0..=180.0)
+                        .suffix("°"),
+                );
+                ui.end_row();
+
+                ui.label("Near clip:");
+                let response = response.union(
+                    ui.add(
+                        DragValue::new(&mut state.camera.near_clip)
+                            .speed(1. * mm)
+                            .range(0.0..=f32::MAX),
+                    ),
+                );
+                ui.end_row();
+
+                ui.label("Far clip:");
+                let response = response.union(
+                    ui.add(
+                        DragValue::new(&mut state.camera.far_clip)
+                            .speed(1. * mm)
+                            .range(0.0..=f32::MAX),
+                    ),
+                );
+                ui.end_row();
+
+                response
+            })
+            .inner
+            .changed()
+        {
+            update_flag.set(UpdateFlag::UPDATE_CAMERA, true);
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("Copy as JSON").clicked() {
+                ui.ctx()
+                    .copy_text(serde_json::to_string_pretty(&state.camera).unwrap());
+            }
+
+            let camera = if ui
+                .small_button("Default")
+                .on_hover_text("Restore the default camera only")
+                .clicked()
+            {
+                Some(crate::State::default().camera)
+            } else if ui
+                .small_button("Reset")
+                .on_hover_text("Restore the camera from startup only")
+                .clicked()
+            {
+                Some(
+                    serde_json::from_str::<crate::State>(initial_state)
+                        .unwrap()
+                        .camera,
+                )
+            } else {
+                None
+            };
+            if let Some(camera) = camera {
+                state.camera = camera;
+                update_flag.set(UpdateFlag::UPDATE_CAMERA, true);
+            }
+        });
+    }
+
+    fn config_tab(
+        ui: &mut egui::Ui,
+        state: &mut crate::State,
+        emulator: &mut EmulatorWrapper,
+        gpu_info: &GpuInfo,
+        update_flag: &mut UpdateFlag,
+    ) {
+        egui::Grid::new("config_env_grid")
+            .num_columns(2)
+            .min_col_width(MIN_COL_WIDTH)
+            .spacing(SPACING)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Sound speed:");
+                if ui
+                    .add(DragValue::new(&mut state.sound_speed).speed(100. * mm))
+                    .changed()
+                {
+                    update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                }
+                ui.end_row();
+            });
+
+        ui.checkbox(&mut state.show_cursor_pos, "Show cursor position");
+        ui.checkbox(&mut state.show_legend, "Show color map legend");
+        ui.checkbox(&mut state.show_transducers, "Show transducers");
+        ui.checkbox(&mut state.phase_arrows, "Show phase as arrows");
+        ui.checkbox(&mut state.slice_first, "Draw slice before transducers")
+            .on_hover_text(
+                "Keeps the slice visible behind the transparent corners of nearer transducers",
+            );
+        if ui
+            .checkbox(&mut state.freeze_trans_color, "Freeze transducer colors")
+            .changed()
+        {
+            update_flag.set(UpdateFlag::UPDATE_TRANS_ALPHA, true);
+        }
+        if ui
+            .checkbox(&mut state.highlight_changes, "Highlight changed transducers")
+            .on_hover_text(
+                "Grays out the transducers whose phase and amplitude did not change with the last frame sent by the client",
+            )
+            .changed()
+        {
+            update_flag.set(UpdateFlag::UPDATE_TRANS_ALPHA, true);
+        }
+        if egui::Grid::new("config_trans_color_grid")
+            .num_columns(2)
+            .min_col_width(MIN_COL_WIDTH)
+            .spacing(SPACING)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Saturation:");
+                let saturation = ui.add(
+                    DragValue::new(&mut state.trans_saturation)
+                        .speed(0.01)
+                        .range(0.0..=1.0),
+                );
+                ui.end_row();
+
+                ui.label("Hue offset [deg]:");
+                let hue_offset = ui
+                    .add(
+                        DragValue::new(&mut state.trans_hue_offset)
+                            .speed(1.)
+                            .range(0.0..=360.0),
+                    )
+                    .on_hover_text("Hue of the transducers with zero phase");
+                ui.end_row();
+
+                ui.label("Phase offset [deg]:");
+                if ui
+                    .add(
+                        DragValue::new(&mut state.phase_offset)
+                            .speed(1.)
+                            .range(-180.0..=180.0),
+                    )
+                    .on_hover_text(
+                        "Phase subtracted from the displayed phase of the transducers and the slice",
+                    )
+                    .changed()
+                {
+                    update_flag.set(UpdateFlag::UPDATE_TRANS_ALPHA, true);
+                    update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                }
+                ui.end_row();
+
+                ui.label("Fade [s]:");
+                ui.add(
+                    DragValue::new(&mut state.trans_fade_duration)
+                        .speed(0.01)
+                        .range(0.0..=MAX_FADE_DURATION),
+                )
+                .on_hover_text(
+                    "Duration of the fade when devices are shown or hidden. 0 disables it",
+                );
+                ui.end_row();
+
+                saturation.union(hue_offset)
+            })
+            .inner
+            .changed()
+        {
+            update_flag.set(UpdateFlag::UPDATE_TRANS_ALPHA, true);
+        }
+        if ui
+            .checkbox(&mut state.frustum_culling, "Cull off-screen transducers")
+            .on_hover_text(
+                "Test every transducer against the view on each camera change. \
+                 Only worth it for huge arrays that are mostly off screen.",
+            )
+            .changed()
+        {
+            update_flag.set(UpdateFlag::UPDATE_CAMERA, true);
+        }
+        if ui
+            .add_enabled(
+                gpu_info.format.is_srgb(),
+                egui::Checkbox::new(&mut state.srgb_correct, "sRGB-correct colors"),
+            )
+            .on_hover_text(
+                "Decode the colors of the slice, transducers and background before the sRGB surface \
+                 encodes them, so they appear as in the color map and exported images",
+            )
+            .on_disabled_hover_text("The surface does not encode into sRGB")
+            .changed()
+        {
+            update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+            update_flag.set(UpdateFlag::UPDATE_TRANS_ALPHA, true);
+        }
+        ui.add_enabled(
+            gpu_info.shader_f16,
+            egui::Checkbox::new(&mut state.half_precision, "Half precision slice"),
+        )
+        .on_hover_text(
+            "Accumulate the field in f16. The speedup, if any, depends on the GPU, \
+             and the relative error of about 1e-3 grows with the number of transducers.",
+        )
+        .on_disabled_hover_text("The GPU does not support f16 in shaders");
+        ui.checkbox(
+            &mut state.skip_slice_depth,
+            "Skip depth buffer for slice only",
+        )
+        .on_hover_text(
+            "Render without a depth buffer while the transducers and the grid are hidden",
+        );
+
+        ui.checkbox(&mut state.grid.show, "Show grid");
+        if state.grid.show
+            && egui::Grid::new("config_grid_grid")
+                .num_columns(2)
+                .min_col_width(MIN_COL_WIDTH)
+                .spacing(SPACING)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Spacing:");
+                    let response = ui.add(
+                        DragValue::new(&mut state.grid.spacing)
+                            .speed(1. * mm)
+                            .range(1. * mm..=1000. * mm),
+                    );
+                    ui.end_row();
+
+                    ui.label("Extent:");
+                    let response = response.union(
+                        ui.add(
+                            DragValue::new(&mut state.grid.extent)
+                                .speed(10. * mm)
+                                .range(0.0..=f32::MAX),
+                        ),
+                    );
+                    ui.end_row();
+
+                    ui.label("Height:");
+                    let response = response
+                        .union(ui.add(DragValue::new(&mut state.grid.height).speed(1. * mm)));
+                    ui.end_row();
+
+                    response
+                })
+                .inner
+                .changed()
+        {
+            state.grid.spacing = (state.grid.spacing / mm).round() * mm;
+            update_flag.set(UpdateFlag::UPDATE_GRID, true);
+        }
+
+        ui.label("Device index: show/in field/overheat");
+        egui::Grid::new("config_device_grid")
+            .num_columns(2)
+            .min_col_width(MIN_COL_WIDTH)
+            .spacing(SPACING)
+            .striped(true)
+            .show(ui, |ui| {
+                emulator
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(i, mut emulator)| {
+                        ui.label(format!("Device {i}: "));
+                        ui.horizontal(|ui| {
+                            if ui
+                                .checkbox(emulator.visible, "")
+                                .on_hover_text("Show the transducers")
+                                .changed()
+                            {
+                                update_flag.set(UpdateFlag::UPDATE_TRANS_ALPHA, true);
+                                let v = if *emulator.visible { 1. } else { 0. };
+                                emulator.transducers.iter_mut().for_each(|s| s.alpha = v);
+                            }
+
+                            if ui
+                                .checkbox(emulator.enable, "")
+                                .on_hover_text("Include in the slice field")
+                                .changed()
+                            {
+                                update_flag.set(UpdateFlag::UPDATE_TRANS_STATE, true);
+                                emulator.apply_enable();
+                            }
+
+                            if ui
+                                .checkbox(emulator.thermal, "")
+                                .on_hover_text("Assert the thermal sensor")
+                                .changed()
+                            {
+                                if *emulator.thermal {
+                                    emulator.cpu.fpga_mut().assert_thermal_sensor();
+                                } else {
+                                    emulator.cpu.fpga_mut().deassert_thermal_sensor();
+                                }
+                            }
+                        });
+                        ui.end_row();
+                    });
+            });
+
+        if state.debug {
+            Self::transducer_overrides(ui, emulator, update_flag);
+        }
+
+        ui.separator();
+
+        egui::Grid::new("config_ui_grid")
+            .num_columns(2)
+            .min_col_width(MIN_COL_WIDTH)
+            .spacing(SPACING)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("UI scale:");
+                ui.add(
+                    DragValue::new(&mut state.ui_scale)
+                        .speed(0.01)
+                        .range(1.0..=10.0),
+                );
+                ui.end_row();
+
+                ui.label("Line width:");
+                if ui
+                    .add(
+                        DragValue::new(&mut state.overlay_line_width)
+                            .speed(0.05)
+                            .range(0.5..=MAX_LINE_WIDTH),
+                    )
+                    .on_hover_text(
+                        "Width of the grid and plot lines in points, \
+                         and of the contour lines in slice texels",
+                    )
+                    .changed()
+                {
+                    update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+                }
+                ui.end_row();
+
+                ui.label("Layout passes:");
+                if ui
+                    .add(DragValue::new(&mut state.max_passes).range(1..=MAX_PASSES_LIMIT))
+                    .on_hover_text(
+                        "Maximum egui layout passes per frame. \
+                         More passes avoid flicker when the layout changes, fewer save CPU time.",
+                    )
+                    .changed()
+                {
+                    Self::set_max_passes(ui.ctx(), state.max_passes);
+                }
+                ui.end_row();
+
+                ui.label("Lock panel:");
+                ui.checkbox(&mut state.panel_locked, "")
+                    .on_hover_text("Keep the control panel from being moved or resized");
+                ui.end_row();
+
+                ui.label("Background:");
+                color_picker_color32(ui, &mut state.background, egui::color_picker::Alpha::Opaque);
+                ui.end_row();
+
+                ui.label("VSync:");
+                if ui
+                    .add_enabled(
+                        state.present_mode == PresentMode::Auto,
+                        egui::Checkbox::without_text(&mut state.vsync),
+                    )
+                    .changed()
+                {
+                    update_flag.set(UpdateFlag::UPDATE_SURFACE, true);
+                }
+                ui.end_row();
+
+                ui.label("Present mode:");
+                let before = state.present_mode;
+                egui::ComboBox::from_id_salt("config_present_mode")
+                    .selected_text(format!("{:?}", state.present_mode))
+                    .show_ui(ui, |ui| {
+                        [
+                            (PresentMode::Auto, None),
+                            (PresentMode::Fifo, Some(wgpu::PresentMode::Fifo)),
+                            (
+                                PresentMode::FifoRelaxed,
+                                Some(wgpu::PresentMode::FifoRelaxed),
+                            ),
+                            (PresentMode::Mailbox, Some(wgpu::PresentMode::Mailbox)),
+                            (PresentMode::Immediate, Some(wgpu::PresentMode::Immediate)),
+                        ]
+                        .into_iter()
+                        .filter(|(_, mode)| {
+                            mode.is_none_or(|mode| gpu_info.present_modes.contains(&mode))
+                        })
+                        .for_each(|(mode, _)| {
+                            ui.selectable_value(&mut state.present_mode, mode, format!("{mode:?}"));
+                        });
+                    })
+                    .response
+                    .on_hover_text(
+                        "Mailbox gives low latency without tearing where supported. \
+                         Auto follows VSync.",
+                    );
+                if state.present_mode != before {
+                    update_flag.set(UpdateFlag::UPDATE_SURFACE, true);
+                }
+                ui.end_row();
+
+                ui.label("Frame latency:");
+                if ui
+                    .add(DragValue::new(&mut state.frame_latency).range(0..=3))
+                    .on_hover_text(
+                        "Maximum number of queued frames. \
+                         Lower values reduce latency, higher values may improve throughput.",
+                    )
+                    .changed()
+                {
+                    update_flag.set(UpdateFlag::UPDATE_SURFACE, true);
+                }
+                ui.end_row();
+
+                ui.label("Max FPS:");
+                ui.horizontal(|ui| {
+                    let mut limit = state.max_fps.is_some();
+                    if ui.checkbox(&mut limit, "").changed() {
+                        state.max_fps = limit.then_some(60);
+                    }
+                    if let Some(max_fps) = &mut state.max_fps {
+                        ui.add(DragValue::new(max_fps).range(1..=240));
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Depth format:");
+                egui::ComboBox::from_id_salt("config_depth_format")
+                    .selected_text(format!("{:?}", state.depth_format))
+                    .show_ui(ui, |ui| {
+                        [DepthFormat::Depth32Float, DepthFormat::Depth24Plus]
+                            .into_iter()
+                            .for_each(|format| {
+                                ui.selectable_value(
+                                    &mut state.depth_format,
+                                    format,
+                                    format!("{format:?}"),
+                                );
+                            });
+                    })
+                    .response
+                    .on_hover_text("Applied on the next start");
+                ui.end_row();
+
+                ui.label("Marker resolution:");
+                ui.horizontal(|ui| {
+                    let mut generate = state.marker_resolution.is_some();
+                    if ui
+                        .checkbox(&mut generate, "")
+                        .on_hover_text(
+                            "Generate the transducer marker at this resolution instead of using \
+                             the bundled image. Applied on the next start",
+                        )
+                        .changed()
+                    {
+                        state.marker_resolution = generate.then_some(128);
+                    }
+                    if let Some(resolution) = &mut state.marker_resolution {
+                        ui.add(DragValue::new(resolution).range(MARKER_RESOLUTION_RANGE));
+                    }
+                });
+                ui.end_row();
+            });
+
+        ui.separator();
+        Self::record_settings(ui, state);
+
+        #[cfg(feature = "gltf")]
+        if ui
+            .button("Export scene as glTF...")
+            .on_hover_text("Save the transducers and the slice as a binary glTF file")
+            .clicked()
+            && let Some(path) = rfd::FileDialog::new()
+                .add_filter("glTF", &["glb"])
+                .set_file_name("scene.glb")
+                .save_file()
+        {
+            state.export_gltf = Some(path);
+        }
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            if ui.button("Export settings...").clicked()
+                && let Some(path) = rfd::FileDialog::new()
+                    .add_filter("JSON", &["json"])
+                    .set_file_name("settings.json")
+                    .save_file()
+                && let Err(e) = state.save(&path)
+            {
+                log::error!("Failed to export settings to {}: {}", path.display(), e);
+            }
+
+            if ui.button("Import settings...").clicked()
+                && let Some(path) = rfd::FileDialog::new()
+                    .add_filter("JSON", &["json"])
+                    .pick_file()
+            {
+                match crate::State::load(&path) {
+                    Ok(imported) => {
+                        state.merge(imported);
+                        Self::set_max_passes(ui.ctx(), state.max_passes);
+                        *update_flag = UpdateFlag::all();
+                    }
+                    Err(e) => {
+                        log::error!("Failed to import settings from {}: {}", path.display(), e)
+                    }
+                }
+            }
+        });
+    }
+
+    /// Pressure read back from the slice at the point last clicked on it.
+    fn slice_pick(ui: &mut egui::Ui, state: &mut crate::State, emulator: &EmulatorWrapper) {
+        let Some(pick) = state.slice_pick else {
+            ui.label("Click on the slice to read the pressure there");
+            return;
+        };
+        let origin = Self::display_origin(state, emulator);
+        egui::Grid::new("info_pick_grid")
             .num_columns(2)
             .min_col_width(MIN_COL_WIDTH)
             .spacing(SPACING)
             .striped(true)
             .show(ui, |ui| {
-                ui.label("FOV:");
-                let response = ui.add(
-                    DragValue::new(&mut state.camera.fov)
-                        .speed(1.)
-                        .range(0.0..=180.0)
-                        .suffix("°"),
-                );
+                let p = pick.point - origin;
+                ui.label("Position:");
+                ui.label(format!("({:.3}, {:.3}, {:.3})", p.x, p.y, p.z));
                 ui.end_row();
 
-                ui.label("Near clip:");
-                let response = response.union(
-                    ui.add(
-                        DragValue::new(&mut state.camera.near_clip)
-                            .speed(1. * mm)
-                            .range(0.0..=f32::MAX),
-                    ),
-                );
-                ui.end_row();
+                if let Some(pressure) = pick.pressure {
+                    ui.label("Pressure [Pa]:");
+                    ui.label(format!("{:.3}", pressure.length()));
+                    ui.end_row();
 
-                ui.label("Far clip:");
-                let response = response.union(
-                    ui.add(
-                        DragValue::new(&mut state.camera.far_clip)
-                            .speed(1. * mm)
-                            .range(0.0..=f32::MAX),
-                    ),
-                );
-                ui.end_row();
+                    // Same convention as the phase coloring of the slice
+                    let phase = (-pressure.y.atan2(pressure.x) - state.phase_offset.to_radians())
+                        .rem_euclid(2. * std::f32::consts::PI);
+                    ui.label("Phase [deg]:");
+                    ui.label(format!("{:.1}", phase.to_degrees()));
+                    ui.end_row();
+                }
+            });
+        if ui.small_button("Clear").clicked() {
+            state.slice_pick = None;
+        }
+    }
 
-                response
-            })
-            .inner
+    /// Slider over a window of the system time, which restarts at the current time when it is
+    /// reset or the time is moved out of it by other means.
+    fn scrub_time(ui: &mut egui::Ui, state: &mut crate::State, update_flag: &mut UpdateFlag) {
+        let start = match state.scrub_start {
+            Some(start)
+                if (start..=start.saturating_add(state.scrub_window))
+                    .contains(&state.real_time) =>
+            {
+                start
+            }
+            _ => state.real_time,
+        };
+        state.scrub_start = Some(start);
+
+        ui.label("Scrub window [ns]:");
+        ui.horizontal(|ui| {
+            ui.add(
+                DragValue::new(&mut state.scrub_window)
+                    .speed(1000)
+                    .range(1..=u32::MAX as u64),
+            );
+            if ui
+                .small_button("Reset")
+                .on_hover_text("Start the window at the current time")
+                .clicked()
+            {
+                state.scrub_start = Some(state.real_time);
+            }
+        });
+        ui.end_row();
+
+        ui.label("Scrub [ns]:");
+        let mut offset = state.real_time - start;
+        if ui
+            .add(egui::Slider::new(&mut offset, 0..=state.scrub_window))
             .changed()
         {
-            update_flag.set(UpdateFlag::UPDATE_CAMERA, true);
+            state.real_time = start + offset;
+            update_flag.set(UpdateFlag::UPDATE_TRANS_STATE, true);
         }
+        ui.end_row();
     }
 
-    fn config_tab(
+    /// Lets individual transducers be switched off to study the effect of dead elements.
+    fn transducer_overrides(
         ui: &mut egui::Ui,
-        state: &mut crate::State,
         emulator: &mut EmulatorWrapper,
         update_flag: &mut UpdateFlag,
     ) {
-        egui::Grid::new("config_env_grid")
-            .num_columns(2)
-            .min_col_width(MIN_COL_WIDTH)
-            .spacing(SPACING)
-            .striped(true)
-            .show(ui, |ui| {
-                ui.label("Sound speed:");
-                if ui
-                    .add(DragValue::new(&mut state.sound_speed).speed(100. * mm))
-                    .changed()
-                {
-                    update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
-                }
-                ui.end_row();
+        ui.collapsing("Transducer overrides", |ui| {
+            if ui.button("Clear all overrides").clicked() {
+                emulator.iter_mut().for_each(|mut emulator| {
+                    emulator.disabled.fill(false);
+                    emulator.apply_enable();
+                });
+                update_flag.set(UpdateFlag::UPDATE_TRANS_STATE, true);
+            }
+            emulator.iter_mut().for_each(|mut emulator| {
+                let idx = emulator.cpu.idx();
+                let num_disabled = emulator.disabled.iter().filter(|&&d| d).count();
+                ui.collapsing(format!("Device {idx} ({num_disabled} disabled)"), |ui| {
+                    let changed = egui::Grid::new(format!("config_overrides_grid_{idx}"))
+                        .spacing([0., 0.])
+                        .show(ui, |ui| {
+                            emulator.disabled.iter_mut().enumerate().fold(
+                                false,
+                                |changed, (i, disabled)| {
+                                    let mut enable = !*disabled;
+                                    let response = ui
+                                        .checkbox(&mut enable, "")
+                                        .on_hover_text(format!("Transducer {i}"));
+                                    *disabled = !enable;
+                                    if (i + 1) % OVERRIDE_COLUMNS == 0 {
+                                        ui.end_row();
+                                    }
+                                    changed | response.changed()
+                                },
+                            )
+                        })
+                        .inner;
+                    if changed {
+                        emulator.apply_enable();
+                        update_flag.set(UpdateFlag::UPDATE_TRANS_STATE, true);
+                    }
+                });
             });
+        });
+    }
 
-        ui.label("Device index: show/enable/overheat");
-        egui::Grid::new("config_device_grid")
-            .num_columns(2)
-            .min_col_width(MIN_COL_WIDTH)
-            .spacing(SPACING)
-            .striped(true)
-            .show(ui, |ui| {
-                emulator.iter_mut().enumerate().for_each(|(i, emulator)| {
-                    ui.label(format!("Device {i}: "));
+    fn record_settings(ui: &mut egui::Ui, state: &mut crate::State) {
+        ui.label("Record");
+        ui.add_enabled_ui(state.recording.is_none(), |ui| {
+            egui::Grid::new("config_record_grid")
+                .num_columns(2)
+                .min_col_width(MIN_COL_WIDTH)
+                .spacing(SPACING)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Folder:");
                     ui.horizontal(|ui| {
-                        if ui.checkbox(emulator.visible, "").changed() {
-                            update_flag.set(UpdateFlag::UPDATE_TRANS_ALPHA, true);
-                            let v = if *emulator.visible { 1. } else { 0. };
-                            emulator.transducers.iter_mut().for_each(|s| s.alpha = v);
-                        }
-
-                        if ui.checkbox(emulator.enable, "").changed() {
-                            update_flag.set(UpdateFlag::UPDATE_TRANS_STATE, true);
-                            let v = if *emulator.enable { 1. } else { 0. };
-                            emulator.transducers.iter_mut().for_each(|s| s.enable = v);
-                        }
-
-                        if ui.checkbox(emulator.thermal, "").changed() {
-                            if *emulator.thermal {
-                                emulator.cpu.fpga_mut().assert_thermal_sensor();
-                            } else {
-                                emulator.cpu.fpga_mut().deassert_thermal_sensor();
-                            }
+                        ui.label(state.record.dir.display().to_string());
+                        if ui.small_button("...").clicked()
+                            && let Some(dir) = rfd::FileDialog::new()
+                                .set_directory(&state.record.dir)
+                                .pick_folder()
+                        {
+                            state.record.dir = dir;
                         }
                     });
                     ui.end_row();
-                });
-            });
 
-        ui.separator();
+                    ui.label("Duration [ns]:");
+                    ui.add(
+                        DragValue::new(&mut state.record.duration)
+                            .speed(1_000_000)
+                            .range(0..=u64::MAX),
+                    );
+                    ui.end_row();
 
-        egui::Grid::new("config_ui_grid")
-            .num_columns(2)
-            .min_col_width(MIN_COL_WIDTH)
-            .spacing(SPACING)
-            .striped(true)
-            .show(ui, |ui| {
-                ui.label("UI scale:");
-                ui.add(
-                    DragValue::new(&mut state.ui_scale)
-                        .speed(0.01)
-                        .range(1.0..=10.0),
-                );
-                ui.end_row();
+                    ui.label("Step [ns]:");
+                    ui.add(
+                        DragValue::new(&mut state.record.step)
+                            .speed(1000)
+                            .range(1..=u64::MAX),
+                    );
+                    ui.end_row();
+                });
+        });
 
-                ui.label("Background:");
-                color_picker_color32(ui, &mut state.background, egui::color_picker::Alpha::Opaque);
-            });
+        ui.horizontal(|ui| match state.recording {
+            Some(frame) => {
+                if ui.button("Stop").clicked() {
+                    state.recording = None;
+                }
+                ui.label(format!("Frame {} / {}", frame, state.record.frames()));
+            }
+            None => {
+                if ui
+                    .button("Start")
+                    .on_hover_text(
+                        "Write numbered PNG frames from the current system time, \
+                         advancing it by the step after each frame. \
+                         The slice parameters are saved to params.json.",
+                    )
+                    .clicked()
+                {
+                    state.recording = Some(0);
+                }
+                ui.label(format!("{} frames", state.record.frames()));
+            }
+        });
     }
 
     fn info_tab(
         ui: &mut egui::Ui,
         state: &mut crate::State,
         emulator: &mut EmulatorWrapper,
+        gpu_info: &GpuInfo,
+        server_log: &ServerLog,
+        time_jump: Option<TimeJump>,
         update_flag: &mut UpdateFlag,
     ) {
+        let line_width = state.overlay_line_width;
+
+        ui.collapsing("Picked point", |ui| {
+            Self::slice_pick(ui, state, emulator);
+        });
+
+        ui.collapsing("Rx", |ui| {
+            egui::Grid::new("info_rx_grid")
+                .num_columns(4)
+                .spacing(SPACING)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Device");
+                    ui.label("Data");
+                    ui.label("Msg ID");
+                    ui.label("Error");
+                    ui.end_row();
+
+                    emulator
+                        .rx_messages()
+                        .iter()
+                        .enumerate()
+                        .for_each(|(i, rx)| {
+                            ui.label(format!("{i}"));
+                            ui.label(format!("0x{:02X}", rx.data()));
+                            ui.label(format!("0x{:X}", rx.ack().msg_id()));
+                            ui.label(format!("0x{:X}", rx.ack().err()));
+                            ui.end_row();
+                        });
+                });
+        });
+
+        let num_devices = emulator.num_devices();
+        if state.info_device.is_some_and(|i| i >= num_devices) {
+            state.info_device = None;
+        }
+        egui::ComboBox::from_label("Device")
+            .selected_text(match state.info_device {
+                Some(i) => format!("Device {i}"),
+                None => "All".to_string(),
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut state.info_device, None, "All");
+                (0..num_devices).for_each(|i| {
+                    ui.selectable_value(&mut state.info_device, Some(i), format!("Device {i}"));
+                });
+            });
+
+        if state.debug
+            && ui
+                .button("Load modulation...")
+                .on_hover_text("JSON file of {\"data\": [...], \"divide\": N}")
+                .clicked()
+        {
+            state.preview_modulation = rfd::FileDialog::new()
+                .add_filter("JSON", &["json"])
+                .pick_file();
+        }
+
+        let selected = state.info_device;
         emulator.iter_mut().for_each(|emulator| {
             let cpu = emulator.cpu;
+            if selected.is_some_and(|i| i != cpu.idx()) {
+                return;
+            }
             ui.collapsing(format!("Device {}", cpu.idx()), |ui| {
                 ui.collapsing("Silencer", |ui| {
                     if cpu.fpga().silencer_fixed_completion_steps_mode() {
@@ -916,12 +2665,17 @@ impl EguiRenderer {
                             .width(ui.max_rect().width() * 0.8)
                             .height(200.)
                             .show(ui, |plot_ui| {
-                                plot_ui.line(Line::new(
-                                    "",
-                                    PlotPoints::from_iter(
-                                        m.into_iter().enumerate().map(|(i, v)| [i as f64, v as _]),
-                                    ),
-                                ));
+                                plot_ui.line(
+                                    Line::new(
+                                        "",
+                                        PlotPoints::from_iter(
+                                            m.into_iter()
+                                                .enumerate()
+                                                .map(|(i, v)| [i as f64, v as _]),
+                                        ),
+                                    )
+                                    .width(line_width),
+                                );
                             });
                     });
                 });
@@ -1105,7 +2859,21 @@ impl EguiRenderer {
                         _ => unreachable!(),
                     };
 
-                    (0..4).for_each(|i| {
+                    (0..gpio_out_types.len()).for_each(|i| {
+                        ui.label(format!(
+                            "GPIO {i}: {}",
+                            gpio_out_type_name(gpio_out_types[i])
+                        ));
+                        match gpio_out_types[i] {
+                            GPIO_O_TYPE_NONE => {
+                                ui.weak("No output assigned");
+                                return;
+                            }
+                            GPIO_O_TYPE_SYNC | GPIO_O_TYPE_SYNC_DIFF => {
+                                ui.weak("Sync timing is not emulated, the trace stays low");
+                            }
+                            _ => {}
+                        }
                         let gpio_out = gpio_out(gpio_out_types[i], gpio_out_values[i]);
                         egui_plot::Plot::new(format!("gpio_{i}"))
                             .auto_bounds([true, false])
@@ -1124,15 +2892,18 @@ impl EguiRenderer {
                             .width(ui.max_rect().width() * 0.8)
                             .height(100.)
                             .show(ui, |plot_ui| {
-                                plot_ui.line(Line::new(
-                                    "",
-                                    PlotPoints::from_iter(
-                                        gpio_out
-                                            .into_iter()
-                                            .enumerate()
-                                            .map(|(i, v)| [i as f64, v as _]),
-                                    ),
-                                ));
+                                plot_ui.line(
+                                    Line::new(
+                                        "",
+                                        PlotPoints::from_iter(
+                                            gpio_out
+                                                .into_iter()
+                                                .enumerate()
+                                                .map(|(i, v)| [i as f64, v as _]),
+                                        ),
+                                    )
+                                    .width(line_width),
+                                );
                             });
                     });
                 });
@@ -1159,19 +2930,50 @@ impl EguiRenderer {
                 ui.label(format!("{}", state.real_time));
                 ui.end_row();
 
-                if state.auto_play {
-                    ui.label("Time scale:");
-                    ui.add(
-                        DragValue::new(&mut state.time_scale)
-                            .speed(0.001)
-                            .range(0.0..=f32::MAX),
-                    );
-                } else {
+                if let Some(offset) = state.time_offset {
+                    ui.label("Client offset [ns]:");
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{offset:+}"));
+                        if ui
+                            .small_button("Clear")
+                            .on_hover_text("Return to the time base of the simulator")
+                            .clicked()
+                        {
+                            state.time_offset = None;
+                            update_flag.set(UpdateFlag::UPDATE_TRANS_STATE, true);
+                        }
+                    });
+                    ui.end_row();
+                }
+
+                if let Some(jump) = time_jump {
                     ui.label("");
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        if jump.to < jump.from {
+                            format!("Time went backward from {}", jump.from)
+                        } else {
+                            format!("Time jumped forward from {}", jump.from)
+                        },
+                    );
+                    ui.end_row();
+                }
+
+                ui.label("Time scale:");
+                ui.add(
+                    DragValue::new(&mut state.time_scale)
+                        .speed(0.001)
+                        .range(0.0..=f32::MAX),
+                );
+                ui.end_row();
+
+                if !state.auto_play || state.deterministic {
+                    let step = state.effective_time_step();
+
+                    ui.label("Step [ns]:");
                     ui.horizontal(|ui| {
                         if ui.button("+").clicked() {
-                            state.real_time =
-                                state.real_time.wrapping_add_signed(state.time_step as _);
+                            state.real_time = state.real_time.wrapping_add(step);
                             update_flag.set(UpdateFlag::UPDATE_TRANS_STATE, true);
                         }
                         ui.add(
@@ -1180,17 +2982,230 @@ impl EguiRenderer {
                                 .range(1..=i32::MAX),
                         );
                     });
+                    ui.end_row();
+
+                    ui.label("Scale step:");
+                    ui.checkbox(&mut state.scale_time_step, "")
+                        .on_hover_text("Multiply each manual step by the time scale");
+                    ui.end_row();
+
+                    ui.label("Effective step [ns]:");
+                    ui.label(format!("{step}"));
+                    ui.end_row();
+                }
+
+                if !state.auto_play {
+                    Self::scrub_time(ui, state, update_flag);
+                } else if !state.deterministic {
+                    ui.label("Emulator step [ns]:");
+                    ui.add(
+                        DragValue::new(&mut state.emulator_update_interval)
+                            .speed(1000)
+                            .range(ULTRASOUND_PERIOD.as_nanos() as u64..=1_000_000_000),
+                    )
+                    .on_hover_text(
+                        "Interval at which the firmware is stepped. \
+                         Shorter intervals follow it more closely at a higher CPU cost.",
+                    );
+                    ui.end_row();
+                }
+            });
+
+        ui.separator();
+
+        ui.collapsing("Diagnostics", |ui| {
+            egui::Grid::new("info_diagnostics_grid")
+                .num_columns(2)
+                .min_col_width(MIN_COL_WIDTH)
+                .spacing(SPACING)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Devices:");
+                    ui.label(emulator.num_devices().to_string());
+                    ui.end_row();
+
+                    ui.label("Transducers:");
+                    ui.label(emulator.transducers().len().to_string());
+                    ui.end_row();
+
+                    gpu_info.buffer_sizes.iter().for_each(|(name, size)| {
+                        ui.label(format!("{name}:"));
+                        ui.label(format_bytes(*size));
+                        ui.end_row();
+                    });
+
+                    ui.label("Total:");
+                    ui.label(format_bytes(
+                        gpu_info.buffer_sizes.iter().map(|(_, size)| size).sum(),
+                    ));
+                    ui.end_row();
+                });
+        });
+
+        ui.separator();
+
+        ui.collapsing("GPU", |ui| {
+            egui::Grid::new("info_gpu_grid")
+                .num_columns(2)
+                .min_col_width(MIN_COL_WIDTH)
+                .spacing(SPACING)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Adapter:");
+                    ui.label(&gpu_info.adapter.name);
+                    ui.end_row();
+
+                    ui.label("Backend:");
+                    ui.label(format!("{:?}", gpu_info.adapter.backend));
+                    ui.end_row();
+
+                    ui.label("Device type:");
+                    ui.label(format!("{:?}", gpu_info.adapter.device_type));
+                    ui.end_row();
+
+                    ui.label("Driver:");
+                    ui.label(format!(
+                        "{} {}",
+                        gpu_info.adapter.driver, gpu_info.adapter.driver_info
+                    ));
+                    ui.end_row();
+
+                    ui.label("Surface format:");
+                    ui.label(format!("{:?}", gpu_info.format));
+                    ui.end_row();
+
+                    ui.label("Present mode:");
+                    ui.label(format!("{:?}", gpu_info.present_mode));
+                    ui.end_row();
+                });
+        });
+
+        ui.separator();
+
+        let errors = server_log.errors.entries();
+        ui.collapsing(format!("Server errors ({})", errors.len()), |ui| {
+            if errors.is_empty() {
+                ui.label("No errors");
+                return;
+            }
+            for error in &errors {
+                ui.label(error);
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Copy").clicked() {
+                    ui.ctx().copy_text(errors.join("\n"));
+                }
+                if ui.button("Clear").clicked() {
+                    server_log.errors.clear();
+                }
+            });
+        });
+
+        let connection_log = &server_log.connection;
+        let entries = connection_log.entries();
+        ui.collapsing("Connection log", |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(200.)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for entry in &entries {
+                        ui.monospace(entry);
+                    }
+                });
+            ui.horizontal(|ui| {
+                if ui.button("Copy").clicked() {
+                    ui.ctx().copy_text(entries.join("\n"));
+                }
+                if ui.button("Clear").clicked() {
+                    connection_log.clear();
+                }
+                match connection_log.file() {
+                    Some(path) => {
+                        if ui
+                            .button("Stop writing to file")
+                            .on_hover_text(path.display().to_string())
+                            .clicked()
+                        {
+                            let _ = connection_log.set_file(None);
+                        }
+                    }
+                    None => {
+                        if ui.button("Write to file...").clicked()
+                            && let Some(path) = rfd::FileDialog::new()
+                                .add_filter("Log", &["log", "txt"])
+                                .set_file_name("connection.log")
+                                .save_file()
+                            && let Err(e) = connection_log.set_file(Some(&path))
+                        {
+                            log::error!("Failed to open {}: {}", path.display(), e);
+                        }
+                    }
                 }
-                ui.end_row();
             });
+        });
+    }
+
+    pub(crate) fn _waiting(&self, ctx: &egui::Context, state: &mut crate::State) {
+        let response = Self::control_panel(state).show(ctx, |ui| {
+            ui.label("Waiting for client connection...");
+            if state.show_hints {
+                ui.separator();
+                ui.label(format!(
+                    "Connect a controller with the simulator or remote link to port {}.",
+                    state.port
+                ));
+                ui.label("The geometry sent by the controller will be shown here.");
+                if let Some(stream_port) = state.stream_port {
+                    ui.label(format!("Slice images are streamed on port {stream_port}."));
+                }
+            }
+            ui.checkbox(&mut state.show_hints, "Show hints");
+        });
+        Self::store_panel_rect(ctx, state, response);
     }
 
-    pub(crate) fn _waiting(&self, ctx: &egui::Context) {
-        egui::Window::new("Control panel")
-            .resizable(true)
+    fn control_panel(state: &crate::State) -> egui::Window<'static> {
+        let locked = state.panel_locked;
+        let window = egui::Window::new("Control panel")
+            .resizable(!locked)
+            .movable(!locked)
             .vscroll(true)
-            .default_open(true)
-            .show(ctx, |ui| ui.label("Waiting for client connection..."));
+            .default_open(true);
+        let window = match state.panel_pos {
+            Some(pos) if locked => window.fixed_pos(pos),
+            Some(pos) => window.default_pos(pos),
+            None => window,
+        };
+        match state.panel_size {
+            Some(size) if locked => window.fixed_size(size),
+            Some(size) => window.default_size(size),
+            None => window,
+        }
+    }
+
+    // The window size includes the frame and the title bar, while `default_size` takes the content size.
+    fn store_panel_rect(
+        ctx: &egui::Context,
+        state: &mut crate::State,
+        response: Option<egui::InnerResponse<Option<()>>>,
+    ) {
+        let Some(response) = response else {
+            return;
+        };
+        if response.inner.is_none() {
+            return;
+        }
+        let style = ctx.global_style();
+        let frame = egui::Frame::window(&style);
+        let title_height = ctx
+            .fonts_mut(|fonts| fonts.row_height(&egui::TextStyle::Heading.resolve(&style)))
+            .max(style.spacing.interact_size.y)
+            + frame.inner_margin.sum().y;
+        let chrome =
+            frame.total_margin().sum() + egui::vec2(0.0, title_height + frame.stroke.width);
+        let rect = response.response.rect;
+        state.panel_pos = Some(rect.min);
+        state.panel_size = Some(rect.size() - chrome);
     }
 
     pub fn on_window_event(
@@ -1209,3 +3224,37 @@ impl EguiRenderer {
         EventResult::Wait
     }
 }
+
+fn gpio_out_type_name(ty: u8) -> &'static str {
+    use autd3_firmware_emulator::fpga::params::*;
+    match ty {
+        GPIO_O_TYPE_NONE => "None",
+        GPIO_O_TYPE_BASE_SIG => "Base signal",
+        GPIO_O_TYPE_THERMO => "Thermal sensor",
+        GPIO_O_TYPE_FORCE_FAN => "Force fan",
+        GPIO_O_TYPE_SYNC => "Sync",
+        GPIO_O_TYPE_MOD_SEGMENT => "Modulation segment",
+        GPIO_O_TYPE_MOD_IDX => "Modulation index",
+        GPIO_O_TYPE_STM_SEGMENT => "STM segment",
+        GPIO_O_TYPE_STM_IDX => "STM index",
+        GPIO_O_TYPE_IS_STM_MODE => "Is STM mode",
+        GPIO_O_TYPE_SYS_TIME_EQ => "System time equal",
+        GPIO_O_TYPE_SYNC_DIFF => "Sync diff",
+        GPIO_O_TYPE_PWM_OUT => "PWM out",
+        GPIO_O_TYPE_DIRECT => "Direct",
+        _ => "Unknown",
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const KIB: f64 = 1024.;
+    const MIB: f64 = 1024. * KIB;
+    let b = bytes as f64;
+    if b >= MIB {
+        format!("{:.1} MiB", b / MIB)
+    } else if b >= KIB {
+        format!("{:.1} KiB", b / KIB)
+    } else {
+        format!("{bytes} B")
+    }
+}
@@ -0,0 +1,265 @@
+use autd3_driver::common::mm;
+use bytemuck::{Pod, Zeroable};
+use egui_wgpu::wgpu;
+use std::{borrow::Cow, mem};
+use wgpu::{Device, Queue, RenderPass, SurfaceConfiguration, util::DeviceExt};
+
+use crate::{Matrix4, Vector3, common::transform::to_gl_pos, state::State};
+
+use super::depth_texture::depth_stencil_state;
+
+const MAX_LINES_PER_AXIS: usize = 1001;
+
+pub struct GridRenderer {
+    vertex_buf: wgpu::Buffer,
+    line_count: u32,
+    proj_view_buf: wgpu::Buffer,
+    line_buf: wgpu::Buffer,
+    line: LineParams,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct Vertex {
+    _pos: [f32; 4],
+}
+
+// Lines are expanded into screen-space quads in the vertex shader,
+// since the width of line primitives is fixed to one pixel.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, PartialEq)]
+struct LineParams {
+    viewport: [f32; 2],
+    width: f32,
+    _pad: f32,
+}
+
+fn vertex(pos: Vector3) -> Vertex {
+    Vertex {
+        _pos: to_gl_pos(pos).extend(1.).into(),
+    }
+}
+
+fn create_vertices(state: &State) -> Vec<Vertex> {
+    let spacing = state.grid.spacing.max(1. * mm);
+    let n = ((state.grid.extent / spacing) as usize).min(MAX_LINES_PER_AXIS / 2) as isize;
+    let e = n as f32 * spacing;
+    let z = state.grid.height;
+    (-n..=n)
+        .flat_map(|k| {
+            let v = k as f32 * spacing;
+            [
+                vertex(Vector3::new(v, -e, z)),
+                vertex(Vector3::new(v, e, z)),
+                vertex(Vector3::new(-e, v, z)),
+                vertex(Vector3::new(e, v, z)),
+            ]
+        })
+        .collect()
+}
+
+impl GridRenderer {
+    pub fn new(
+        device: &Device,
+        surface_config: &SurfaceConfiguration,
+        depth_format: wgpu::TextureFormat,
+        state: &State,
+    ) -> Self {
+        let vertex_size = mem::size_of::<Vertex>();
+        let vertex_data = create_vertices(state);
+
+        let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid Vertex Buffer"),
+            usage: wgpu::BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(&vertex_data),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(64),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(mem::size_of::<LineParams>() as _),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[Some(&bind_group_layout)],
+            immediate_size: 0,
+        });
+
+        let proj_view_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Grid Projection View Buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: size_of::<Matrix4>() as wgpu::BufferAddress,
+            mapped_at_creation: false,
+        });
+
+        let line = LineParams {
+            viewport: [surface_config.width as f32, surface_config.height as f32],
+            width: 1.,
+            _pad: 0.,
+        };
+        let line_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid Line Buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            contents: bytemuck::bytes_of(&line),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: proj_view_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: line_buf.as_entire_binding(),
+                },
+            ],
+            label: None,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader.wgsl"))),
+        });
+
+        // Each instance is one line, read as a pair of consecutive vertices.
+        let vertex_buffers = [wgpu::VertexBufferLayout {
+            array_stride: 2 * vertex_size as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: 0,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: vertex_size as wgpu::BufferAddress,
+                    shader_location: 1,
+                },
+            ],
+        }];
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: None,
+                compilation_options: Default::default(),
+                buffers: &vertex_buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: None,
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_config.view_formats[0],
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::SrcAlpha,
+                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(depth_stencil_state(depth_format)),
+            multisample: wgpu::MultisampleState::default(),
+            cache: None,
+            multiview_mask: None,
+        });
+
+        Self {
+            vertex_buf,
+            line_count: (vertex_data.len() / 2) as _,
+            proj_view_buf,
+            line_buf,
+            line,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn update_grid(&mut self, state: &State, device: &Device) {
+        let vertex_data = create_vertices(state);
+        self.vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid Vertex Buffer"),
+            usage: wgpu::BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(&vertex_data),
+        });
+        self.line_count = (vertex_data.len() / 2) as _;
+    }
+
+    /// Sets the line width in physical pixels.
+    pub fn set_line_width(&mut self, width: f32, queue: &Queue) {
+        self.update_line(LineParams { width, ..self.line }, queue);
+    }
+
+    pub fn set_viewport(&mut self, width: u32, height: u32, queue: &Queue) {
+        self.update_line(
+            LineParams {
+                viewport: [width as f32, height as f32],
+                ..self.line
+            },
+            queue,
+        );
+    }
+
+    fn update_line(&mut self, line: LineParams, queue: &Queue) {
+        if line != self.line {
+            self.line = line;
+            queue.write_buffer(&self.line_buf, 0, bytemuck::bytes_of(&line));
+        }
+    }
+
+    pub fn update_camera(&mut self, proj_view: Matrix4, queue: &Queue) {
+        queue.write_buffer(
+            &self.proj_view_buf,
+            0,
+            bytemuck::cast_slice(proj_view.as_ref()),
+        );
+    }
+
+    pub fn resize(&mut self, proj_view: Matrix4, width: u32, height: u32, queue: &Queue) {
+        self.update_camera(proj_view, queue);
+        self.set_viewport(width, height, queue);
+    }
+
+    pub fn render(&mut self, pass: &mut RenderPass) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+        pass.draw(0..4, 0..self.line_count);
+    }
+}
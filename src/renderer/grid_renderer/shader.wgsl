@@ -0,0 +1,70 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) edge: f32,
+};
+
+struct Line {
+    viewport: vec2<f32>,
+    width: f32,
+}
+
+@group(0)
+@binding(0)
+var<uniform> proj_view: mat4x4<f32>;
+
+@group(0)
+@binding(1)
+var<uniform> line: Line;
+
+// Lines fade out over this many pixels on each side for anti-aliasing.
+const FEATHER: f32 = 1.0;
+const W_MIN: f32 = 1e-5;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @location(0) start: vec4<f32>,
+    @location(1) end: vec4<f32>,
+) -> VertexOutput {
+    var a = proj_view * start;
+    var b = proj_view * end;
+    var result: VertexOutput;
+    if a.w < W_MIN && b.w < W_MIN {
+        // Entirely behind the camera
+        result.position = vec4(0.0, 0.0, 2.0, 1.0);
+        result.edge = 0.0;
+        return result;
+    }
+    // Clip the segment against the camera plane before the perspective division.
+    if a.w < W_MIN {
+        a = mix(a, b, (W_MIN - a.w) / (b.w - a.w));
+    } else if b.w < W_MIN {
+        b = mix(b, a, (W_MIN - b.w) / (a.w - b.w));
+    }
+
+    let along = vertex_index / 2u;
+    let side = f32(vertex_index % 2u) * 2.0 - 1.0;
+
+    let dir = b.xy / b.w - a.xy / a.w;
+    let screen_dir = dir * line.viewport;
+    var normal = vec2(0.0, 1.0);
+    if dot(screen_dir, screen_dir) > 0.0 {
+        normal = normalize(vec2(-screen_dir.y, screen_dir.x));
+    }
+    let half_width = 0.5 * line.width + FEATHER;
+
+    var p = a;
+    if along == 1u {
+        p = b;
+    }
+
+    result.position = p + vec4(normal * side * half_width * 2.0 / line.viewport * p.w, 0.0, 0.0);
+    result.edge = side * half_width;
+    return result;
+}
+
+@fragment
+fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = clamp(0.5 * line.width + FEATHER - abs(vertex.edge), 0.0, 1.0);
+    return vec4<f32>(0.7, 0.7, 0.7, 0.5 * coverage);
+}
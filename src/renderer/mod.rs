@@ -1,48 +1,111 @@
 mod depth_texture;
 mod egui_renderer;
+mod grid_renderer;
+mod scene_renderer;
 mod slice_renderer;
+#[cfg(test)]
+mod test_util;
 mod transducer_renderer;
 
-use std::{num::NonZeroU32, sync::Arc, time::{Duration, Instant}};
+use std::{
+    num::NonZeroU32,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    Matrix4, State, Vector3,
-    common::camera::{Camera, CameraPerspective, create_camera},
+    MIN_WINDOW_SIZE, State, Vector2,
     emulator::EmulatorWrapper,
     error::{Result, SimulatorError},
     event::{EventResult, UserEvent},
+    log,
+    server::ServerLog,
+    state::PresentMode,
     update_flag::UpdateFlag,
 };
 
-use depth_texture::DepthTexture;
 use egui::ViewportId;
 use egui_renderer::EguiRenderer;
 use egui_wgpu::ScreenDescriptor;
+use scene_renderer::SceneRenderer;
 use wgpu::{ExperimentalFeatures, Trace};
 use winit::{event::DeviceEvent, event_loop::EventLoopProxy, window::Window};
 
+#[cfg(feature = "gltf")]
+pub use transducer_renderer::trans_color;
+
 pub struct Renderer {
+    /// Configured with the target of `scene`.
     surface: wgpu::Surface<'static>,
-    surface_config: wgpu::SurfaceConfiguration,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+    /// Present modes supported by the surface.
+    present_modes: Vec<wgpu::PresentMode>,
     egui_renderer: egui_renderer::EguiRenderer,
-    transducer_renderer: transducer_renderer::TransducerRenderer,
-    slice_renderer: slice_renderer::SliceRenderer,
-    depth_texture: DepthTexture,
-    camera: Camera<f32>,
+    scene: SceneRenderer,
+}
+
+const PREFERRED_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
+
+/// Picks the preferred format, then another 8-bit RGBA format, preferring sRGB ones. Screenshots
+/// are read back as 8-bit RGBA, so no other format is accepted.
+fn surface_format(supported: &[wgpu::TextureFormat]) -> Option<wgpu::TextureFormat> {
+    [
+        PREFERRED_FORMAT,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        wgpu::TextureFormat::Bgra8Unorm,
+        wgpu::TextureFormat::Rgba8Unorm,
+    ]
+    .into_iter()
+    .find(|format| supported.contains(format))
+}
+
+/// Maps `buffer` for reading and waits until it is mapped.
+fn map_read(device: &wgpu::Device, buffer: &wgpu::Buffer) -> Result<()> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    buffer.map_async(wgpu::MapMode::Read, .., move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::PollType::wait_indefinitely())?;
+    receiver.recv().map_err(|_| wgpu::BufferAsyncError)??;
+    Ok(())
+}
+
+fn present_mode(state: &State, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+    let mode = match state.present_mode {
+        PresentMode::Auto => None,
+        PresentMode::Fifo => Some(wgpu::PresentMode::Fifo),
+        PresentMode::FifoRelaxed => Some(wgpu::PresentMode::FifoRelaxed),
+        PresentMode::Mailbox => Some(wgpu::PresentMode::Mailbox),
+        PresentMode::Immediate => Some(wgpu::PresentMode::Immediate),
+    };
+    match mode {
+        Some(mode) if supported.contains(&mode) => mode,
+        _ => {
+            if let Some(mode) = mode {
+                log::error!("Present mode {:?} is not supported by the surface", mode);
+            }
+            if state.vsync {
+                wgpu::PresentMode::AutoVsync
+            } else {
+                wgpu::PresentMode::AutoNoVsync
+            }
+        }
+    }
 }
 
 impl Renderer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         instance: &wgpu::Instance,
         event_loop_proxy: EventLoopProxy<UserEvent>,
         egui_ctx: egui::Context,
         window: Arc<Window>,
+        server_log: ServerLog,
         width: u32,
         height: u32,
         state: &State,
     ) -> Result<Self> {
+        let width = width.max(MIN_WINDOW_SIZE);
+        let height = height.max(MIN_WINDOW_SIZE);
         let surface = instance.create_surface(window.clone())?;
 
         let adapter =
@@ -55,7 +118,8 @@ impl Renderer {
         let (device, queue) =
             crate::executor::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
                 label: None,
-                required_features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+                required_features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                    | (adapter.features() & wgpu::Features::SHADER_F16),
                 required_limits: Default::default(),
                 memory_hints: Default::default(),
                 trace: Trace::Off,
@@ -63,25 +127,27 @@ impl Renderer {
             }))?;
 
         let swapchain_capabilities = surface.get_capabilities(&adapter);
-        let swapchain_format = swapchain_capabilities
-            .formats
-            .iter()
-            .find(|d| **d == wgpu::TextureFormat::Bgra8UnormSrgb)
+        let swapchain_format = surface_format(&swapchain_capabilities.formats)
             .ok_or(SimulatorError::NoSuitableFormat)?;
+        if swapchain_format == PREFERRED_FORMAT {
+            log::info!("Surface format: {:?}", swapchain_format);
+        } else {
+            log::info!(
+                "{:?} is not supported by the surface, falling back to {:?}",
+                PREFERRED_FORMAT,
+                swapchain_format
+            );
+        }
 
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: *swapchain_format,
+            format: swapchain_format,
             width,
             height,
-            present_mode: if state.vsync {
-                wgpu::PresentMode::AutoVsync
-            } else {
-                wgpu::PresentMode::AutoNoVsync
-            },
-            desired_maximum_frame_latency: 0,
+            present_mode: present_mode(state, &swapchain_capabilities.present_modes),
+            desired_maximum_frame_latency: state.frame_latency,
             alpha_mode: swapchain_capabilities.alpha_modes[0],
-            view_formats: vec![wgpu::TextureFormat::Bgra8UnormSrgb],
+            view_formats: vec![swapchain_format],
         };
 
         surface.configure(&device, &surface_config);
@@ -93,30 +159,29 @@ impl Renderer {
                 event_loop_proxy,
                 egui_ctx,
                 window,
+                server_log,
                 &surface_config,
+                adapter.get_info(),
+                swapchain_capabilities.present_modes.clone(),
             ),
-            transducer_renderer: transducer_renderer::TransducerRenderer::new(
-                &device,
-                &queue,
-                &surface_config,
-            )?,
-            slice_renderer: slice_renderer::SliceRenderer::new(&device, &surface_config),
-            depth_texture: DepthTexture::new(&device, &surface_config),
-            camera: create_camera(),
+            scene: SceneRenderer::new(device, queue, surface_config, state)?,
             surface,
-            surface_config,
-            device,
-            queue,
+            present_modes: swapchain_capabilities.present_modes,
         })
     }
 
-    pub fn create_egui_context() -> egui::Context {
-        EguiRenderer::create_egui_context()
+    pub fn create_egui_context(state: &State) -> egui::Context {
+        EguiRenderer::create_egui_context(state)
     }
 
     pub fn initialize(&mut self, emulator: &EmulatorWrapper) {
-        self.transducer_renderer.initialize(&self.device, emulator);
-        self.slice_renderer.initialize(&self.device, emulator);
+        self.scene.initialize(emulator);
+        self.update_buffer_sizes();
+    }
+
+    fn update_buffer_sizes(&mut self) {
+        self.egui_renderer
+            .set_buffer_sizes(self.scene.buffer_sizes());
     }
 
     pub fn run_ui_and_paint(
@@ -128,17 +193,13 @@ impl Renderer {
     ) -> Result<EventResult> {
         let Self {
             surface,
-            surface_config,
-            device,
-            queue,
             egui_renderer,
-            transducer_renderer,
-            slice_renderer,
+            scene,
             ..
         } = self;
 
         let screen_descriptor = ScreenDescriptor {
-            size_in_pixels: [surface_config.width, surface_config.height],
+            size_in_pixels: [scene.config.width, scene.config.height],
             pixels_per_point: window.scale_factor() as f32 * state.ui_scale,
         };
 
@@ -159,9 +220,9 @@ impl Renderer {
             wgpu::CurrentSurfaceTexture::Outdated => {
                 let size = window.inner_size();
                 if size.width > 0 && size.height > 0 {
-                    surface_config.width = size.width;
-                    surface_config.height = size.height;
-                    surface.configure(device, surface_config);
+                    scene.config.width = size.width;
+                    scene.config.height = size.height;
+                    surface.configure(&scene.device, &scene.config);
                 }
                 return Ok(EventResult::RepaintNow);
             }
@@ -174,53 +235,25 @@ impl Renderer {
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        let mut encoder =
-            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        let mut encoder = scene
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
         let load = if emulator.initialized() {
-            {
-                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                    label: None,
-                    timestamp_writes: None,
-                });
-                slice_renderer.compute(&mut compute_pass);
-            }
-
-            {
-                let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: Some("main render pass"),
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &surface_view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(state.background()),
-                            store: wgpu::StoreOp::Store,
-                        },
-                        depth_slice: None,
-                    })],
-                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                        view: self.depth_texture.view(),
-                        depth_ops: Some(wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(1.0),
-                            store: wgpu::StoreOp::Store,
-                        }),
-                        stencil_ops: None,
-                    }),
-                    timestamp_writes: None,
-                    occlusion_query_set: None,
-                    multiview_mask: None,
-                });
-                transducer_renderer.render(&mut rpass);
-                slice_renderer.render(&mut rpass);
-            }
+            scene.encode(
+                &mut encoder,
+                &surface_view,
+                state,
+                state.overlay_line_width * screen_descriptor.pixels_per_point,
+            );
             wgpu::LoadOp::Load
         } else {
-            wgpu::LoadOp::Clear(state.background())
+            wgpu::LoadOp::Clear(scene.background(state))
         };
 
         let result = egui_renderer.run_ui_and_paint(
-            device,
-            queue,
+            &scene.device,
+            &scene.queue,
             load,
             &mut encoder,
             &surface_view,
@@ -231,74 +264,105 @@ impl Renderer {
             update_flag,
         )?;
 
-        queue.submit(Some(encoder.finish()));
+        scene.queue.submit(Some(encoder.finish()));
         surface_texture.present();
 
         if needs_reconfigure {
-            surface.configure(device, surface_config);
+            surface.configure(&scene.device, &scene.config);
         }
 
         Ok(result)
     }
 
-    pub fn update_camera(&mut self, state: &State, window: &Window) {
-        crate::common::camera::set_camera(
-            &mut self.camera,
-            Vector3::new(state.camera.pos.x, state.camera.pos.y, state.camera.pos.z),
-            Vector3::new(state.camera.rot.x, state.camera.rot.y, state.camera.rot.z),
-        );
-        let view_proj = Self::proj_view(&self.camera, state, window);
-        self.transducer_renderer
-            .update_camera(view_proj, &self.queue);
-        self.slice_renderer.update_camera(view_proj, &self.queue);
+    /// Captures a recorded frame by rendering the scene without the UI into an offscreen target
+    /// of `width` x `height`, returned as RGBA8 rows, top row first. Nothing is presented to the surface.
+    pub fn capture_frame(
+        &mut self,
+        state: &State,
+        emulator: &EmulatorWrapper,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>> {
+        self.scene.capture(state, emulator, width, height)
     }
 
-    fn proj_view(camera: &Camera<f32>, state: &State, window: &Window) -> Matrix4 {
-        fn projection(state: &State, window: &Window) -> Matrix4 {
-            let draw_size = window.inner_size();
-            Matrix4::from_cols_array_2d(
-                &CameraPerspective {
-                    fov: state.camera.fov,
-                    near_clip: state.camera.near_clip,
-                    far_clip: state.camera.far_clip,
-                    aspect_ratio: (draw_size.width as f32) / (draw_size.height as f32),
-                }
-                .projection(),
-            )
-        }
+    pub fn slice_resolution(&self) -> (u32, u32) {
+        self.scene.slice_resolution()
+    }
 
-        fn view(camera: &Camera<f32>) -> Matrix4 {
-            Matrix4::from_cols_array_2d(&camera.orthogonal())
-        }
+    pub fn read_slice(&self) -> Result<((u32, u32), Vec<u8>)> {
+        self.scene.read_slice()
+    }
+
+    pub fn export_slice_png(&self, path: &std::path::Path) -> Result<()> {
+        self.scene.export_slice_png(path)
+    }
 
-        projection(state, window) * view(camera)
+    pub fn export_slice_field(&self, state: &State, path: &std::path::Path) -> Result<()> {
+        self.scene.export_slice_field(state, path)
+    }
+
+    pub fn export_slice_npy(&self, state: &State, path: &std::path::Path) -> Result<()> {
+        self.scene.export_slice_npy(state, path)
+    }
+
+    pub fn probe_slice(&self, uv: Vector2) -> Result<Option<Vector2>> {
+        self.scene.probe_slice(uv)
+    }
+
+    pub fn update_camera(&mut self, state: &State, window: &Window) {
+        let draw_size = window.inner_size();
+        self.scene.update_camera(
+            state,
+            (draw_size.width.max(1) as f32) / (draw_size.height.max(1) as f32),
+        );
     }
 
     pub fn update_trans_pos(&mut self, emulator: &EmulatorWrapper) {
-        self.transducer_renderer.update_model(emulator, &self.queue);
-        self.slice_renderer.update_trans_pos(emulator, &self.queue);
+        self.scene.update_trans_pos(emulator);
     }
 
     pub fn update_trans_state(&mut self, emulator: &EmulatorWrapper) {
-        self.slice_renderer
-            .update_trans_state(emulator, &self.queue);
+        self.scene.update_trans_state(emulator);
+    }
+
+    pub fn diff_sent(&mut self, emulator: &EmulatorWrapper) {
+        self.scene.diff_sent(emulator);
     }
 
-    pub fn update_color(&mut self, emulator: &EmulatorWrapper) {
-        self.transducer_renderer.update_color(emulator, &self.queue);
+    /// Returns whether the transducers are still fading in or out.
+    pub fn update_color(&mut self, state: &State, emulator: &EmulatorWrapper) -> bool {
+        self.scene.update_color(state, emulator)
     }
 
     pub fn update_slice(&mut self, state: &State) {
-        self.slice_renderer.update_slice(state, &self.queue);
+        self.scene.update_slice(state);
+        self.update_buffer_sizes();
+    }
+
+    pub fn update_reference(&mut self, state: &State) {
+        self.scene.update_reference(state);
+        self.update_buffer_sizes();
     }
 
     pub fn update_config(&mut self, state: &State, emulator: &EmulatorWrapper) {
-        self.slice_renderer
-            .update_config(state, emulator, &self.queue);
+        self.scene.update_config(state, emulator);
+    }
+
+    pub fn update_grid(&mut self, state: &State) {
+        self.scene.update_grid(state);
+    }
+
+    pub fn update_surface(&mut self, state: &State) {
+        let config = &mut self.scene.config;
+        config.present_mode = present_mode(state, &self.present_modes);
+        config.desired_maximum_frame_latency = state.frame_latency;
+        self.surface.configure(&self.scene.device, config);
+        self.egui_renderer.set_present_mode(config.present_mode);
     }
 
     pub fn update_color_map(&mut self, state: &State) {
-        self.slice_renderer.update_color_map(state, &self.queue);
+        self.scene.update_color_map(state);
     }
 
     pub(crate) fn on_window_event(
@@ -309,11 +373,8 @@ impl Renderer {
     ) -> EventResult {
         let Self {
             surface,
-            surface_config,
-            device,
-            queue,
             egui_renderer,
-            camera,
+            scene,
             ..
         } = self;
         let mut repaint_asap = false;
@@ -325,14 +386,8 @@ impl Renderer {
                     NonZeroU32::new(physical_size.height),
                 ) {
                     repaint_asap = true;
-                    surface_config.width = width.get();
-                    surface_config.height = height.get();
-                    surface.configure(device, surface_config);
-
-                    let view_proj = Self::proj_view(camera, state, window);
-                    self.transducer_renderer.resize(view_proj, queue);
-                    self.slice_renderer.resize(view_proj, queue);
-                    self.depth_texture = DepthTexture::new(device, surface_config);
+                    scene.resize(state, width.get(), height.get());
+                    surface.configure(&scene.device, &scene.config);
                 }
             }
 
@@ -0,0 +1,481 @@
+use crate::{
+    Matrix4, State, Vector2, Vector3,
+    common::camera::{Camera, CameraPerspective, create_camera},
+    emulator::EmulatorWrapper,
+    error::Result,
+};
+
+use super::{
+    depth_texture::{DepthTexture, texture_format},
+    grid_renderer::GridRenderer,
+    map_read,
+    slice_renderer::SliceRenderer,
+    transducer_renderer::TransducerRenderer,
+};
+
+/// Draws the scene without the UI. It only needs a device and the format and size of the target,
+/// so that it can also render offscreen without a window.
+pub struct SceneRenderer {
+    pub(super) device: wgpu::Device,
+    pub(super) queue: wgpu::Queue,
+    /// Format and size of the target the scene is drawn into, i.e. the surface in the window.
+    pub(super) config: wgpu::SurfaceConfiguration,
+    transducer_renderer: TransducerRenderer,
+    slice_renderer: SliceRenderer,
+    grid_renderer: GridRenderer,
+    depth_texture: DepthTexture,
+    camera: Camera<f32>,
+}
+
+impl SceneRenderer {
+    pub fn new(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        state: &State,
+    ) -> Result<Self> {
+        let depth_format = texture_format(state.depth_format);
+        Ok(Self {
+            transducer_renderer: TransducerRenderer::new(
+                &device,
+                &queue,
+                &config,
+                depth_format,
+                state,
+            )?,
+            slice_renderer: SliceRenderer::new(&device, &config, depth_format),
+            grid_renderer: GridRenderer::new(&device, &config, depth_format, state),
+            depth_texture: DepthTexture::new(&device, &config, depth_format),
+            camera: create_camera(),
+            device,
+            queue,
+            config,
+        })
+    }
+
+    pub fn initialize(&mut self, emulator: &EmulatorWrapper) {
+        self.transducer_renderer.initialize(&self.device, emulator);
+        self.slice_renderer.initialize(&self.device, emulator);
+    }
+
+    pub fn buffer_sizes(&self) -> Vec<(&'static str, u64)> {
+        self.transducer_renderer
+            .buffer_sizes()
+            .into_iter()
+            .chain(self.slice_renderer.buffer_sizes())
+            .collect()
+    }
+
+    /// Background color in the encoding of the target.
+    pub fn background(&self, state: &State) -> wgpu::Color {
+        state.background(self.config.view_formats[0].is_srgb())
+    }
+
+    /// Draws the scene into `view` of the target, with overlay lines `line_width` pixels wide.
+    pub fn encode(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        state: &State,
+        line_width: f32,
+    ) {
+        self.grid_renderer
+            .set_viewport(self.config.width, self.config.height, &self.queue);
+        self.grid_renderer.set_line_width(line_width, &self.queue);
+        Self::encode_scene(
+            encoder,
+            view,
+            self.depth_texture.view(),
+            self.background(state),
+            state,
+            &mut self.transducer_renderer,
+            &mut self.slice_renderer,
+            &mut self.grid_renderer,
+        );
+    }
+
+    /// Resizes the target to `width` x `height`.
+    pub fn resize(&mut self, state: &State, width: u32, height: u32) {
+        self.config.width = width;
+        self.config.height = height;
+        let view_proj = Self::proj_view(&self.camera, state, width as f32 / height as f32);
+        self.transducer_renderer.resize(view_proj, &self.queue);
+        self.slice_renderer.resize(view_proj, &self.queue);
+        self.grid_renderer
+            .resize(view_proj, width, height, &self.queue);
+        self.depth_texture =
+            DepthTexture::new(&self.device, &self.config, self.depth_texture.format());
+    }
+
+    /// Computes the slice and draws the scene without the UI into `view`, cleared with `background`.
+    #[allow(clippy::too_many_arguments)]
+    fn encode_scene(
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        background: wgpu::Color,
+        state: &State,
+        transducer_renderer: &mut TransducerRenderer,
+        slice_renderer: &mut SliceRenderer,
+        grid_renderer: &mut GridRenderer,
+    ) {
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: None,
+                timestamp_writes: None,
+            });
+            slice_renderer.compute(
+                &mut compute_pass,
+                state.slice_show_reference,
+                state.half_precision,
+            );
+        }
+
+        // Nothing is depth tested against the slice if it is drawn alone
+        let depth = !(state.skip_slice_depth && !state.show_transducers && !state.grid.show);
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("main render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(background),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: depth.then_some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            multiview_mask: None,
+        });
+        if state.grid.show {
+            grid_renderer.render(&mut rpass);
+        }
+        // The slice is opaque, so drawing it first lets the depth test hide only the transducers
+        // behind it while the transparent parts of the transducers in front blend over it.
+        if state.slice_first {
+            slice_renderer.render(&mut rpass, depth);
+        }
+        if state.show_transducers {
+            if state.phase_arrows {
+                transducer_renderer.render_arrows(&mut rpass);
+            } else {
+                transducer_renderer.render(&mut rpass);
+            }
+        }
+        if !state.slice_first {
+            slice_renderer.render(&mut rpass, depth);
+        }
+    }
+
+    /// Renders the scene into an offscreen target of `width` x `height` in the format of the
+    /// target, returned as RGBA8 rows, top row first. The projection of the target is restored after.
+    pub fn capture(
+        &mut self,
+        state: &State,
+        emulator: &EmulatorWrapper,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>> {
+        let width = width.max(1);
+        let height = height.max(1);
+        let config = wgpu::SurfaceConfiguration {
+            width,
+            height,
+            ..self.config.clone()
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_texture = DepthTexture::new(&self.device, &config, self.depth_texture.format());
+
+        self.set_proj_view(state, width as f32 / height as f32);
+        self.grid_renderer.set_viewport(width, height, &self.queue);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        if emulator.initialized() {
+            Self::encode_scene(
+                &mut encoder,
+                &view,
+                depth_texture.view(),
+                state.background(config.view_formats[0].is_srgb()),
+                state,
+                &mut self.transducer_renderer,
+                &mut self.slice_renderer,
+                &mut self.grid_renderer,
+            );
+        } else {
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(
+                            state.background(config.view_formats[0].is_srgb()),
+                        ),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+                multiview_mask: None,
+            });
+        }
+
+        let unpadded_bytes_per_row = width * 4;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen Readback Buffer"),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            size: (padded_bytes_per_row * height) as _,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
+        let mapped = map_read(&self.device, &buffer);
+
+        // Restore the projection of the target.
+        self.set_proj_view(
+            state,
+            self.config.width.max(1) as f32 / self.config.height.max(1) as f32,
+        );
+        self.grid_renderer
+            .set_viewport(self.config.width, self.config.height, &self.queue);
+        mapped?;
+
+        let bgra = matches!(
+            config.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let data = buffer
+            .get_mapped_range(..)
+            .chunks(padded_bytes_per_row as _)
+            .flat_map(|row| {
+                row[..unpadded_bytes_per_row as usize]
+                    .chunks(4)
+                    .flat_map(|p| {
+                        if bgra {
+                            [p[2], p[1], p[0], p[3]]
+                        } else {
+                            [p[0], p[1], p[2], p[3]]
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        buffer.unmap();
+        Ok(data)
+    }
+
+    pub fn slice_resolution(&self) -> (u32, u32) {
+        self.slice_renderer.texture_size()
+    }
+
+    pub fn read_slice(&self) -> Result<((u32, u32), Vec<u8>)> {
+        self.slice_renderer.read_texture(&self.device, &self.queue)
+    }
+
+    pub fn export_slice_png(&self, path: &std::path::Path) -> Result<()> {
+        self.slice_renderer
+            .export_png(&self.device, &self.queue, path)
+    }
+
+    pub fn export_slice_field(&self, state: &State, path: &std::path::Path) -> Result<()> {
+        self.slice_renderer
+            .export_field(&self.device, &self.queue, state, path)
+    }
+
+    pub fn export_slice_npy(&self, state: &State, path: &std::path::Path) -> Result<()> {
+        self.slice_renderer
+            .export_npy(&self.device, &self.queue, state, path)
+    }
+
+    pub fn probe_slice(&self, uv: Vector2) -> Result<Option<Vector2>> {
+        self.slice_renderer.probe(uv, &self.device, &self.queue)
+    }
+
+    pub fn update_camera(&mut self, state: &State, aspect_ratio: f32) {
+        crate::common::camera::set_camera(
+            &mut self.camera,
+            Vector3::new(state.camera.pos.x, state.camera.pos.y, state.camera.pos.z),
+            Vector3::new(state.camera.rot.x, state.camera.rot.y, state.camera.rot.z),
+        );
+        self.set_proj_view(state, aspect_ratio);
+    }
+
+    fn set_proj_view(&mut self, state: &State, aspect_ratio: f32) {
+        let view_proj = Self::proj_view(&self.camera, state, aspect_ratio);
+        self.transducer_renderer.set_culling(state.frustum_culling);
+        self.transducer_renderer
+            .update_camera(view_proj, &self.queue);
+        self.slice_renderer.update_camera(view_proj, &self.queue);
+        self.grid_renderer.update_camera(view_proj, &self.queue);
+    }
+
+    fn proj_view(camera: &Camera<f32>, state: &State, aspect_ratio: f32) -> Matrix4 {
+        fn projection(state: &State, aspect_ratio: f32) -> Matrix4 {
+            Matrix4::from_cols_array_2d(
+                &CameraPerspective {
+                    fov: state.camera.fov,
+                    near_clip: state.camera.near_clip,
+                    far_clip: state.camera.far_clip,
+                    aspect_ratio,
+                }
+                .projection(),
+            )
+        }
+
+        fn view(camera: &Camera<f32>) -> Matrix4 {
+            Matrix4::from_cols_array_2d(&camera.orthogonal())
+        }
+
+        projection(state, aspect_ratio) * view(camera)
+    }
+
+    pub fn update_trans_pos(&mut self, emulator: &EmulatorWrapper) {
+        self.transducer_renderer.update_model(emulator, &self.queue);
+        self.slice_renderer.update_trans_pos(emulator, &self.queue);
+    }
+
+    pub fn update_trans_state(&mut self, emulator: &EmulatorWrapper) {
+        self.slice_renderer
+            .update_trans_state(emulator, &self.queue);
+    }
+
+    pub fn diff_sent(&mut self, emulator: &EmulatorWrapper) {
+        self.transducer_renderer.diff_sent(emulator);
+    }
+
+    /// Returns whether the transducers are still fading in or out.
+    pub fn update_color(&mut self, state: &State, emulator: &EmulatorWrapper) -> bool {
+        self.transducer_renderer
+            .update_color(state, emulator, &self.queue)
+    }
+
+    pub fn update_slice(&mut self, state: &State) {
+        self.slice_renderer
+            .update_slice(state, &self.device, &self.queue);
+    }
+
+    pub fn update_reference(&mut self, state: &State) {
+        if state.slice_reference {
+            self.slice_renderer
+                .snapshot_reference(&self.device, &self.queue);
+        } else {
+            self.slice_renderer.clear_reference();
+        }
+    }
+
+    pub fn update_config(&mut self, state: &State, emulator: &EmulatorWrapper) {
+        self.slice_renderer
+            .update_config(state, emulator, &self.queue);
+    }
+
+    pub fn update_grid(&mut self, state: &State) {
+        self.grid_renderer.update_grid(state, &self.device);
+    }
+
+    pub fn update_color_map(&mut self, state: &State) {
+        self.slice_renderer.update_color_map(state, &self.queue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::test_util::{emulator, gpu, target};
+
+    const SIZE: u32 = 64;
+
+    #[test]
+    fn capture_shows_focus() {
+        let Some((device, queue)) = gpu() else {
+            eprintln!("No GPU adapter available, skipping");
+            return;
+        };
+        let mut state = State::default();
+        // Halfway to the slice, it fills the view, and nothing is drawn in front of it.
+        state.camera.pos = state.slice.pos.lerp(state.camera.pos, 0.5);
+        state.show_transducers = false;
+        let emulator = emulator(&state);
+        state.slice.pressure_max = emulator
+            .transducers()
+            .pressure_at(state.slice.pos, state.sound_speed);
+
+        let mut scene = SceneRenderer::new(
+            device,
+            queue,
+            target(wgpu::TextureFormat::Rgba8Unorm, SIZE, SIZE),
+            &state,
+        )
+        .unwrap();
+        scene.initialize(&emulator);
+        scene.update_trans_pos(&emulator);
+        scene.update_trans_state(&emulator);
+        scene.update_color(&state, &emulator);
+        scene.update_config(&state, &emulator);
+        scene.update_slice(&state);
+        scene.update_color_map(&state);
+        scene.update_camera(&state, 1.);
+        let data = scene.capture(&state, &emulator, SIZE, SIZE).unwrap();
+        assert_eq!(data.len(), (SIZE * SIZE * 4) as usize);
+
+        let brightness = |x: u32, y: u32| {
+            let i = ((y * SIZE + x) * 4) as usize;
+            data[i..i + 3].iter().map(|&c| c as u32).sum::<u32>()
+        };
+        let center = brightness(SIZE / 2, SIZE / 2);
+        [(0, 0), (SIZE - 1, 0), (0, SIZE - 1), (SIZE - 1, SIZE - 1)]
+            .into_iter()
+            .for_each(|(x, y)| {
+                assert!(
+                    center > brightness(x, y),
+                    "center {center} is not brighter than ({x}, {y}) {}",
+                    brightness(x, y)
+                );
+            });
+    }
+}
@@ -1,21 +1,41 @@
 use autd3_driver::common::mm;
 use bytemuck::{NoUninit, Pod, Zeroable};
 use egui_wgpu::wgpu;
-use std::{borrow::Cow, mem};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufWriter, Write},
+    mem,
+    ops::Range,
+    path::Path,
+};
 use wgpu::{ComputePass, Device, Queue, RenderPass, SurfaceConfiguration, util::DeviceExt};
 
 use crate::{
     Matrix4, Vector2, Vector3, Vector4,
     common::transform::{to_gl_pos, to_gl_rot},
     emulator::EmulatorWrapper,
-    state::State,
+    error::Result,
+    export::save_npy,
+    state::{MAX_CONTOURS, State},
 };
 
-use super::DepthTexture;
+use super::{depth_texture::depth_stencil_state, map_read};
 
-const TEXTURE_DIMS: (u32, u32) = (1024, 1024);
+// Unless the resolution is set, the field is sampled once per mm, scaled down to keep within
+// this many texels. Either way, the texture is kept within this size per side and the aspect
+// ratio of the slice is kept.
+const MAX_TEXELS: f32 = 1024. * 1024.;
+const MAX_TEXTURE_DIM: f32 = 4096.;
+// Bytes of the field buffer per texel
+const FIELD_TEXEL_SIZE: u64 = size_of::<[f32; 4]>() as u64;
+// Must match `@workgroup_size` and `TILE_SIZE` in shader.wgsl.
 const WORKGROUP_SIZE: (u32, u32) = (8, 8);
 const COLOR_MAP_TEXTURE_SIZE: u32 = 256;
+const SHADER: &str = include_str!("shader.wgsl");
+// The half precision variant only changes the type the field is accumulated in.
+const ACC_TYPE_F32: &str = "alias acc_t = f32;";
+const ACC_TYPE_F16: &str = "alias acc_t = f16;";
 
 #[derive(NoUninit, Clone, Copy)]
 #[repr(C)]
@@ -24,6 +44,43 @@ struct Config {
     num_trans: u32,
     max_pressure: f32,
     scale: f32,
+    mirror_pos: [f32; 4],
+    mirror_normal: [f32; 4],
+    mirror: u32,
+    color_gamma: f32,
+    rms: u32,
+    persistence: f32,
+    split: u32,
+    split_max_pressure: f32,
+    srgb_correct: u32,
+    phase_offset: f32,
+    quantity: u32,
+    log_scale: u32,
+    min_pressure: f32,
+    // Pads the struct to the 16-byte aligned size of the uniform in shader.wgsl.
+    _pad: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct Contours {
+    levels: [f32; MAX_CONTOURS],
+    count: u32,
+    /// Width of the lines in texels.
+    width: f32,
+    // Pads the struct to the 16-byte aligned size of the uniform in shader.wgsl.
+    _pad: [u32; 2],
+}
+
+impl Config {
+    /// Whether the complex pressure computed with `other` is the same, regardless of how it is displayed.
+    fn same_field(&self, other: &Self) -> bool {
+        self.sound_speed == other.sound_speed
+            && self.num_trans == other.num_trans
+            && self.mirror == other.mirror
+            && self.mirror_pos == other.mirror_pos
+            && self.mirror_normal == other.mirror_normal
+    }
 }
 
 pub struct SliceRenderer {
@@ -34,14 +91,33 @@ pub struct SliceRenderer {
     slice_size_buf: wgpu::Buffer,
     trans_pos_buf: Option<wgpu::Buffer>,
     trans_state_buf: Option<wgpu::Buffer>,
+    reference_state_buf: Option<wgpu::Buffer>,
     config_buf: Option<wgpu::Buffer>,
+    contours_buf: wgpu::Buffer,
+    texture: wgpu::Texture,
     texture_view: wgpu::TextureView,
+    texture_size: (u32, u32),
+    /// Complex pressure at each texel and its mirrored point, from which `colorize` derives the texture.
+    field_buf: wgpu::Buffer,
+    /// Whether the field must be computed again before the next `colorize`.
+    field_dirty: bool,
+    /// Whether the field was last computed from the reference and in half precision.
+    field_source: (bool, bool),
+    config: Option<Config>,
     color_map_texture: wgpu::Texture,
     index_count: usize,
     bind_group: Option<wgpu::BindGroup>,
+    reference_bind_group: Option<wgpu::BindGroup>,
     bind_group_layout: wgpu::BindGroupLayout,
     pipeline: wgpu::RenderPipeline,
+    /// Same as `pipeline`, for render passes without a depth buffer.
+    pipeline_no_depth: wgpu::RenderPipeline,
     compute_pipeline: wgpu::ComputePipeline,
+    /// Accumulates the field in f16. Only available if the device supports `SHADER_F16`.
+    compute_pipeline_f16: Option<wgpu::ComputePipeline>,
+    colorize_pipeline: wgpu::ComputePipeline,
+    /// Whether the render target encodes its input into sRGB.
+    srgb_target: bool,
 }
 
 #[repr(C)]
@@ -58,6 +134,57 @@ fn vertex(pos: [f32; 3], tc: [i8; 2]) -> Vertex {
     }
 }
 
+/// Texels of the slice along its width and height, with `resolution` texels along the longer
+/// side if set. The size is also kept within the texture and storage buffer limits of the device.
+fn texture_size(slice_size: Vector2, resolution: Option<u32>, limits: &wgpu::Limits) -> (u32, u32) {
+    let size = (slice_size / mm).max(Vector2::ONE);
+    let max_texels = (limits.max_storage_buffer_binding_size / FIELD_TEXEL_SIZE) as f32;
+    let max_dim = MAX_TEXTURE_DIM.min(limits.max_texture_dimension_2d as f32);
+    let scale = match resolution {
+        Some(resolution) => resolution as f32 / size.max_element(),
+        None => (MAX_TEXELS / (size.x * size.y)).sqrt().min(1.),
+    }
+    .min((max_texels / (size.x * size.y)).sqrt())
+    .min(max_dim / size.max_element());
+    (
+        ((size.x * scale) as u32).max(1),
+        ((size.y * scale) as u32).max(1),
+    )
+}
+
+fn create_storage_texture(
+    device: &Device,
+    (width, height): (u32, u32),
+) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    (texture, view)
+}
+
+fn create_field_buffer(device: &Device, (width, height): (u32, u32)) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Slice Field Buffer"),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        size: width as u64 * height as u64 * FIELD_TEXEL_SIZE,
+        mapped_at_creation: false,
+    })
+}
+
 fn create_vertices() -> (Vec<Vertex>, Vec<u16>) {
     let vertex_data = [
         vertex([-0.5, -0.5, 0.], [0, 0]),
@@ -72,7 +199,11 @@ fn create_vertices() -> (Vec<Vertex>, Vec<u16>) {
 }
 
 impl SliceRenderer {
-    pub fn new(device: &Device, surface_config: &SurfaceConfiguration) -> Self {
+    pub fn new(
+        device: &Device,
+        surface_config: &SurfaceConfiguration,
+        depth_format: wgpu::TextureFormat,
+    ) -> Self {
         let vertex_size = mem::size_of::<Vertex>();
         let (vertex_data, index_data) = create_vertices();
 
@@ -87,24 +218,9 @@ impl SliceRenderer {
             contents: bytemuck::cast_slice(&index_data),
         });
 
-        let storage_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
-            size: wgpu::Extent3d {
-                width: TEXTURE_DIMS.0,
-                height: TEXTURE_DIMS.1,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::STORAGE_BINDING
-                | wgpu::TextureUsages::COPY_SRC,
-            view_formats: &[],
-        });
-        let storage_texture_view =
-            storage_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Replaced with one of the size of the slice in `update_slice`.
+        let (storage_texture, storage_texture_view) = create_storage_texture(device, (1, 1));
+        let field_buf = create_field_buffer(device, (1, 1));
 
         let slice_size_buf = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Slice Size Buffer"),
@@ -113,8 +229,16 @@ impl SliceRenderer {
             mapped_at_creation: false,
         });
 
+        let contours_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Slice Contours Buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: size_of::<Contours>() as _,
+            mapped_at_creation: false,
+        });
+
+        // The color maps of the left and right half of the split view, one after another.
         let texture_extent = wgpu::Extent3d {
-            width: COLOR_MAP_TEXTURE_SIZE,
+            width: 2 * COLOR_MAP_TEXTURE_SIZE,
             height: 1,
             depth_or_array_layers: 1,
         };
@@ -194,11 +318,11 @@ impl SliceRenderer {
                 },
                 wgpu::BindGroupLayoutEntry {
                     binding: 6,
-                    visibility: wgpu::ShaderStages::COMPUTE,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
-                        min_binding_size: wgpu::BufferSize::new(16),
+                        min_binding_size: wgpu::BufferSize::new(size_of::<Config>() as _),
                     },
                     count: None,
                 },
@@ -212,6 +336,26 @@ impl SliceRenderer {
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(size_of::<Contours>() as _),
+                    },
+                    count: None,
+                },
             ],
         });
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -235,7 +379,7 @@ impl SliceRenderer {
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader.wgsl"))),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER)),
         });
 
         let vertex_buffers = [wgpu::VertexBufferLayout {
@@ -255,47 +399,45 @@ impl SliceRenderer {
             ],
         }];
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: None,
-                compilation_options: Default::default(),
-                buffers: &vertex_buffers,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: None,
-                compilation_options: Default::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_config.view_formats[0],
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent::OVER,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                cull_mode: None,
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: DepthTexture::DEPTH_FORMAT,
-                depth_write_enabled: Some(true),
-                depth_compare: Some(wgpu::CompareFunction::Less),
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            cache: None,
-            multiview_mask: None,
-        });
+        let create_pipeline = |depth_stencil| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: None,
+                    compilation_options: Default::default(),
+                    buffers: &vertex_buffers,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: None,
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: surface_config.view_formats[0],
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::SrcAlpha,
+                                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                            alpha: wgpu::BlendComponent::OVER,
+                        }),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil,
+                multisample: wgpu::MultisampleState::default(),
+                cache: None,
+                multiview_mask: None,
+            })
+        };
+        let pipeline = create_pipeline(Some(depth_stencil_state(depth_format)));
+        let pipeline_no_depth = create_pipeline(None);
 
         let compute_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -307,10 +449,39 @@ impl SliceRenderer {
             label: None,
             layout: Some(&compute_pipeline_layout),
             module: &shader,
-            entry_point: None,
+            entry_point: Some("compute_field"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        let colorize_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&compute_pipeline_layout),
+            module: &shader,
+            entry_point: Some("colorize"),
             compilation_options: Default::default(),
             cache: None,
         });
+        let compute_pipeline_f16 =
+            device
+                .features()
+                .contains(wgpu::Features::SHADER_F16)
+                .then(|| {
+                    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                        label: Some("Slice Shader (f16)"),
+                        source: wgpu::ShaderSource::Wgsl(Cow::Owned(format!(
+                            "enable f16;\n{}",
+                            SHADER.replace(ACC_TYPE_F32, ACC_TYPE_F16)
+                        ))),
+                    });
+                    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                        label: None,
+                        layout: Some(&compute_pipeline_layout),
+                        module: &shader,
+                        entry_point: Some("compute_field"),
+                        compilation_options: Default::default(),
+                        cache: None,
+                    })
+                });
 
         Self {
             vertex_buf,
@@ -319,15 +490,28 @@ impl SliceRenderer {
             model_buf,
             proj_view_buf,
             slice_size_buf,
+            texture: storage_texture,
             texture_view: storage_texture_view,
+            texture_size: (0, 0),
+            field_buf,
+            field_dirty: true,
+            field_source: (false, false),
+            config: None,
             bind_group: None,
+            reference_bind_group: None,
             bind_group_layout,
             pipeline,
+            pipeline_no_depth,
             compute_pipeline,
+            compute_pipeline_f16,
+            colorize_pipeline,
+            srgb_target: surface_config.view_formats[0].is_srgb(),
             color_map_texture,
             trans_pos_buf: None,
             trans_state_buf: None,
+            reference_state_buf: None,
             config_buf: None,
+            contours_buf,
         }
     }
 
@@ -342,7 +526,9 @@ impl SliceRenderer {
 
         self.trans_state_buf = Some(device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Transducer State Buffer"),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
             size: (n * size_of::<Vector4>()) as _,
             mapped_at_creation: false,
         }));
@@ -354,11 +540,41 @@ impl SliceRenderer {
             mapped_at_creation: false,
         }));
 
+        self.config = None;
+        self.bind_group =
+            Some(self.create_bind_group(device, self.trans_state_buf.as_ref().unwrap()));
+        self.clear_reference();
+    }
+
+    /// Sizes in bytes of the buffers and the storage texture allocated for the slice.
+    pub fn buffer_sizes(&self) -> Vec<(&'static str, u64)> {
+        [
+            ("Transducer positions", &self.trans_pos_buf),
+            ("Transducer states", &self.trans_state_buf),
+            ("Reference states", &self.reference_state_buf),
+        ]
+        .into_iter()
+        .filter_map(|(name, buf)| Some((name, buf.as_ref()?.size())))
+        .chain([
+            ("Slice field", self.field_buf.size()),
+            (
+                "Slice texture",
+                self.texture_size.0 as u64 * self.texture_size.1 as u64 * 4,
+            ),
+        ])
+        .collect()
+    }
+
+    fn create_bind_group(
+        &self,
+        device: &Device,
+        trans_state_buf: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
         let color_map_texture_view = self
             .color_map_texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &self.bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -383,7 +599,7 @@ impl SliceRenderer {
                 },
                 wgpu::BindGroupEntry {
                     binding: 5,
-                    resource: self.trans_state_buf.as_ref().unwrap().as_entire_binding(),
+                    resource: trans_state_buf.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 6,
@@ -393,9 +609,44 @@ impl SliceRenderer {
                     binding: 7,
                     resource: wgpu::BindingResource::TextureView(&color_map_texture_view),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: self.field_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: self.contours_buf.as_entire_binding(),
+                },
             ],
             label: None,
-        }))
+        })
+    }
+
+    /// Copies the current transducer states into a reference that is kept until cleared or the geometry changes.
+    pub fn snapshot_reference(&mut self, device: &Device, queue: &Queue) {
+        let Some(trans_state_buf) = self.trans_state_buf.as_ref() else {
+            return;
+        };
+        let reference_state_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Reference Transducer State Buffer"),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            size: trans_state_buf.size(),
+            mapped_at_creation: false,
+        });
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(trans_state_buf, 0, &reference_state_buf, 0, None);
+        queue.submit(Some(encoder.finish()));
+
+        self.reference_bind_group = Some(self.create_bind_group(device, &reference_state_buf));
+        self.reference_state_buf = Some(reference_state_buf);
+        self.field_dirty = true;
+    }
+
+    pub fn clear_reference(&mut self) {
+        self.reference_bind_group = None;
+        self.reference_state_buf = None;
+        self.field_dirty = true;
     }
 
     pub fn update_trans_pos(&mut self, emulator: &EmulatorWrapper, queue: &Queue) {
@@ -405,6 +656,7 @@ impl SliceRenderer {
             0,
             bytemuck::cast_slice(&trans_pos),
         );
+        self.field_dirty = true;
     }
 
     pub fn update_trans_state(&mut self, emulator: &EmulatorWrapper, queue: &Queue) {
@@ -414,6 +666,7 @@ impl SliceRenderer {
             0,
             bytemuck::cast_slice(&trans_state),
         );
+        self.field_dirty = true;
     }
 
     pub fn update_config(&mut self, state: &State, emulator: &EmulatorWrapper, queue: &Queue) {
@@ -422,15 +675,53 @@ impl SliceRenderer {
             num_trans: emulator.transducers().len() as u32,
             max_pressure: state.slice.pressure_max,
             scale: 1. / mm,
+            mirror_pos: to_gl_pos(state.slice.mirror_pos).extend(0.).into(),
+            mirror_normal: to_gl_pos(state.slice.mirror_normal)
+                .normalize_or(Vector3::X)
+                .extend(0.)
+                .into(),
+            mirror: state.slice.mirror as u32,
+            color_gamma: state.slice.color_gamma,
+            rms: state.slice.rms as u32,
+            persistence: state.slice.persistence,
+            split: state.slice.split as u32,
+            split_max_pressure: state.slice.split_pressure_max,
+            srgb_correct: (state.srgb_correct && self.srgb_target) as u32,
+            phase_offset: state.phase_offset.to_radians(),
+            quantity: state.slice.quantity as u32,
+            log_scale: state.slice.log_scale as u32,
+            min_pressure: state.slice.clamped_pressure_min(),
+            _pad: 0,
         };
+        if self
+            .config
+            .is_none_or(|previous| !previous.same_field(&config))
+        {
+            self.field_dirty = true;
+        }
+        self.config = Some(config);
         queue.write_buffer(
             self.config_buf.as_ref().unwrap(),
             0,
             bytemuck::cast_slice(&[config]),
         );
+
+        let mut contours = Contours {
+            levels: [0.; MAX_CONTOURS],
+            count: state.slice.contours.len().min(MAX_CONTOURS) as u32,
+            width: state.overlay_line_width,
+            _pad: [0; 2],
+        };
+        contours
+            .levels
+            .iter_mut()
+            .zip(&state.slice.contours)
+            .for_each(|(dst, &level)| *dst = level);
+        queue.write_buffer(&self.contours_buf, 0, bytemuck::cast_slice(&[contours]));
     }
 
-    pub fn update_slice(&mut self, state: &State, queue: &Queue) {
+    /// Updates the pose of the slice, and recreates the storage texture if its size has changed.
+    pub fn update_slice(&mut self, state: &State, device: &Device, queue: &Queue) {
         let model = Matrix4::from_rotation_translation(
             to_gl_rot(state.slice.rotation()),
             to_gl_pos(state.slice.pos),
@@ -440,7 +731,12 @@ impl SliceRenderer {
             1. / mm,
         ));
         queue.write_buffer(&self.model_buf, 0, bytemuck::cast_slice(model.as_ref()));
-        let slice_size = Vector2::new(state.slice.size.x, state.slice.size.y) / mm;
+        self.field_dirty = true;
+        let texture_size = texture_size(state.slice.size, state.slice.resolution, &device.limits());
+        if texture_size != self.texture_size {
+            self.resize_texture(device, texture_size);
+        }
+        let slice_size = Vector2::new(texture_size.0 as f32, texture_size.1 as f32);
         queue.write_buffer(
             &self.slice_size_buf,
             0,
@@ -448,10 +744,23 @@ impl SliceRenderer {
         );
     }
 
-    pub fn update_color_map(&mut self, _state: &State, queue: &Queue) {
-        let iter = (0..COLOR_MAP_TEXTURE_SIZE).map(|x| x as f64 / COLOR_MAP_TEXTURE_SIZE as f64);
-        let texels = crate::common::color_map::inferno_color_map(iter)
+    fn resize_texture(&mut self, device: &Device, texture_size: (u32, u32)) {
+        (self.texture, self.texture_view) = create_storage_texture(device, texture_size);
+        self.field_buf = create_field_buffer(device, texture_size);
+        self.texture_size = texture_size;
+        if let Some(trans_state_buf) = &self.trans_state_buf {
+            self.bind_group = Some(self.create_bind_group(device, trans_state_buf));
+        }
+        if let Some(reference_state_buf) = &self.reference_state_buf {
+            self.reference_bind_group = Some(self.create_bind_group(device, reference_state_buf));
+        }
+    }
+
+    pub fn update_color_map(&mut self, state: &State, queue: &Queue) {
+        let iter = || (0..COLOR_MAP_TEXTURE_SIZE).map(|x| x as f64 / COLOR_MAP_TEXTURE_SIZE as f64);
+        let texels = [state.slice.color_map, state.slice.split_color_map]
             .into_iter()
+            .flat_map(|color_map| state.slice.colors(color_map, iter()))
             .flat_map(|[r, g, b]| [(r * 255.) as u8, (g * 255.) as u8, (b * 255.) as u8, 255])
             .collect::<Vec<_>>();
         queue.write_texture(
@@ -463,13 +772,18 @@ impl SliceRenderer {
                 rows_per_image: None,
             },
             wgpu::Extent3d {
-                width: COLOR_MAP_TEXTURE_SIZE,
+                width: 2 * COLOR_MAP_TEXTURE_SIZE,
                 height: 1,
                 depth_or_array_layers: 1,
             },
         );
     }
 
+    /// Number of texels the field is sampled at along the width and height of the slice.
+    pub fn texture_size(&self) -> (u32, u32) {
+        self.texture_size
+    }
+
     pub fn update_camera(&mut self, proj_view: Matrix4, queue: &Queue) {
         queue.write_buffer(
             &self.proj_view_buf,
@@ -482,21 +796,352 @@ impl SliceRenderer {
         self.update_camera(proj_view, queue);
     }
 
-    pub fn compute(&mut self, pass: &mut ComputePass) {
-        pass.set_bind_group(0, self.bind_group.as_ref().unwrap(), &[]);
-        pass.set_pipeline(&self.compute_pipeline);
-        pass.dispatch_workgroups(
-            (TEXTURE_DIMS.0 - 1) / WORKGROUP_SIZE.0 + 1,
-            (TEXTURE_DIMS.1 - 1) / WORKGROUP_SIZE.1 + 1,
-            1,
+    /// Copies the part of the storage texture covered by the slice back to the CPU as RGBA8 rows, top row first.
+    pub fn read_texture(&self, device: &Device, queue: &Queue) -> Result<((u32, u32), Vec<u8>)> {
+        let (width, height) = self.texture_size;
+        if width == 0 || height == 0 {
+            return Ok(((width, height), Vec::new()));
+        }
+
+        let unpadded_bytes_per_row = width * 4;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Slice Readback Buffer"),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            size: (padded_bytes_per_row * height) as _,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
         );
+        queue.submit(Some(encoder.finish()));
+
+        map_read(device, &buffer)?;
+
+        let data = buffer
+            .get_mapped_range(..)
+            .chunks(padded_bytes_per_row as _)
+            .rev()
+            .flat_map(|row| row[..unpadded_bytes_per_row as usize].iter().copied())
+            .collect();
+        buffer.unmap();
+
+        Ok(((width, height), data))
+    }
+
+    /// Saves the colored slice to `path` as a PNG image at the resolution of the texture.
+    pub fn export_png(&self, device: &Device, queue: &Queue, path: &Path) -> Result<()> {
+        let ((width, height), data) = self.read_texture(device, queue)?;
+        image::save_buffer_with_format(
+            path,
+            &data,
+            width,
+            height,
+            image::ExtendedColorType::Rgba8,
+            image::ImageFormat::Png,
+        )?;
+        Ok(())
+    }
+
+    /// Reads back the complex pressure in Pa of the texel at `uv`, the position on the slice from
+    /// its bottom left corner in units of its size. This waits for the GPU, so it is only meant
+    /// to be called on demand. `None` if `uv` is off the slice.
+    pub fn probe(&self, uv: Vector2, device: &Device, queue: &Queue) -> Result<Option<Vector2>> {
+        let (width, height) = self.texture_size;
+        if width == 0 || height == 0 || !(0.0..=1.0).contains(&uv.x) || !(0.0..=1.0).contains(&uv.y)
+        {
+            return Ok(None);
+        }
+        let x = ((uv.x * width as f32) as u32).min(width - 1);
+        let y = ((uv.y * height as f32) as u32).min(height - 1);
+        let i = (y * width + x) as usize;
+        let [re, im, _, _] = self.read_field(device, queue, i..i + 1)?[0];
+        Ok(Some(Vector2::new(re, im)))
     }
 
-    pub fn render(&mut self, pass: &mut RenderPass) {
-        pass.set_pipeline(&self.pipeline);
+    /// Reads back the displayed pressure in Pa (RMS if enabled) of each texel, row by row from
+    /// the bottom. Like the slice, this is the field last computed, i.e. the reference if shown.
+    fn read_pressure(&self, device: &Device, queue: &Queue, state: &State) -> Result<Vec<f32>> {
+        let (width, height) = self.texture_size;
+        let magnitude = |re: f32, im: f32| {
+            let p = re.hypot(im);
+            if state.slice.rms {
+                p * std::f32::consts::FRAC_1_SQRT_2
+            } else {
+                p
+            }
+        };
+        Ok(self
+            .read_field(device, queue, 0..(width * height) as usize)?
+            .into_iter()
+            .map(|[re, im, re_m, im_m]| {
+                if state.slice.mirror {
+                    (magnitude(re, im) - magnitude(re_m, im_m)).abs()
+                } else {
+                    magnitude(re, im)
+                }
+            })
+            .collect())
+    }
+
+    /// Writes the displayed pressure in Pa of each texel to `path` as CSV, with the position of
+    /// the texel on the slice plane in mm from its center, row by row from the bottom.
+    pub fn export_field(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        state: &State,
+        path: &Path,
+    ) -> Result<()> {
+        let (width, height) = self.texture_size;
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "x,y,p")?;
+        self.read_pressure(device, queue, state)?
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, p)| {
+                let x = ((i as u32 % width) as f32 / width as f32 - 0.5) * state.slice.size.x / mm;
+                let y = ((i as u32 / width) as f32 / height as f32 - 0.5) * state.slice.size.y / mm;
+                writeln!(writer, "{x},{y},{p}")
+            })?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the displayed pressure in Pa of each texel to `path` as a NumPy array with one row
+    /// per row of texels, top row first like the exported images.
+    pub fn export_npy(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        state: &State,
+        path: &Path,
+    ) -> Result<()> {
+        let (width, height) = self.texture_size;
+        let pressure = self.read_pressure(device, queue, state)?;
+        let data = pressure
+            .chunks_exact(width.max(1) as usize)
+            .rev()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        save_npy(path, (height, width), &data)
+    }
+
+    /// Reads back the complex pressure of the texels in `range`, each followed by that at the
+    /// mirrored point, into a staging buffer allocated for this read only.
+    fn read_field(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        range: Range<usize>,
+    ) -> Result<Vec<[f32; 4]>> {
+        let size = range.len() as u64 * FIELD_TEXEL_SIZE;
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Slice Field Readback Buffer"),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            size,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(
+            &self.field_buf,
+            range.start as u64 * FIELD_TEXEL_SIZE,
+            &buffer,
+            0,
+            size,
+        );
+        queue.submit(Some(encoder.finish()));
+
+        map_read(device, &buffer)?;
+
+        let data = bytemuck::pod_collect_to_vec(&buffer.get_mapped_range(..));
+        buffer.unmap();
+        Ok(data)
+    }
+
+    /// Computes the slice, accumulating the field in f16 if `half_precision` is set and supported.
+    ///
+    /// The field is only computed again if it has changed since the last call, while the texture
+    /// is colored from it every time to apply the display settings and persistence.
+    pub fn compute(&mut self, pass: &mut ComputePass, show_reference: bool, half_precision: bool) {
+        let (bind_group, show_reference) = match &self.reference_bind_group {
+            Some(reference_bind_group) if show_reference => (reference_bind_group, true),
+            _ => (self.bind_group.as_ref().unwrap(), false),
+        };
+        let (pipeline, half_precision) = match &self.compute_pipeline_f16 {
+            Some(pipeline) if half_precision => (pipeline, true),
+            _ => (&self.compute_pipeline, false),
+        };
+        let workgroups = (
+            self.texture_size.0.div_ceil(WORKGROUP_SIZE.0),
+            self.texture_size.1.div_ceil(WORKGROUP_SIZE.1),
+        );
+        pass.set_bind_group(0, bind_group, &[]);
+        if self.field_dirty || self.field_source != (show_reference, half_precision) {
+            pass.set_pipeline(pipeline);
+            pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+            self.field_dirty = false;
+            self.field_source = (show_reference, half_precision);
+        }
+        pass.set_pipeline(&self.colorize_pipeline);
+        pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+    }
+
+    /// Draws the slice, into a render pass with a depth buffer if `depth` is set.
+    pub fn render(&mut self, pass: &mut RenderPass, depth: bool) {
+        pass.set_pipeline(if depth {
+            &self.pipeline
+        } else {
+            &self.pipeline_no_depth
+        });
         pass.set_bind_group(0, self.bind_group.as_ref().unwrap(), &[]);
         pass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint16);
         pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
         pass.draw_indexed(0..self.index_count as u32, 0, 0..1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        renderer::test_util::{emulator, gpu, target},
+        state::DepthFormat,
+    };
+
+    fn renderer(
+        device: &Device,
+        queue: &Queue,
+        state: &State,
+        emulator: &EmulatorWrapper,
+    ) -> SliceRenderer {
+        let mut renderer = SliceRenderer::new(
+            device,
+            &target(wgpu::TextureFormat::Rgba8Unorm, 1, 1),
+            super::super::depth_texture::texture_format(DepthFormat::Depth32Float),
+        );
+        renderer.initialize(device, emulator);
+        renderer.update_trans_pos(emulator, queue);
+        renderer.update_trans_state(emulator, queue);
+        renderer.update_config(state, emulator, queue);
+        renderer.update_slice(state, device, queue);
+        renderer.update_color_map(state, queue);
+        renderer
+    }
+
+    /// Computes the slice and reads back its field.
+    fn compute(
+        renderer: &mut SliceRenderer,
+        device: &Device,
+        queue: &Queue,
+        half_precision: bool,
+    ) -> Vec<[f32; 4]> {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: None,
+                timestamp_writes: None,
+            });
+            renderer.compute(&mut pass, false, half_precision);
+        }
+        queue.submit(Some(encoder.finish()));
+        let (width, height) = renderer.texture_size();
+        renderer
+            .read_field(device, queue, 0..(width * height) as usize)
+            .unwrap()
+    }
+
+    #[test]
+    fn field_matches_cpu() {
+        let Some((device, queue)) = gpu() else {
+            eprintln!("No GPU adapter available, skipping");
+            return;
+        };
+        let state = State::default();
+        let emulator = emulator(&state);
+        let mut renderer = renderer(&device, &queue, &state, &emulator);
+        let field = compute(&mut renderer, &device, &queue, false);
+
+        let (width, height) = renderer.texture_size();
+        let rotation = state.slice.rotation();
+        let peak = emulator
+            .transducers()
+            .pressure_at(state.slice.pos, state.sound_speed);
+        // Away from the near field at the bottom edge of the slice
+        [0, width / 4, width / 2, 3 * width / 4, width - 1]
+            .into_iter()
+            .flat_map(|x| [height / 4, height / 2, 3 * height / 4, height - 1].map(|y| (x, y)))
+            .for_each(|(x, y)| {
+                let local = Vector3::new(
+                    (x as f32 / width as f32 - 0.5) * state.slice.size.x,
+                    (y as f32 / height as f32 - 0.5) * state.slice.size.y,
+                    0.,
+                );
+                let expected = emulator
+                    .transducers()
+                    .field_at(state.slice.pos + rotation * local, state.sound_speed);
+                let [re, im, _, _] = field[(y * width + x) as usize];
+                let error = Vector2::new(re, im).distance(expected);
+                assert!(
+                    error <= 1e-3 * peak,
+                    "texel ({x}, {y}): {re} + {im}i, expected {expected}"
+                );
+            });
+    }
+
+    #[test]
+    fn f16_matches_f32() {
+        let Some((device, queue)) = gpu() else {
+            eprintln!("No GPU adapter available, skipping");
+            return;
+        };
+        let state = State::default();
+        let emulator = emulator(&state);
+        let mut renderer = renderer(&device, &queue, &state, &emulator);
+        if renderer.compute_pipeline_f16.is_none() {
+            eprintln!("No f16 support, skipping");
+            return;
+        }
+        let full = compute(&mut renderer, &device, &queue, false);
+        let half = compute(&mut renderer, &device, &queue, true);
+
+        let peak = emulator
+            .transducers()
+            .pressure_at(state.slice.pos, state.sound_speed);
+        full.iter().zip(&half).enumerate().for_each(
+            |(i, (&[re, im, _, _], &[re_h, im_h, _, _]))| {
+                let error = Vector2::new(re, im).distance(Vector2::new(re_h, im_h));
+                assert!(
+                    error <= 1e-2 * peak,
+                    "texel {i}: {re_h} + {im_h}i in f16, {re} + {im}i in f32"
+                );
+            },
+        );
+    }
+}
@@ -32,6 +32,19 @@ struct Config {
     num_trans: u32,
     max_pressure: f32,
     scale: f32,
+    mirror_pos: vec4<f32>,
+    mirror_normal: vec4<f32>,
+    mirror: u32,
+    color_gamma: f32,
+    rms: u32,
+    persistence: f32,
+    split: u32,
+    split_max_pressure: f32,
+    srgb_correct: u32,
+    phase_offset: f32,
+    quantity: u32,
+    log_scale: u32,
+    min_pressure: f32,
 }
 
 @group(0)
@@ -42,6 +55,22 @@ var<uniform> config: Config;
 @binding(7)
 var color_map: texture_1d<f32>;
 
+// Complex pressure in Pa at each texel, row by row, as `vec4(acc, acc_mirrored)` with that at the mirrored point.
+@group(0)
+@binding(8)
+var<storage, read_write> field: array<vec4<f32>>;
+
+// Same as `MAX_CONTOURS` in the state, packed four to a vector for the uniform layout.
+struct Contours {
+    levels: array<vec4<f32>, 4>,
+    count: u32,
+    width: f32,
+}
+
+@group(0)
+@binding(9)
+var<uniform> contours: Contours;
+
 @vertex
 fn vs_main(
     @location(0) position: vec4<f32>,
@@ -58,30 +87,115 @@ fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
     var coord = vertex.tex_coord;
     coord.x *= slice_size.x;
     coord.y *= slice_size.y;
-    return textureLoad(texture, vec2<i32>(coord));
+    let color = textureLoad(texture, vec2<i32>(coord));
+    if config.srgb_correct != 0u {
+        return vec4(srgb_to_linear(color.rgb), color.a);
+    }
+    return color;
+}
+
+// The texture holds sRGB encoded colors, as the color maps and exported images do,
+// while the surface encodes the output of the fragment shader into sRGB once more.
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3(2.4)), c / 12.92, c <= vec3(0.04045));
 }
 
 const ULTRASOUND_FREQ: f32 = 40000;
 const COLOR_MAP_TEXTURE_SIZE: f32 = 256;
 
 const PI: f32 = radians(180.0);
+const FRAC_1_SQRT_2: f32 = 0.70710678;
+// Same as `SPL_REFERENCE` and `SPL_RANGE` in the state
+const P_REF: f32 = 20e-6; // [Pa]
+const SPL_RANGE: f32 = 60.0;
+const MIN_COLOR_MAP_RANGE: f32 = 1e-6;
+
+// Values of `config.quantity`, in the order of `FieldQuantity`.
+const QUANTITY_PHASE: u32 = 1u;
+const QUANTITY_INTENSITY: u32 = 2u;
+const QUANTITY_SPL: u32 = 3u;
+const QUANTITY_PHASE_PRESSURE: u32 = 4u;
 const T4010A1_AMPLITUDE: f32 = 55114.85; // [Pa*mm]
 const P0: f32 = T4010A1_AMPLITUDE / (4. * PI);
 
-fn coloring(t: f32) -> vec4<f32> {
-    return textureLoad(color_map, u32(clamp(t, 0.0, 1.0) * COLOR_MAP_TEXTURE_SIZE), 0);
+// The texture holds the color map of the left half followed by that of the right half of the split view.
+fn coloring(t: f32, right: bool) -> vec4<f32> {
+    var i = min(u32(clamp(t, 0.0, 1.0) * COLOR_MAP_TEXTURE_SIZE), u32(COLOR_MAP_TEXTURE_SIZE) - 1u);
+    if right {
+        i += u32(COLOR_MAP_TEXTURE_SIZE);
+    }
+    return textureLoad(color_map, i, 0);
+}
+
+// WGSL has no base 10 logarithm.
+fn log10(x: f32) -> f32 {
+    return log2(x) / log2(10.0);
+}
+
+// `x` from `lo` to `hi`, on the logarithmic scale if `log_scale` is set, clamped to [0, 1].
+// The range is kept from rounding to zero, which `lo` being clamped below `hi` does not prevent.
+fn normalize(x: f32, lo: f32, hi: f32) -> f32 {
+    if config.log_scale != 0u {
+        let l = log10(1.0 + lo);
+        return clamp((log10(1.0 + x) - l) / max(log10(1.0 + hi) - l, MIN_COLOR_MAP_RANGE), 0.0, 1.0);
+    }
+    return clamp((x - lo) / max(hi - lo, MIN_COLOR_MAP_RANGE), 0.0, 1.0);
+}
+
+// Phase of the field in turns, in the convention of the transducer colors.
+fn phase_turns(acc: vec2<f32>) -> f32 {
+    return fract((-atan2(acc.y, acc.x) - config.phase_offset) / (2.0 * PI));
+}
+
+// Hue is the phase of the field, matching the transducer colors, and value is `v`.
+fn phase_coloring(acc: vec2<f32>, v: f32) -> vec4<f32> {
+    let h = phase_turns(acc);
+    let k = (vec3(5.0, 3.0, 1.0) + h * 6.0) % 6.0;
+    let rgb = v - v * clamp(min(k, 4.0 - k), vec3(0.0), vec3(1.0));
+    return vec4(rgb, 1.0);
+}
+
+// Precision of the accumulated field. The half precision pipeline replaces this with f16,
+// so the field is accumulated in units of `FIELD_UNIT` to stay well within the f16 range.
+alias acc_t = f32;
+const FIELD_UNIT: f32 = 1e3; // [Pa]
+
+// Transducers are processed in tiles of one workgroup size, which are loaded cooperatively into workgroup memory.
+const TILE_SIZE: u32 = 64;
+
+// RMS of the instantaneous pressure if `rms` is set, which is the amplitude over √2 for a single
+// frequency, or the amplitude otherwise.
+fn magnitude(acc: vec2<f32>) -> f32 {
+    if config.rms != 0u {
+        return length(acc) * FRAC_1_SQRT_2;
+    }
+    return length(acc);
+}
+
+var<workgroup> tile_pos: array<vec3<f32>, TILE_SIZE>;
+var<workgroup> tile_state: array<vec4<f32>, TILE_SIZE>;
+
+fn accumulate(acc: vec2<acc_t>, point: vec3<f32>, tr_pos: vec3<f32>, tr_state: vec4<f32>, wavenum: f32) -> vec2<acc_t> {
+    let r = distance(tr_pos, point);
+
+    let amp = tr_state.x;
+    let phase = tr_state.y;
+    let en = tr_state.z;
+
+    let p = -phase - wavenum * r;
+    let a = en * P0 * amp / (r * FIELD_UNIT);
+    return acc + vec2<acc_t>(vec2(a * cos(p), a * sin(p)));
 }
 
+// Computes the complex pressure of the slice. The displayed quantity is derived from it in `colorize`,
+// so this only needs to run again when the field itself changes.
 @compute
 @workgroup_size(8, 8, 1)
-fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+fn compute_field(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) local_idx: u32) {
     let id_x = i32(id.x);
     let id_y = i32(id.y);
     let width = i32(slice_size.x);
     let height = i32(slice_size.y);
-    if id_x >= width || id_y >= height {
-        return;
-    }
 
     let x = f32(id.x) / slice_size.x - 0.5;
     let y = f32(id.y) / slice_size.y - 0.5;
@@ -91,22 +205,118 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
         0.0, 0.0, 0.0, 1.0);
     let point = (model * vec4(x, y, 0.0, 1.0) * s).xyz;
 
+    let o = config.mirror_pos.xyz * config.scale;
+    let n = config.mirror_normal.xyz;
+    let mirrored = point - 2.0 * dot(point - o, n) * n;
+
     let wavenum = 2 * PI * ULTRASOUND_FREQ / (config.sound_speed * config.scale);
 
-    var re: f32 = 0.;
-    var im: f32 = 0.;
-    for (var i: u32 = 0; i < config.num_trans; i++) {
-        let r = distance(v_tr_pos[i] * config.scale, point);
+    // Every invocation takes part in loading tiles, so out-of-range texels return only after the loop.
+    var acc_n = vec2<acc_t>(0.);
+    var acc_mirrored_n = vec2<acc_t>(0.);
+    for (var base: u32 = 0; base < config.num_trans; base += TILE_SIZE) {
+        let i = base + local_idx;
+        if i < config.num_trans {
+            tile_pos[local_idx] = v_tr_pos[i] * config.scale;
+            tile_state[local_idx] = v_tr_state[i];
+        }
+        workgroupBarrier();
+
+        let len = min(TILE_SIZE, config.num_trans - base);
+        for (var j: u32 = 0; j < len; j++) {
+            acc_n = accumulate(acc_n, point, tile_pos[j], tile_state[j], wavenum);
+            if config.mirror != 0u {
+                acc_mirrored_n = accumulate(acc_mirrored_n, mirrored, tile_pos[j], tile_state[j], wavenum);
+            }
+        }
+        workgroupBarrier();
+    }
+
+    if id_x >= width || id_y >= height {
+        return;
+    }
+
+    let acc = vec2<f32>(acc_n) * FIELD_UNIT;
+    let acc_mirrored = vec2<f32>(acc_mirrored_n) * FIELD_UNIT;
+    field[id.y * u32(width) + id.x] = vec4(acc, acc_mirrored);
+}
 
-        let amp = v_tr_state[i].x;
-        let phase = v_tr_state[i].y;
-        let en = v_tr_state[i].z;
+// Displayed pressure at the texel (x, y), clamped to the edges of the slice.
+fn pressure_at(x: i32, y: i32) -> f32 {
+    let width = i32(slice_size.x);
+    let height = i32(slice_size.y);
+    let f = field[clamp(y, 0, height - 1) * width + clamp(x, 0, width - 1)];
+    var c = magnitude(f.xy);
+    if config.mirror != 0u {
+        c = abs(c - magnitude(f.zw));
+    }
+    return c;
+}
 
-        let p = -phase - wavenum * r;
-        let a = en * P0 * amp / r;
-        re += a * cos(p);
-        im += a * sin(p);
+// Coverage of the contour lines at the texel (x, y) with pressure `c`. The distance to each level
+// is estimated in texels from the gradient over the neighboring texels, which keeps the lines
+// of even width and free of aliasing.
+fn contour_alpha(x: i32, y: i32, c: f32) -> f32 {
+    let grad = 0.5 * vec2(
+        pressure_at(x + 1, y) - pressure_at(x - 1, y),
+        pressure_at(x, y + 1) - pressure_at(x, y - 1),
+    );
+    let g = max(length(grad), 1e-6);
+    var alpha = 0.0;
+    for (var i: u32 = 0; i < contours.count; i++) {
+        let d = abs(c - contours.levels[i / 4u][i % 4u]) / g;
+        alpha = max(alpha, clamp(0.5 * contours.width + 0.5 - d, 0.0, 1.0));
+    }
+    return alpha;
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn colorize(@builtin(global_invocation_id) id: vec3<u32>) {
+    let id_x = i32(id.x);
+    let id_y = i32(id.y);
+    let width = i32(slice_size.x);
+    let height = i32(slice_size.y);
+    if id_x >= width || id_y >= height {
+        return;
+    }
+
+    let acc = field[id.y * u32(width) + id.x].xy;
+    let c = pressure_at(id_x, id_y);
+    let right = config.split != 0u && id_x >= width / 2;
+    var max_pressure = config.max_pressure;
+    if right {
+        max_pressure = config.split_max_pressure;
+    }
+    let min_pressure = config.min_pressure;
+    let v = pow(normalize(c, min_pressure, max_pressure), config.color_gamma);
+    var t = v;
+    switch config.quantity {
+        case QUANTITY_PHASE: {
+            t = phase_turns(acc);
+        }
+        case QUANTITY_INTENSITY: {
+            t = pow(
+                normalize(c * c, min_pressure * min_pressure, max_pressure * max_pressure),
+                config.color_gamma,
+            );
+        }
+        case QUANTITY_SPL: {
+            let spl = 20.0 * log10(max(c, P_REF) / P_REF);
+            let spl_max = 20.0 * log10(max(max_pressure, P_REF) / P_REF);
+            t = pow(clamp(1.0 - (spl_max - spl) / SPL_RANGE, 0.0, 1.0), config.color_gamma);
+        }
+        default: {}
+    }
+    var color = coloring(t, right);
+    if config.quantity == QUANTITY_PHASE_PRESSURE {
+        color = phase_coloring(acc, v);
+    }
+    if config.persistence > 0.0 {
+        color = mix(color, textureLoad(texture, vec2(id_x, id_y)), config.persistence);
+    }
+    if contours.count > 0u {
+        color = mix(color, vec4(0.0, 0.0, 0.0, 1.0), contour_alpha(id_x, id_y, c));
     }
-    let c = sqrt(re * re + im * im) / config.max_pressure;
-    textureStore(texture, vec2(id_x, id_y), coloring(c));
+    textureStore(texture, vec2(id_x, id_y), color);
 }
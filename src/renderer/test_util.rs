@@ -0,0 +1,47 @@
+use std::sync::{Arc, RwLock};
+
+use autd3_core::devices::AUTD3;
+use autd3_driver::{common::ULTRASOUND_FREQ, geometry::Geometry};
+
+use crate::{State, common::transform::to_gl_pos, emulator::EmulatorWrapper, executor::block_on};
+
+/// Device of the default adapter, or `None` if the machine has no GPU to run the tests on.
+pub fn gpu() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle());
+    let adapter = block_on(instance.request_adapter(&Default::default())).ok()?;
+    block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+        required_features: adapter.features() & wgpu::Features::SHADER_F16,
+        ..Default::default()
+    }))
+    .ok()
+}
+
+/// A single device focusing on the center of the slice.
+pub fn emulator(state: &State) -> EmulatorWrapper {
+    let mut emulator = EmulatorWrapper::new(Arc::new(RwLock::new(Vec::new())));
+    emulator.initialize(&Geometry::new(vec![AUTD3::default().into()]));
+    let focus = to_gl_pos(state.slice.pos);
+    let wavenum = 2. * std::f32::consts::PI * ULTRASOUND_FREQ.hz() as f32 / state.sound_speed;
+    let positions = emulator.transducers().positions().to_vec();
+    emulator.iter_mut().for_each(|e| {
+        e.transducers.iter_mut().zip(&positions).for_each(|(s, p)| {
+            s.amp = 1.;
+            s.phase = -wavenum * p.truncate().distance(focus);
+        })
+    });
+    emulator
+}
+
+/// Target of `width` x `height` in `format`, as the window surface would be configured.
+pub fn target(format: wgpu::TextureFormat, width: u32, height: u32) -> wgpu::SurfaceConfiguration {
+    wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format,
+        width,
+        height,
+        present_mode: wgpu::PresentMode::Fifo,
+        desired_maximum_frame_latency: 2,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![format],
+    }
+}
@@ -0,0 +1,39 @@
+struct VertexOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(position) position: vec4<f32>,
+};
+
+@group(0)
+@binding(0)
+var<uniform> proj_view: mat4x4<f32>;
+
+@vertex
+fn vs_main(
+    @location(0) position: vec4<f32>,
+    @location(2) model_matrix_0: vec4<f32>,
+    @location(3) model_matrix_1: vec4<f32>,
+    @location(4) model_matrix_2: vec4<f32>,
+    @location(5) model_matrix_3: vec4<f32>,
+    @location(6) color: vec4<f32>,
+    @location(7) phase: f32,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        model_matrix_0,
+        model_matrix_1,
+        model_matrix_2,
+        model_matrix_3,
+    );
+    // Rotate the arrow in the transducer plane so that it points in the direction of the phase
+    let c = cos(phase);
+    let s = sin(phase);
+    let p = vec4(c * position.x - s * position.y, s * position.x + c * position.y, position.z, position.w);
+    var result: VertexOutput;
+    result.position = proj_view * model_matrix * p;
+    result.color = color;
+    return result;
+}
+
+@fragment
+fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vertex.color;
+}
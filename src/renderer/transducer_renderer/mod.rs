@@ -2,28 +2,46 @@ use autd3_core::devices::AUTD3;
 use bytemuck::{Pod, Zeroable};
 use egui_wgpu::wgpu;
 use image::{ImageBuffer, Rgba};
-use std::{borrow::Cow, f32::consts::PI, mem};
+use std::{borrow::Cow, f32::consts::PI, mem, ops::Range, time::Instant};
 use wgpu::{Device, Queue, RenderPass, SurfaceConfiguration, util::DeviceExt};
 
 use crate::{
     Matrix4, Vector3, Vector4,
-    common::color::{Color, Hsv},
-    emulator::EmulatorWrapper,
+    common::color::{Color, Hsv, srgb_to_linear},
+    emulator::{EmulatorWrapper, TransState},
     error::Result,
+    state::{MARKER_RESOLUTION_RANGE, State},
 };
 
-use super::DepthTexture;
+use super::depth_texture::depth_stencil_state;
 
 pub struct TransducerRenderer {
     vertex_buf: wgpu::Buffer,
     index_buf: wgpu::Buffer,
+    arrow_vertex_buf: wgpu::Buffer,
     model_instance_buf: Option<wgpu::Buffer>,
     color_instance_buf: Option<wgpu::Buffer>,
+    phase_instance_buf: Option<wgpu::Buffer>,
     proj_view_buf: wgpu::Buffer,
     index_count: usize,
+    arrow_vertex_count: u32,
     instance_count: u32,
+    centers: Vec<Vector3>,
+    proj_view: Matrix4,
+    culling: bool,
+    draw_ranges: Vec<Range<u32>>,
+    /// Displayed alpha of each transducer, which follows the one in the transducer states.
+    alpha: Vec<f32>,
+    last_fade: Option<Instant>,
+    /// Transducer states after the last sent frame.
+    sent_states: Vec<TransState>,
+    /// Whether the phase or amplitude of each transducer changed with the last sent frame.
+    changed: Vec<bool>,
+    /// Whether the render target encodes its input into sRGB.
+    srgb_target: bool,
     bind_group: wgpu::BindGroup,
     pipeline: wgpu::RenderPipeline,
+    arrow_pipeline: wgpu::RenderPipeline,
 }
 
 #[repr(C)]
@@ -33,7 +51,7 @@ struct Vertex {
     _tex_coord: [f32; 2],
 }
 
-fn vertex(pos: [f32; 3], tc: [i16; 2]) -> Vertex {
+fn vertex(pos: [f32; 3], tc: [i8; 2]) -> Vertex {
     Vertex {
         _pos: [pos[0], pos[1], pos[2], 1.0],
         _tex_coord: [tc[0] as f32, tc[1] as f32],
@@ -43,9 +61,9 @@ fn vertex(pos: [f32; 3], tc: [i16; 2]) -> Vertex {
 fn create_vertices() -> (Vec<Vertex>, Vec<u16>) {
     let vertex_data = [
         vertex([-0.5, -0.5, 0.], [0, 0]),
-        vertex([0.5, -0.5, 0.], [128, 0]),
-        vertex([0.5, 0.5, 0.], [128, 128]),
-        vertex([-0.5, 0.5, 0.], [0, 128]),
+        vertex([0.5, -0.5, 0.], [1, 0]),
+        vertex([0.5, 0.5, 0.], [1, 1]),
+        vertex([-0.5, 0.5, 0.], [0, 1]),
     ];
 
     let index_data: &[u16] = &[0, 1, 2, 2, 3, 0];
@@ -53,8 +71,84 @@ fn create_vertices() -> (Vec<Vertex>, Vec<u16>) {
     (vertex_data.to_vec(), index_data.to_vec())
 }
 
+// Arrow pointing in +x, drawn as a triangle list
+fn create_arrow_vertices() -> Vec<Vertex> {
+    const SHAFT_START: f32 = -0.4;
+    const SHAFT_END: f32 = 0.1;
+    const SHAFT_WIDTH: f32 = 0.06;
+    const HEAD_END: f32 = 0.45;
+    const HEAD_WIDTH: f32 = 0.18;
+    [
+        [SHAFT_START, -SHAFT_WIDTH],
+        [SHAFT_END, -SHAFT_WIDTH],
+        [SHAFT_END, SHAFT_WIDTH],
+        [SHAFT_END, SHAFT_WIDTH],
+        [SHAFT_START, SHAFT_WIDTH],
+        [SHAFT_START, -SHAFT_WIDTH],
+        [SHAFT_END, -HEAD_WIDTH],
+        [HEAD_END, 0.],
+        [SHAFT_END, HEAD_WIDTH],
+    ]
+    .into_iter()
+    .map(|[x, y]| vertex([x, y, 0.], [0, 0]))
+    .collect()
+}
+
+fn create_pipeline(
+    device: &Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    vertex_buffers: &[wgpu::VertexBufferLayout],
+    surface_config: &SurfaceConfiguration,
+    depth_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: None,
+            compilation_options: Default::default(),
+            buffers: vertex_buffers,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: None,
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: surface_config.view_formats[0],
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::OVER,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(depth_stencil_state(depth_format)),
+        multisample: wgpu::MultisampleState::default(),
+        cache: None,
+        multiview_mask: None,
+    })
+}
+
 #[allow(clippy::type_complexity)]
-fn create_texels() -> Result<((u32, u32), ImageBuffer<Rgba<u8>, Vec<u8>>)> {
+fn create_texels(state: &State) -> Result<((u32, u32), ImageBuffer<Rgba<u8>, Vec<u8>>)> {
+    if let Some(resolution) = state.marker_resolution {
+        let resolution = resolution.clamp(
+            *MARKER_RESOLUTION_RANGE.start(),
+            *MARKER_RESOLUTION_RANGE.end(),
+        );
+        return Ok(((resolution, resolution), create_circle_texels(resolution)));
+    }
+
     let diffuse_bytes = include_bytes!("circle.png");
     let diffuse_image = image::load_from_memory(diffuse_bytes)?;
     let diffuse_rgba = diffuse_image.to_rgba8();
@@ -65,16 +159,42 @@ fn create_texels() -> Result<((u32, u32), ImageBuffer<Rgba<u8>, Vec<u8>>)> {
     Ok((dimensions, diffuse_rgba))
 }
 
-fn coloring_hsv(h: f32, v: f32, a: f32) -> [f32; 4] {
-    let hsv = Hsv { h, s: 1., v, a };
+fn create_circle_texels(resolution: u32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let radius = resolution as f32 / 2.;
+    ImageBuffer::from_fn(resolution, resolution, |x, y| {
+        let dx = x as f32 + 0.5 - radius;
+        let dy = y as f32 + 0.5 - radius;
+        // Signed distance to the circle edge, anti-aliased over one texel
+        let d = (dx * dx + dy * dy).sqrt() - radius;
+        let alpha = (0.5 - d).clamp(0., 1.);
+        Rgba([255, 255, 255, (alpha * 255.).round() as u8])
+    })
+}
+
+fn coloring_hsv(h: f32, s: f32, v: f32, a: f32) -> [f32; 4] {
+    let hsv = Hsv { h, s, v, a };
     hsv.rgba()
 }
 
+/// sRGB color of a transducer with the given phase, amplitude and alpha.
+pub fn trans_color(state: &State, phase: f32, amp: f32, alpha: f32) -> [f32; 4] {
+    let hue_offset = state.trans_hue_offset / 360.;
+    let phase_offset = state.phase_offset.to_radians();
+    coloring_hsv(
+        ((phase - phase_offset) / (2.0 * PI) + hue_offset).rem_euclid(1.),
+        state.trans_saturation.clamp(0., 1.),
+        amp,
+        alpha,
+    )
+}
+
 impl TransducerRenderer {
     pub fn new(
         device: &Device,
         queue: &Queue,
         surface_config: &SurfaceConfiguration,
+        depth_format: wgpu::TextureFormat,
+        state: &State,
     ) -> Result<Self> {
         let vertex_size = mem::size_of::<Vertex>();
         let (vertex_data, index_data) = create_vertices();
@@ -90,6 +210,13 @@ impl TransducerRenderer {
             usage: wgpu::BufferUsages::INDEX,
         });
 
+        let arrow_vertex_data = create_arrow_vertices();
+        let arrow_vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Arrow Vertex Buffer"),
+            contents: bytemuck::cast_slice(&arrow_vertex_data),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
             entries: &[
@@ -121,7 +248,7 @@ impl TransducerRenderer {
             immediate_size: 0,
         });
 
-        let ((width, height), texels) = create_texels()?;
+        let ((width, height), texels) = create_texels(state)?;
         let texture_extent = wgpu::Extent3d {
             width,
             height,
@@ -230,58 +357,65 @@ impl TransducerRenderer {
             },
         ];
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        let pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            &vertex_buffers,
+            surface_config,
+            depth_format,
+        );
+
+        let arrow_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: None,
-                compilation_options: Default::default(),
-                buffers: &vertex_buffers,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: None,
-                compilation_options: Default::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_config.view_formats[0],
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent::OVER,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                cull_mode: None,
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: DepthTexture::DEPTH_FORMAT,
-                depth_write_enabled: Some(true),
-                depth_compare: Some(wgpu::CompareFunction::Less),
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            cache: None,
-            multiview_mask: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("arrow.wgsl"))),
         });
+        let [vertex_layout, model_layout, color_layout] = vertex_buffers;
+        let arrow_pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &arrow_shader,
+            &[
+                vertex_layout,
+                model_layout,
+                color_layout,
+                wgpu::VertexBufferLayout {
+                    array_stride: size_of::<f32>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &[wgpu::VertexAttribute {
+                        offset: 0,
+                        shader_location: 7,
+                        format: wgpu::VertexFormat::Float32,
+                    }],
+                },
+            ],
+            surface_config,
+            depth_format,
+        );
 
         Ok(Self {
             vertex_buf,
             index_buf,
+            arrow_vertex_buf,
             index_count: index_data.len(),
+            arrow_vertex_count: arrow_vertex_data.len() as _,
             model_instance_buf: None,
             color_instance_buf: None,
+            phase_instance_buf: None,
             instance_count: 0,
+            centers: Vec::new(),
+            proj_view: Matrix4::IDENTITY,
+            culling: false,
+            draw_ranges: Vec::new(),
+            srgb_target: surface_config.view_formats[0].is_srgb(),
+            alpha: Vec::new(),
+            last_fade: None,
+            sent_states: Vec::new(),
+            changed: Vec::new(),
             bind_group,
             proj_view_buf,
             pipeline,
+            arrow_pipeline,
         })
     }
 
@@ -291,6 +425,51 @@ impl TransducerRenderer {
             0,
             bytemuck::cast_slice(proj_view.as_ref()),
         );
+        self.proj_view = proj_view;
+        self.cull();
+    }
+
+    pub fn set_culling(&mut self, culling: bool) {
+        self.culling = culling;
+    }
+
+    // Instances are not reordered, so the visible ones are drawn as runs of consecutive instances.
+    // This costs a bounding sphere test per transducer on every camera change and a draw call per run,
+    // which only pays off when most of a large array is off screen.
+    fn cull(&mut self) {
+        self.draw_ranges.clear();
+        if !self.culling || self.centers.len() != self.instance_count as usize {
+            self.draw_ranges.push(0..self.instance_count);
+            return;
+        }
+
+        let m = self.proj_view;
+        let planes = [
+            m.row(3) + m.row(0),
+            m.row(3) - m.row(0),
+            m.row(3) + m.row(1),
+            m.row(3) - m.row(1),
+            m.row(2),
+            m.row(3) - m.row(2),
+        ]
+        .map(|p| p / p.truncate().length());
+        let radius = AUTD3::TRANS_SPACING * std::f32::consts::FRAC_1_SQRT_2;
+
+        let mut start = None;
+        self.centers.iter().enumerate().for_each(|(i, c)| {
+            let visible = planes.iter().all(|p| p.truncate().dot(*c) + p.w >= -radius);
+            match (visible, start) {
+                (true, None) => start = Some(i as u32),
+                (false, Some(s)) => {
+                    self.draw_ranges.push(s..i as u32);
+                    start = None;
+                }
+                _ => {}
+            }
+        });
+        if let Some(s) = start {
+            self.draw_ranges.push(s..self.instance_count);
+        }
     }
 
     pub fn resize(&mut self, proj_view: Matrix4, queue: &Queue) {
@@ -304,7 +483,21 @@ impl TransducerRenderer {
         pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
         pass.set_vertex_buffer(1, self.model_instance_buf.as_ref().unwrap().slice(..));
         pass.set_vertex_buffer(2, self.color_instance_buf.as_ref().unwrap().slice(..));
-        pass.draw_indexed(0..self.index_count as u32, 0, 0..self.instance_count);
+        self.draw_ranges.iter().for_each(|instances| {
+            pass.draw_indexed(0..self.index_count as u32, 0, instances.clone());
+        });
+    }
+
+    pub fn render_arrows(&mut self, pass: &mut RenderPass) {
+        pass.set_pipeline(&self.arrow_pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.arrow_vertex_buf.slice(..));
+        pass.set_vertex_buffer(1, self.model_instance_buf.as_ref().unwrap().slice(..));
+        pass.set_vertex_buffer(2, self.color_instance_buf.as_ref().unwrap().slice(..));
+        pass.set_vertex_buffer(3, self.phase_instance_buf.as_ref().unwrap().slice(..));
+        self.draw_ranges.iter().for_each(|instances| {
+            pass.draw(0..self.arrow_vertex_count, instances.clone());
+        });
     }
 
     pub fn initialize(&mut self, device: &Device, emulator: &EmulatorWrapper) {
@@ -321,10 +514,45 @@ impl TransducerRenderer {
             size: (size_of::<Vector4>() * instance_count) as _,
             mapped_at_creation: false,
         }));
+        self.phase_instance_buf = Some(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Phase Instance Buffer"),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            size: (size_of::<f32>() * instance_count) as _,
+            mapped_at_creation: false,
+        }));
         self.instance_count = instance_count as _;
+        self.alpha = emulator
+            .transducers()
+            .states()
+            .iter()
+            .map(|s| s.alpha)
+            .collect();
+        self.last_fade = None;
+        self.centers.clear();
+        self.cull();
+    }
+
+    /// Sizes in bytes of the buffers allocated for the transducers.
+    pub fn buffer_sizes(&self) -> Vec<(&'static str, u64)> {
+        [
+            ("Model instances", &self.model_instance_buf),
+            ("Color instances", &self.color_instance_buf),
+            ("Phase instances", &self.phase_instance_buf),
+        ]
+        .into_iter()
+        .filter_map(|(name, buf)| Some((name, buf.as_ref()?.size())))
+        .collect()
     }
 
     pub fn update_model(&mut self, emulator: &EmulatorWrapper, queue: &Queue) {
+        self.centers = emulator
+            .transducers()
+            .positions()
+            .iter()
+            .map(|p| p.truncate())
+            .collect();
+        self.cull();
+
         let instance_data = emulator
             .transducers()
             .positions()
@@ -346,17 +574,96 @@ impl TransducerRenderer {
         );
     }
 
-    pub fn update_color(&mut self, emulator: &EmulatorWrapper, queue: &Queue) {
+    /// Returns whether the alpha of some transducers is still fading.
+    pub fn update_color(
+        &mut self,
+        state: &State,
+        emulator: &EmulatorWrapper,
+        queue: &Queue,
+    ) -> bool {
+        let fading = self.fade(state.trans_fade_duration, emulator);
+        let phase_offset = state.phase_offset.to_radians();
+        let linear = state.srgb_correct && self.srgb_target;
+        let highlight = state.highlight_changes && self.changed.len() == self.alpha.len();
         let instance_data = emulator
             .transducers()
             .states()
             .iter()
-            .map(|d| coloring_hsv(d.phase / (2.0 * PI), d.amp, d.alpha))
+            .zip(self.alpha.iter())
+            .enumerate()
+            .map(|(i, (d, &alpha))| {
+                let [r, g, b, a] = trans_color(state, d.phase, d.amp, alpha);
+                let [r, g, b] = if highlight && !self.changed[i] {
+                    [0.2126 * r + 0.7152 * g + 0.0722 * b; 3]
+                } else {
+                    [r, g, b]
+                };
+                if linear {
+                    [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]
+                } else {
+                    [r, g, b, a]
+                }
+            })
             .collect::<Vec<_>>();
         queue.write_buffer(
             self.color_instance_buf.as_ref().unwrap(),
             0,
             bytemuck::cast_slice(instance_data.as_ref()),
         );
+
+        let phase_data = emulator
+            .transducers()
+            .states()
+            .iter()
+            .map(|d| d.phase - phase_offset)
+            .collect::<Vec<_>>();
+        queue.write_buffer(
+            self.phase_instance_buf.as_ref().unwrap(),
+            0,
+            bytemuck::cast_slice(phase_data.as_ref()),
+        );
+
+        fading
+    }
+
+    /// Marks the transducers whose phase or amplitude differ from those after the previous sent
+    /// frame, and keeps the current states for the next comparison.
+    pub fn diff_sent(&mut self, emulator: &EmulatorWrapper) {
+        let states = emulator.transducers().states();
+        self.changed = if self.sent_states.len() == states.len() {
+            self.sent_states
+                .iter()
+                .zip(states.iter())
+                .map(|(prev, cur)| prev.phase != cur.phase || prev.amp != cur.amp)
+                .collect()
+        } else {
+            vec![true; states.len()]
+        };
+        self.sent_states = states.to_vec();
+    }
+
+    /// Moves the displayed alpha toward the target by the time elapsed since the last step.
+    fn fade(&mut self, duration: f32, emulator: &EmulatorWrapper) -> bool {
+        let states = emulator.transducers().states();
+        if duration <= 0. || self.alpha.len() != states.len() {
+            self.alpha = states.iter().map(|s| s.alpha).collect();
+            self.last_fade = None;
+            return false;
+        }
+
+        let now = Instant::now();
+        let step = self
+            .last_fade
+            .map_or(0., |last| (now - last).as_secs_f32() / duration);
+        let fading = self
+            .alpha
+            .iter_mut()
+            .zip(states.iter())
+            .fold(false, |fading, (alpha, s)| {
+                *alpha += (s.alpha - *alpha).clamp(-step, step);
+                fading | (*alpha != s.alpha)
+            });
+        self.last_fade = fading.then_some(now);
+        fading
     }
 }
@@ -37,5 +37,7 @@ var r_color: texture_2d<f32>;
 
 @fragment
 fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
-    return vertex.color * textureLoad(r_color, vec2<i32>(vertex.tex_coord), 0);
+    let dims = vec2<f32>(textureDimensions(r_color));
+    let coord = min(vec2<i32>(vertex.tex_coord * dims), vec2<i32>(dims) - 1);
+    return vertex.color * textureLoad(r_color, coord, 0);
 }
@@ -1,93 +1,30 @@
-// # Protocol Specification
-//
-// ## Message Types
-//
-// - `0x01`: Configure Geometry
-// - `0x02`: Update Geometry
-// - `0x03`: Send Data
-// - `0x04`: Read Data
-// - `0x05`: Close
-// - `0x10`: Hello (handshake)
-//
-// ## Response Status Codes
-//
-// - `0x00`: OK
-// - `0xFF`: Error
-//
-// ## Message Formats
-//
-// ### Hello (Handshake)
-// Request:
-// - 1 byte: message type (0x10)
-// - 2 bytes: protocol version (u16, little-endian)
-// - 11 bytes: magic string `AUTD3REMOTE`
-//
-// Response (Success):
-// - 1 byte: status (0x00 = OK)
-//
-// ### Configure/Update Geometry
-// Request:
-// - 1 byte: message type (0x01 or 0x02)
-// - 4 bytes: number of devices (u32, little-endian)
-// - For each device:
-//   - 12 bytes: position (3x f32, little-endian)
-//   - 16 bytes: rotation quaternion (w, i, j, k as f32, little-endian)
-//
-// Response (Success):
-// - 1 byte: status (0x00 = OK)
-//
-// ### Send Data
-// Request:
-// - 1 byte: message type (0x03)
-// - Raw TxMessage data for each device
-//
-// Response (Success):
-// - 1 byte: status (0x00 = OK)
-//
-// ### Read Data
-// Request:
-// - 1 byte: message type (0x04)
-//
-// Response (Success):
-// - 1 byte: status (0x00 = OK)
-// - Raw RxMessage data for each device
-//
-// ### Close
-// Request:
-// - 1 byte: message type (0x05)
-//
-// Response (Success):
-// - 1 byte: status (0x00 = OK)
-//
-// ### Error Response
-// - 1 byte: status (0xFF = Error)
-// - 4 bytes: error message length (u32, little-endian)
-// - N bytes: error message (UTF-8 string)
+// The wire format is documented in `crate::protocol`.
 
-pub(crate) const MSG_CONFIG_GEOMETRY: u8 = 0x01;
-pub(crate) const MSG_UPDATE_GEOMETRY: u8 = 0x02;
-pub(crate) const MSG_SEND_DATA: u8 = 0x03;
-pub(crate) const MSG_READ_DATA: u8 = 0x04;
-pub(crate) const MSG_CLOSE: u8 = 0x05;
-pub(crate) const MSG_HELLO: u8 = 0x10;
-
-pub(crate) const MSG_OK: u8 = 0x00;
-pub(crate) const MSG_ERROR: u8 = 0xFF;
-
-pub(crate) const REMOTE_PROTOCOL_VERSION: u16 = 1;
-pub(crate) const REMOTE_PROTOCOL_MAGIC: &[u8; 11] = b"AUTD3REMOTE";
+// Send Data payloads are read in chunks of this size; each chunk must arrive within the timeout.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
 
 use std::io::{Read, Write};
 use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Receiver;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use autd3_core::link::{RxMessage, TxMessage};
 use autd3_driver::geometry::Geometry;
 use winit::event_loop::EventLoopProxy;
 
-use crate::error::{Result, SimulatorError};
+use crate::error::{ProtocolError, Result, SimulatorError};
 use crate::event::{Signal, UserEvent};
+use crate::log;
+use crate::protocol::{
+    DEVICE_CONFIG_SIZE, MIN_REMOTE_PROTOCOL_VERSION, MSG_CLOSE, MSG_CONFIG_GEOMETRY, MSG_HELLO,
+    MSG_OK, MSG_READ_DATA, MSG_SEND_AND_READ_DATA, MSG_SEND_DATA, MSG_SET_TIME_BASE,
+    MSG_UPDATE_GEOMETRY, REMOTE_PROTOCOL_MAGIC, REMOTE_PROTOCOL_VERSION, RX_MESSAGE_SIZE,
+    TX_MESSAGE_SIZE, decode_device, encode_error, message_name,
+};
+use crate::server::ServerLog;
 
 pub struct CustomServer {
     rx_buf: Arc<RwLock<Vec<RxMessage>>>,
@@ -95,6 +32,9 @@ pub struct CustomServer {
     tx_buffer_queue: Receiver<Vec<TxMessage>>,
     proxy: EventLoopProxy<UserEvent>,
     num_devices: usize,
+    shutdown: Arc<AtomicBool>,
+    client: Arc<Mutex<Option<TcpStream>>>,
+    server_log: ServerLog,
 }
 
 unsafe impl Send for CustomServer {}
@@ -105,6 +45,9 @@ impl CustomServer {
         rx_buf: Arc<RwLock<Vec<RxMessage>>>,
         tx_buffer_queue: Receiver<Vec<TxMessage>>,
         proxy: EventLoopProxy<UserEvent>,
+        shutdown: Arc<AtomicBool>,
+        client: Arc<Mutex<Option<TcpStream>>>,
+        server_log: ServerLog,
     ) -> Self {
         Self {
             rx_buf,
@@ -112,18 +55,39 @@ impl CustomServer {
             tx_buffer_queue,
             proxy,
             num_devices: 0,
+            shutdown,
+            client,
+            server_log,
         }
     }
 
     pub fn run(mut self, listener: TcpListener) -> Result<()> {
         loop {
-            let (stream, _addr) = listener.accept()?;
+            let (stream, addr) = listener.accept()?;
+            {
+                let mut client = self.client.lock().unwrap();
+                if self.shutdown.load(Ordering::Acquire) {
+                    return Ok(());
+                }
+                *client = stream.try_clone().ok();
+            }
+            self.server_log
+                .connection
+                .push(format_args!("{addr} connected"));
             let _ = self.handle_client(stream);
+            self.client.lock().unwrap().take();
+            let _ = self
+                .proxy
+                .send_event(UserEvent::Server(Signal::Disconnected));
+            self.server_log
+                .connection
+                .push(format_args!("{addr} disconnected"));
         }
     }
 
     fn handle_client(&mut self, mut stream: TcpStream) -> Result<()> {
         let mut handshake_completed = false;
+        let mut version = 0;
 
         loop {
             let mut msg_type = [0u8; size_of::<u8>()];
@@ -134,47 +98,64 @@ impl CustomServer {
             let msg = msg_type[0];
             let result = if msg == MSG_HELLO {
                 if handshake_completed {
-                    Err(SimulatorError::server_error("Handshake already completed"))
+                    Err(ProtocolError::HandshakeAlreadyCompleted.into())
                 } else {
                     match Self::handle_handshake(&mut stream) {
-                        Ok(()) => {
+                        Ok(v) => {
                             handshake_completed = true;
+                            version = v;
+                            let _ = self.proxy.send_event(UserEvent::Server(Signal::Connected));
+                            self.server_log
+                                .connection
+                                .push(format_args!("Handshake completed, protocol version {v}"));
                             Ok(())
                         }
                         Err(e) => {
-                            eprintln!("Handshake failed: {}", e);
+                            log::error!("Handshake failed: {}", e);
                             Err(e)
                         }
                     }
                 }
             } else if !handshake_completed {
-                Err(SimulatorError::server_error(
-                    "Handshake is required before sending commands",
-                ))
+                Err(ProtocolError::HandshakeRequired.into())
             } else {
                 match msg {
                     MSG_CONFIG_GEOMETRY => self.handle_config_geometry(&mut stream),
                     MSG_UPDATE_GEOMETRY => self.handle_update_geometry(&mut stream),
                     MSG_SEND_DATA => self.handle_send_data(&mut stream),
                     MSG_READ_DATA => self.handle_read_data(&mut stream),
+                    MSG_SEND_AND_READ_DATA if version >= 2 => {
+                        self.handle_send_and_read_data(&mut stream)
+                    }
+                    MSG_SET_TIME_BASE if version >= 3 => self.handle_set_time_base(&mut stream),
                     MSG_CLOSE => self.handle_close(&mut stream),
-                    other => Err(SimulatorError::server_error(format!(
-                        "Unknown message type: {}",
-                        other
-                    ))),
+                    other => Err(ProtocolError::UnknownMessage(other).into()),
                 }
             };
 
             match result {
                 Ok(()) => {
+                    log::verbose!("Handled message 0x{:02X}", msg);
+                    if msg != MSG_HELLO {
+                        self.server_log.connection.push(message_name(msg));
+                    }
                     if msg == MSG_CLOSE {
                         break;
                     }
                 }
                 Err(e) => {
-                    eprintln!("Error handling client request: {}", e);
+                    log::error!("Error handling client request: {}", e);
+                    self.server_log.errors.push(format!("0x{msg:02X}: {e}"));
+                    self.server_log.connection.push(format_args!(
+                        "{} failed: {}",
+                        message_name(msg),
+                        e
+                    ));
+                    // The stream is out of sync after a partial payload
+                    let truncated =
+                        matches!(e, SimulatorError::Protocol(ProtocolError::Truncated { .. }));
                     let _ = Self::send_error(&mut stream, e);
-                    if !handshake_completed || msg == MSG_CLOSE {
+                    if !handshake_completed || msg == MSG_CLOSE || truncated {
                         break;
                     }
                 }
@@ -183,26 +164,23 @@ impl CustomServer {
         Ok(())
     }
 
-    fn handle_handshake(stream: &mut TcpStream) -> Result<()> {
+    fn handle_handshake(stream: &mut TcpStream) -> Result<u16> {
         let mut version_buf = [0u8; size_of::<u16>()];
         stream.read_exact(&mut version_buf)?;
         let version = u16::from_le_bytes(version_buf);
-        if version != REMOTE_PROTOCOL_VERSION {
-            return Err(SimulatorError::server_error(format!(
-                "Unsupported protocol version: {}",
-                version
-            )));
+        if !(MIN_REMOTE_PROTOCOL_VERSION..=REMOTE_PROTOCOL_VERSION).contains(&version) {
+            return Err(ProtocolError::UnsupportedVersion(version).into());
         }
 
         let mut magic_buf = [0u8; REMOTE_PROTOCOL_MAGIC.len()];
         stream.read_exact(&mut magic_buf)?;
         if &magic_buf != REMOTE_PROTOCOL_MAGIC {
-            eprintln!("Invalid client magic: {:?}", magic_buf);
-            return Err(SimulatorError::server_error("Invalid client magic"));
+            log::error!("Invalid client magic: {:?}", magic_buf);
+            return Err(ProtocolError::InvalidMagic.into());
         }
 
         stream.write_all(&[MSG_OK])?;
-        Ok(())
+        Ok(version)
     }
 
     fn handle_config_geometry(&mut self, stream: &mut TcpStream) -> Result<()> {
@@ -210,7 +188,7 @@ impl CustomServer {
         self.num_devices = geometry.num_devices();
         self.proxy
             .send_event(UserEvent::Server(Signal::ConfigGeometry(geometry)))
-            .map_err(|_e| SimulatorError::server_error("Simulator is closed"))?;
+            .map_err(|_e| SimulatorError::Disconnected)?;
         stream.write_all(&[MSG_OK])?;
         Ok(())
     }
@@ -219,7 +197,7 @@ impl CustomServer {
         let geometry = self.read_geometry(stream)?;
         self.proxy
             .send_event(UserEvent::Server(Signal::UpdateGeometry(geometry)))
-            .map_err(|_e| SimulatorError::server_error("Simulator is closed"))?;
+            .map_err(|_e| SimulatorError::Disconnected)?;
         stream.write_all(&[MSG_OK])?;
         Ok(())
     }
@@ -231,67 +209,111 @@ impl CustomServer {
         Ok(autd3_core::geometry::Geometry::new(
             (0..num_devices)
                 .map(|_| {
-                    let mut pos_buf = [0u8; 12];
-                    stream.read_exact(&mut pos_buf)?;
-                    let x = f32::from_le_bytes([pos_buf[0], pos_buf[1], pos_buf[2], pos_buf[3]]);
-                    let y = f32::from_le_bytes([pos_buf[4], pos_buf[5], pos_buf[6], pos_buf[7]]);
-                    let z = f32::from_le_bytes([pos_buf[8], pos_buf[9], pos_buf[10], pos_buf[11]]);
-
-                    let mut rot_buf = [0u8; 16];
-                    stream.read_exact(&mut rot_buf)?;
-                    let w = f32::from_le_bytes([rot_buf[0], rot_buf[1], rot_buf[2], rot_buf[3]]);
-                    let i = f32::from_le_bytes([rot_buf[4], rot_buf[5], rot_buf[6], rot_buf[7]]);
-                    let j = f32::from_le_bytes([rot_buf[8], rot_buf[9], rot_buf[10], rot_buf[11]]);
-                    let k =
-                        f32::from_le_bytes([rot_buf[12], rot_buf[13], rot_buf[14], rot_buf[15]]);
-
-                    Ok(autd3_core::devices::AUTD3 {
-                        pos: autd3_core::geometry::Point3::new(x, y, z),
-                        rot: autd3_core::geometry::UnitQuaternion { w, i, j, k },
-                    }
-                    .into())
+                    let mut buf = [0u8; DEVICE_CONFIG_SIZE];
+                    stream.read_exact(&mut buf)?;
+                    let config = decode_device(&buf);
+                    config.validate()?;
+                    Ok(config.into())
                 })
                 .collect::<Result<Vec<_>>>()?,
         ))
     }
 
     fn handle_send_data(&self, stream: &mut TcpStream) -> Result<()> {
+        self.receive_tx_data(stream)?;
+        stream.write_all(&[MSG_OK])?;
+        Ok(())
+    }
+
+    fn handle_send_and_read_data(&mut self, stream: &mut TcpStream) -> Result<()> {
+        self.receive_tx_data(stream)?;
+        self.handle_read_data(stream)
+    }
+
+    fn receive_tx_data(&self, stream: &mut TcpStream) -> Result<()> {
+        if self.num_devices == 0 {
+            return Err(ProtocolError::GeometryRequired.into());
+        }
         let mut tx_data = match self.tx_buffer_queue.try_recv() {
-            Ok(data) => data,
-            Err(_) => {
+            Ok(data) if data.len() == self.num_devices => data,
+            _ => {
                 vec![TxMessage::new(); self.num_devices]
             }
         };
         unsafe {
             let buf = std::slice::from_raw_parts_mut(
                 tx_data.as_mut_ptr() as *mut u8,
-                tx_data.len() * std::mem::size_of::<TxMessage>(),
+                tx_data.len() * TX_MESSAGE_SIZE,
             );
-            stream.read_exact(buf)?;
+            Self::read_chunked(stream, buf)?;
         }
 
         self.proxy
             .send_event(UserEvent::Server(Signal::Send(tx_data)))
-            .map_err(|_e| SimulatorError::server_error("Simulator is closed"))?;
+            .map_err(|_e| SimulatorError::Disconnected)
+    }
 
-        stream.write_all(&[MSG_OK])?;
-        Ok(())
+    fn read_chunked(stream: &mut TcpStream, buf: &mut [u8]) -> Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut received = 0;
+        let result = loop {
+            if received == buf.len() {
+                break Ok(());
+            }
+            let end = (received + READ_CHUNK_SIZE).min(buf.len());
+            match stream.read(&mut buf[received..end]) {
+                Ok(0) => {
+                    break Err(ProtocolError::Truncated {
+                        expected: buf.len(),
+                        received,
+                    }
+                    .into());
+                }
+                Ok(n) => received += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break Err(ProtocolError::Truncated {
+                        expected: buf.len(),
+                        received,
+                    }
+                    .into());
+                }
+                Err(e) => break Err(e.into()),
+            }
+        };
+        stream.set_read_timeout(None)?;
+        result
     }
 
     fn handle_read_data(&mut self, stream: &mut TcpStream) -> Result<()> {
+        if self.num_devices == 0 {
+            return Err(ProtocolError::GeometryRequired.into());
+        }
         let rx_data = {
             let mut rx_data = match self.rx_data.take() {
-                Some(buf) if buf.len() == self.num_devices * std::mem::size_of::<RxMessage>() => {
-                    buf
-                }
-                _ => vec![0x00; self.num_devices * std::mem::size_of::<RxMessage>()],
+                Some(buf) if buf.len() == self.num_devices * RX_MESSAGE_SIZE => buf,
+                _ => vec![0x00; self.num_devices * RX_MESSAGE_SIZE],
             };
             let rx = self.rx_buf.read().unwrap();
+            // The emulator may not have applied the latest geometry yet
+            if rx.len() != self.num_devices {
+                log::verbose!(
+                    "Rx data of {} devices is not ready, {} available",
+                    self.num_devices,
+                    rx.len()
+                );
+                rx_data.fill(0x00);
+            }
             unsafe {
                 std::ptr::copy_nonoverlapping(
                     rx.as_ptr(),
                     rx_data.as_mut_ptr() as *mut RxMessage,
-                    rx.len(),
+                    rx.len().min(self.num_devices),
                 );
             }
             rx_data
@@ -305,24 +327,27 @@ impl CustomServer {
         Ok(())
     }
 
+    fn handle_set_time_base(&self, stream: &mut TcpStream) -> Result<()> {
+        let mut sys_time_buf = [0u8; size_of::<u64>()];
+        stream.read_exact(&mut sys_time_buf)?;
+        self.proxy
+            .send_event(UserEvent::Server(Signal::SetTimeBase(u64::from_le_bytes(
+                sys_time_buf,
+            ))))
+            .map_err(|_e| SimulatorError::Disconnected)?;
+        stream.write_all(&[MSG_OK])?;
+        Ok(())
+    }
+
     fn handle_close(&self, stream: &mut TcpStream) -> Result<()> {
         self.proxy
             .send_event(UserEvent::Server(Signal::Close))
-            .map_err(|_e| SimulatorError::server_error("Simulator is closed"))?;
+            .map_err(|_e| SimulatorError::Disconnected)?;
         stream.write_all(&[MSG_OK])?;
         Ok(())
     }
 
     fn send_error(stream: &mut TcpStream, error: SimulatorError) -> std::io::Result<()> {
-        let error_msg = error.to_string();
-        let error_bytes = error_msg.as_bytes();
-        let error_len = error_bytes.len() as u32;
-
-        let mut buffer = Vec::with_capacity(size_of::<u8>() + size_of::<u32>() + error_bytes.len());
-        buffer.push(MSG_ERROR);
-        buffer.extend_from_slice(&error_len.to_le_bytes());
-        buffer.extend_from_slice(error_bytes);
-
-        stream.write_all(&buffer)
+        stream.write_all(&encode_error(&error.to_string()))
     }
 }
@@ -1,19 +1,145 @@
 mod custom;
+mod stream;
+
+pub use stream::SliceStreamer;
 
 use std::sync::mpsc::Receiver;
 
-use crate::error::Result;
+use crate::error::{Result, SimulatorError};
 use crate::event::UserEvent;
+use crate::log;
 use winit::event_loop::EventLoopProxy;
 
-use std::net::TcpListener;
-use std::sync::{Arc, RwLock};
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, LineWriter, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::{self, JoinHandle};
+use std::time::Instant;
 
 use autd3_core::link::{RxMessage, TxMessage};
 
+// Number of server errors kept for display in the UI
+const MAX_ERRORS: usize = 16;
+
+/// Most recent errors of the server, shared with the UI. The oldest entry is dropped when full.
+#[derive(Clone, Default)]
+pub struct ErrorLog(Arc<Mutex<VecDeque<String>>>);
+
+impl ErrorLog {
+    pub fn push(&self, error: String) {
+        let mut errors = self.0.lock().unwrap();
+        if errors.len() == MAX_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(error);
+    }
+
+    /// Errors in the order they occurred.
+    pub fn entries(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+// Number of connection events kept for display in the UI
+const MAX_CONNECTION_EVENTS: usize = 256;
+
+struct ConnectionLogInner {
+    start: Instant,
+    entries: VecDeque<String>,
+    file: Option<(PathBuf, LineWriter<File>)>,
+}
+
+/// Protocol events of the client connections with the seconds since startup, shared with the UI.
+/// The oldest entry is dropped when full. Entries can additionally be appended to a file.
+#[derive(Clone)]
+pub struct ConnectionLog(Arc<Mutex<ConnectionLogInner>>);
+
+impl Default for ConnectionLog {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(ConnectionLogInner {
+            start: Instant::now(),
+            entries: VecDeque::new(),
+            file: None,
+        })))
+    }
+}
+
+impl ConnectionLog {
+    pub fn push(&self, event: impl Display) {
+        let mut inner = self.0.lock().unwrap();
+        let entry = format!("[{:10.3}] {event}", inner.start.elapsed().as_secs_f64());
+        if let Some((path, file)) = &mut inner.file
+            && let Err(e) = writeln!(file, "{entry}")
+        {
+            log::error!(
+                "Failed to write connection log to {}: {}",
+                path.display(),
+                e
+            );
+            inner.file = None;
+        }
+        if inner.entries.len() == MAX_CONNECTION_EVENTS {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back(entry);
+    }
+
+    /// Entries in the order they occurred.
+    pub fn entries(&self) -> Vec<String> {
+        self.0.lock().unwrap().entries.iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().entries.clear();
+    }
+
+    /// File new entries are appended to, if any.
+    pub fn file(&self) -> Option<PathBuf> {
+        self.0
+            .lock()
+            .unwrap()
+            .file
+            .as_ref()
+            .map(|(path, _)| path.clone())
+    }
+
+    /// Appends new entries to `path`, or stops writing them to a file if `None`.
+    pub fn set_file(&self, path: Option<&Path>) -> std::io::Result<()> {
+        let file = path
+            .map(|path| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map(|file| (path.to_owned(), LineWriter::new(file)))
+            })
+            .transpose()?;
+        self.0.lock().unwrap().file = file;
+        Ok(())
+    }
+}
+
+/// Logs of the server shown in the UI.
+#[derive(Clone, Default)]
+pub struct ServerLog {
+    pub errors: ErrorLog,
+    pub connection: ConnectionLog,
+}
+
 pub struct Server {
-    _server_th: JoinHandle<Result<()>>,
+    port: u16,
+    server_th: JoinHandle<Result<()>>,
+    shutdown: Arc<AtomicBool>,
+    client: Arc<Mutex<Option<TcpStream>>>,
 }
 
 impl Server {
@@ -22,20 +148,58 @@ impl Server {
         rx_buf: Arc<RwLock<Vec<RxMessage>>>,
         tx_buffer_queue: Receiver<Vec<TxMessage>>,
         proxy: EventLoopProxy<UserEvent>,
+        server_log: ServerLog,
     ) -> Result<Self> {
-        let server_th = thread::spawn(move || {
-            let listener = TcpListener::bind(format!("0.0.0.0:{port}"))?;
-            println!("listening on port {}", port);
-            custom::CustomServer::new(rx_buf, tx_buffer_queue, proxy).run(listener)?;
-            Ok(())
+        // Bind before spawning so that a port conflict is reported to the caller right away.
+        let listener =
+            TcpListener::bind(format!("0.0.0.0:{port}")).map_err(|e| match e.kind() {
+                ErrorKind::AddrInUse => {
+                    SimulatorError::server_error(format!("Port {port} is already in use"))
+                }
+                _ => e.into(),
+            })?;
+        log::info!("listening on port {}", port);
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let client = Arc::new(Mutex::new(None));
+        let server_th = thread::spawn({
+            let shutdown = shutdown.clone();
+            let client = client.clone();
+            move || {
+                custom::CustomServer::new(
+                    rx_buf,
+                    tx_buffer_queue,
+                    proxy,
+                    shutdown,
+                    client,
+                    server_log,
+                )
+                .run(listener)?;
+                Ok(())
+            }
         });
 
         Ok(Self {
-            _server_th: server_th,
+            port,
+            server_th,
+            shutdown,
+            client,
         })
     }
 
     pub fn shutdown(self) -> Result<()> {
-        Ok(())
+        {
+            let mut client = self.client.lock().unwrap();
+            self.shutdown.store(true, Ordering::Release);
+            if let Some(stream) = client.take() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+        // Wake up the listener blocked in `accept` so that it can observe the shutdown flag.
+        let _ = TcpStream::connect(("127.0.0.1", self.port));
+
+        self.server_th
+            .join()
+            .map_err(|_| SimulatorError::server_error("Server thread panicked"))?
     }
 }
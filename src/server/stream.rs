@@ -0,0 +1,114 @@
+// # Slice Stream Specification
+//
+// Viewers connect to the stream port and receive every slice frame computed while they are connected.
+// Nothing is read from viewers.
+//
+// ## Frame Format
+// - 8 bytes: system time of the frame in ns (u64, little-endian), in the time base set by the client if any
+// - 4 bytes: width (u32, little-endian)
+// - 4 bytes: height (u32, little-endian)
+// - width * height * 4 bytes: RGBA8 pixels, top row first
+
+use std::io::{ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::{Result, SimulatorError};
+use crate::log;
+
+// A viewer that does not take a frame within this time is dropped, so that it cannot stall the
+// stream for the others. A partially written frame would leave it out of sync anyway.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Frame {
+    time: u64,
+    size: (u32, u32),
+    data: Vec<u8>,
+}
+
+pub struct SliceStreamer {
+    viewers: Arc<Mutex<Vec<TcpStream>>>,
+    frame_queue: SyncSender<Frame>,
+}
+
+impl SliceStreamer {
+    pub fn new(port: u16) -> Result<Self> {
+        let listener =
+            TcpListener::bind(format!("0.0.0.0:{port}")).map_err(|e| match e.kind() {
+                ErrorKind::AddrInUse => {
+                    SimulatorError::server_error(format!("Port {port} is already in use"))
+                }
+                _ => e.into(),
+            })?;
+        log::info!("streaming slice on port {}", port);
+
+        let viewers = Arc::new(Mutex::new(Vec::new()));
+        thread::spawn({
+            let viewers = viewers.clone();
+            move || {
+                for stream in listener.incoming().flatten() {
+                    if let Err(e) = stream
+                        .set_write_timeout(Some(WRITE_TIMEOUT))
+                        .and_then(|_| stream.set_nodelay(true))
+                    {
+                        log::error!("Failed to set up a stream viewer: {}", e);
+                        continue;
+                    }
+                    viewers.lock().unwrap().push(stream);
+                }
+            }
+        });
+
+        let (frame_queue, frame_receiver) = std::sync::mpsc::sync_channel(1);
+        thread::spawn({
+            let viewers = viewers.clone();
+            move || Self::run(viewers, frame_receiver)
+        });
+
+        Ok(Self {
+            viewers,
+            frame_queue,
+        })
+    }
+
+    pub fn has_viewers(&self) -> bool {
+        !self.viewers.lock().unwrap().is_empty()
+    }
+
+    /// Queues a frame for the viewers. The frame is dropped if the previous one has not been sent yet.
+    pub fn push(&self, time: u64, size: (u32, u32), data: Vec<u8>) {
+        if let Err(TrySendError::Disconnected(_)) =
+            self.frame_queue.try_send(Frame { time, size, data })
+        {
+            log::error!("Slice streaming thread has stopped");
+        }
+    }
+
+    fn run(viewers: Arc<Mutex<Vec<TcpStream>>>, frame_receiver: Receiver<Frame>) {
+        while let Ok(frame) = frame_receiver.recv() {
+            let mut header = Vec::with_capacity(size_of::<u64>() + 2 * size_of::<u32>());
+            header.extend_from_slice(&frame.time.to_le_bytes());
+            header.extend_from_slice(&frame.size.0.to_le_bytes());
+            header.extend_from_slice(&frame.size.1.to_le_bytes());
+
+            // Write without holding the lock so that a slow viewer does not block the render thread.
+            let mut current = std::mem::take(&mut *viewers.lock().unwrap());
+            current.retain_mut(|stream| {
+                match stream
+                    .write_all(&header)
+                    .and_then(|_| stream.write_all(&frame.data))
+                {
+                    Ok(()) => true,
+                    Err(e) => {
+                        log::info!("Dropped a stream viewer: {}", e);
+                        false
+                    }
+                }
+            });
+            viewers.lock().unwrap().append(&mut current);
+        }
+    }
+}
@@ -1,9 +1,14 @@
 use std::{
-    sync::{Arc, RwLock, mpsc::SyncSender},
-    time::Instant,
+    path::Path,
+    sync::{
+        Arc, RwLock,
+        mpsc::{SyncSender, TrySendError},
+    },
+    time::{Duration, Instant},
 };
 
 use autd3_core::link::TxMessage;
+use autd3_driver::geometry::Geometry;
 use wgpu::{InstanceFlags, MemoryBudgetThresholds};
 use winit::{
     application::ApplicationHandler,
@@ -12,22 +17,47 @@ use winit::{
 };
 
 use crate::{
+    MIN_WINDOW_SIZE,
     emulator::EmulatorWrapper,
+    emulator::modulation_frames,
     error::Result,
     event::{EventResult, Signal, UserEvent},
+    export::SamplingParams,
+    log,
     renderer::Renderer,
-    server::Server,
-    state::State,
+    server::{Server, ServerLog, SliceStreamer},
+    state::{SlicePick, State},
     update_flag::UpdateFlag,
 };
 
+// Number of received buffers handed back to the server for reuse
+const TX_BUFFER_QUEUE_SIZE: usize = 16;
+
+/// Hook invoked with every message sent by a client, before it reaches the emulator.
+pub type SendHook = Box<dyn FnMut(&[TxMessage])>;
+
 pub struct Simulator {
     server: Option<Server>,
+    server_log: ServerLog,
+    streamer: Option<SliceStreamer>,
     tx_buffer_queue: SyncSender<Vec<TxMessage>>,
     emulator: EmulatorWrapper,
     instance: wgpu::Instance,
     repaint_proxy: Option<EventLoopProxy<UserEvent>>,
+    proxy: EventLoopProxy<UserEvent>,
+    initial_geometry: Option<Geometry>,
+    geometry: Option<Geometry>,
+    on_send: Option<SendHook>,
     windows_next_repaint_time: Option<Instant>,
+    last_paint_time: Option<Instant>,
+    next_emulator_update: Option<Instant>,
+    /// System time the emulator was last stepped to.
+    last_emulator_time: Option<u64>,
+    /// Time of the last client message or user input, for the idle timeout.
+    last_activity: Instant,
+    /// Whether a client has completed the handshake and not disconnected since, which stops
+    /// the idle timeout.
+    client_connected: bool,
     window: Option<Arc<Window>>,
     renderer: Option<Renderer>,
     run_result: Result<()>,
@@ -37,20 +67,49 @@ pub struct Simulator {
 
 impl Simulator {
     pub fn run(event_loop: winit::event_loop::EventLoop<UserEvent>, state: State) -> Result<State> {
-        let (buffer_queue_sender, buffer_queue_receiver) = std::sync::mpsc::sync_channel(16);
+        Self::run_with_geometry(event_loop, state, None)
+    }
+
+    /// Runs the simulator, configuring `geometry` on startup as if a client had sent it.
+    pub fn run_with_geometry(
+        event_loop: winit::event_loop::EventLoop<UserEvent>,
+        state: State,
+        geometry: Option<Geometry>,
+    ) -> Result<State> {
+        Self::run_with_send_hook(event_loop, state, geometry, None)
+    }
+
+    /// Same as [`Simulator::run_with_geometry`], additionally calling `on_send`
+    /// for every message sent by a client.
+    pub fn run_with_send_hook(
+        event_loop: winit::event_loop::EventLoop<UserEvent>,
+        state: State,
+        geometry: Option<Geometry>,
+        on_send: Option<SendHook>,
+    ) -> Result<State> {
+        crate::log::set_level(state.log_level);
+
+        let (buffer_queue_sender, buffer_queue_receiver) =
+            std::sync::mpsc::sync_channel(TX_BUFFER_QUEUE_SIZE);
 
         let rx_buf = Arc::new(RwLock::default());
+        let server_log = ServerLog::default();
         let server = Server::new(
             state.port,
             rx_buf.clone(),
             buffer_queue_receiver,
             event_loop.create_proxy(),
+            server_log.clone(),
         )?;
 
+        let streamer = state.stream_port.map(SliceStreamer::new).transpose()?;
+
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::PRIMARY,
-            flags: if state.debug {
+            flags: if state.gpu_validation {
                 InstanceFlags::VALIDATION | InstanceFlags::GPU_BASED_VALIDATION
+            } else if state.validation || state.debug {
+                InstanceFlags::VALIDATION
             } else {
                 InstanceFlags::empty()
             },
@@ -62,10 +121,21 @@ impl Simulator {
         let mut app = Self {
             instance,
             repaint_proxy: Some(event_loop.create_proxy()),
+            proxy: event_loop.create_proxy(),
+            initial_geometry: geometry,
+            geometry: None,
+            on_send,
             tx_buffer_queue: buffer_queue_sender,
             server: Some(server),
+            server_log,
+            streamer,
             emulator: EmulatorWrapper::new(rx_buf),
             windows_next_repaint_time: None,
+            last_paint_time: None,
+            next_emulator_update: None,
+            last_emulator_time: None,
+            last_activity: Instant::now(),
+            client_connected: false,
             window: None,
             renderer: None,
             run_result: Ok(()),
@@ -81,7 +151,7 @@ impl Simulator {
     }
 
     fn initialize(&mut self, event_loop: &ActiveEventLoop) -> Result<()> {
-        let egui_ctx = Renderer::create_egui_context();
+        let egui_ctx = Renderer::create_egui_context(&self.state);
         let window = self.create_window(&egui_ctx, event_loop)?;
         self.init_run_state(egui_ctx, window)?;
         Ok(())
@@ -93,9 +163,12 @@ impl Simulator {
         event_loop: &ActiveEventLoop,
     ) -> Result<Window> {
         let viewport_builder = egui::ViewportBuilder::default()
-            .with_inner_size([self.state.window_size.0 as _, self.state.window_size.1 as _])
+            .with_inner_size([
+                self.state.window_size.0.max(MIN_WINDOW_SIZE) as _,
+                self.state.window_size.1.max(MIN_WINDOW_SIZE) as _,
+            ])
             .with_visible(false)
-            .with_title("AUTD3 Simulator");
+            .with_title(self.state.title());
         let window = egui_winit::create_window(egui_ctx, event_loop, &viewport_builder)?;
         Ok(window)
     }
@@ -108,23 +181,52 @@ impl Simulator {
             self.repaint_proxy.take().unwrap(),
             egui_ctx,
             window.clone(),
+            self.server_log.clone(),
             self.state.window_size.0,
             self.state.window_size.1,
             &self.state,
         )?);
         self.window = Some(window);
 
+        if let Some(geometry) = self.initial_geometry.take() {
+            let _ = self
+                .proxy
+                .send_event(UserEvent::Server(Signal::ConfigGeometry(geometry)));
+        }
+
         Ok(())
     }
 
-    fn update(&mut self, event: Option<Signal>) {
+    /// Steps the firmware emulator to the current system time, if it has moved since the last step.
+    fn step_emulator(&mut self) {
         let system_time = self.state.system_time();
-        self.emulator.update(system_time);
+        if self.last_emulator_time != Some(system_time.sys_time()) {
+            self.emulator.update(system_time);
+            self.last_emulator_time = Some(system_time.sys_time());
+        }
+    }
 
+    fn update(&mut self, event: Option<Signal>) {
         if let Some(signal) = event {
+            self.step_emulator();
             match signal {
+                crate::event::Signal::Connected => {
+                    self.client_connected = true;
+                }
+                // A reconfiguration with the same layout only moves the devices, which keeps
+                // the firmware state and avoids resetting the view.
+                crate::event::Signal::ConfigGeometry(geometry)
+                    if self.emulator.initialized() && self.emulator.same_layout(&geometry) =>
+                {
+                    self.emulator.update_geometry(&geometry);
+
+                    self.update_flag.set(UpdateFlag::UPDATE_TRANS_POS, true);
+
+                    self.geometry = Some(geometry);
+                }
                 crate::event::Signal::ConfigGeometry(geometry) => {
                     self.emulator.initialize(&geometry);
+                    self.last_emulator_time = None;
                     self.renderer.as_mut().unwrap().initialize(&self.emulator);
 
                     self.update_flag.set(UpdateFlag::UPDATE_CAMERA, true);
@@ -136,31 +238,90 @@ impl Simulator {
                     self.update_flag
                         .set(UpdateFlag::UPDATE_SLICE_COLOR_MAP, true);
                     self.update_flag.set(UpdateFlag::UPDATE_CONFIG, true);
+
+                    self.geometry = Some(geometry);
                 }
                 crate::event::Signal::UpdateGeometry(geometry) => {
                     self.emulator.update_geometry(&geometry);
 
                     self.update_flag.set(UpdateFlag::UPDATE_TRANS_POS, true);
+
+                    self.geometry = Some(geometry);
                 }
                 crate::event::Signal::Send(tx) => {
+                    if let Some(on_send) = &mut self.on_send {
+                        on_send(&tx);
+                    }
                     self.emulator.send(&tx);
-                    self.tx_buffer_queue.send(tx).unwrap();
+                    // Compare the states right after each frame, at the time it was applied, so
+                    // that the emulator advancing until the next repaint does not count as a change.
+                    if let Some(renderer) = &mut self.renderer {
+                        self.emulator.update_transducers(self.state.mod_enable);
+                        renderer.diff_sent(&self.emulator);
+                    }
+                    // The queue only recycles buffers, so a buffer is dropped instead of blocking
+                    // the UI thread when the server lags behind. The server allocates a new one then.
+                    match self.tx_buffer_queue.try_send(tx) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(_)) => {
+                            log::verbose!("Buffer queue is full, dropping a received frame buffer");
+                        }
+                        Err(TrySendError::Disconnected(_)) => {
+                            log::verbose!("Server has stopped, dropping a received frame buffer");
+                        }
+                    }
 
                     self.update_flag.set(UpdateFlag::UPDATE_TRANS_STATE, true);
                 }
+                crate::event::Signal::SetTimeBase(sys_time) => {
+                    self.state.time_offset =
+                        Some(sys_time.wrapping_sub(self.state.real_time) as i64);
+                    self.update_flag.set(UpdateFlag::UPDATE_TRANS_STATE, true);
+                }
                 crate::event::Signal::Close => {
                     self.emulator.clear();
+                    self.last_emulator_time = None;
+                    self.geometry = None;
+                    self.state.time_offset = None;
+                }
+                // The time base only holds for the client that set it. The idle timeout restarts
+                // from here, as for any client message, even if the client never sent Close.
+                crate::event::Signal::Disconnected => {
+                    self.client_connected = false;
+                    self.state.time_offset = None;
                 }
             }
         }
+
+        if let Some(path) = self.state.preview_modulation.take() {
+            self.load_modulation(&path);
+        }
+    }
+
+    fn load_modulation(&mut self, path: &std::path::Path) {
+        let Some(geometry) = &self.geometry else {
+            log::error!("Cannot load a modulation before the geometry is configured");
+            return;
+        };
+        match modulation_frames(path, geometry) {
+            Ok(frames) => frames.into_iter().for_each(|tx| {
+                let _ = self.proxy.send_event(UserEvent::Server(Signal::Send(tx)));
+            }),
+            Err(e) => log::error!("Failed to load modulation from {}: {}", path.display(), e),
+        }
     }
 
     fn run_ui_and_paint(&mut self, window: &Window) -> Result<EventResult> {
+        self.last_paint_time = Some(Instant::now());
+        // Picks up changes of the system time made by the UI or the deterministic clock
+        self.step_emulator();
+
         let Self {
             renderer,
             state,
             emulator,
             update_flag,
+            streamer,
             ..
         } = self;
 
@@ -175,6 +336,7 @@ impl Simulator {
                 update_flag.remove(UpdateFlag::UPDATE_TRANS_POS);
             }
 
+            let mut fading = false;
             if update_flag.contains(UpdateFlag::UPDATE_TRANS_ALPHA)
                 | update_flag.contains(UpdateFlag::UPDATE_TRANS_STATE)
             {
@@ -184,16 +346,20 @@ impl Simulator {
 
                     update_flag.remove(UpdateFlag::UPDATE_TRANS_STATE);
                 }
-                renderer.update_color(emulator);
+                if !state.freeze_trans_color {
+                    fading = renderer.update_color(state, emulator);
+                }
                 update_flag.remove(UpdateFlag::UPDATE_TRANS_ALPHA);
             }
 
             if update_flag.contains(UpdateFlag::UPDATE_SLICE_POS)
                 | update_flag.contains(UpdateFlag::UPDATE_SLICE_SIZE)
+                | update_flag.contains(UpdateFlag::UPDATE_SLICE_RESOLUTION)
             {
                 renderer.update_slice(state);
                 update_flag.remove(UpdateFlag::UPDATE_SLICE_POS);
                 update_flag.remove(UpdateFlag::UPDATE_SLICE_SIZE);
+                update_flag.remove(UpdateFlag::UPDATE_SLICE_RESOLUTION);
             }
 
             if update_flag.contains(UpdateFlag::UPDATE_CONFIG) {
@@ -206,12 +372,118 @@ impl Simulator {
                 update_flag.remove(UpdateFlag::UPDATE_SLICE_COLOR_MAP);
             }
 
+            if update_flag.contains(UpdateFlag::UPDATE_GRID) {
+                renderer.update_grid(state);
+                update_flag.remove(UpdateFlag::UPDATE_GRID);
+            }
+
+            if update_flag.contains(UpdateFlag::UPDATE_REFERENCE) {
+                renderer.update_reference(state);
+                update_flag.remove(UpdateFlag::UPDATE_REFERENCE);
+            }
+
+            if update_flag.contains(UpdateFlag::UPDATE_SURFACE) {
+                renderer.update_surface(state);
+                update_flag.remove(UpdateFlag::UPDATE_SURFACE);
+            }
+
             assert!(update_flag.is_empty());
 
             let result = renderer.run_ui_and_paint(state, emulator, window, update_flag)?;
 
-            if emulator.initialized() && state.auto_play {
-                if cfg!(target_os = "windows") {
+            if let Some(streamer) = streamer
+                && emulator.initialized()
+                && streamer.has_viewers()
+            {
+                match renderer.read_slice() {
+                    Ok((size, data)) => streamer.push(state.system_time().sys_time(), size, data),
+                    Err(e) => log::error!("Failed to read the slice for the stream: {}", e),
+                }
+            }
+
+            if let Some(frame) = state.recording
+                && emulator.initialized()
+            {
+                Self::record_frame(renderer, state, emulator, window, update_flag, frame);
+            }
+
+            if let Some(path) = state.export_npy.take()
+                && emulator.initialized()
+            {
+                match renderer.export_slice_npy(state, &path).and_then(|_| {
+                    SamplingParams::new(state, renderer.slice_resolution()).save_beside(&path)
+                }) {
+                    Ok(()) => log::info!("Exported the slice field to {}", path.display()),
+                    Err(e) => log::error!(
+                        "Failed to export the slice field to {}: {}",
+                        path.display(),
+                        e
+                    ),
+                }
+            }
+
+            if let Some(path) = state.export_csv.take()
+                && emulator.initialized()
+            {
+                match renderer.export_slice_field(state, &path).and_then(|_| {
+                    SamplingParams::new(state, renderer.slice_resolution()).save_beside(&path)
+                }) {
+                    Ok(()) => log::info!("Exported the slice field to {}", path.display()),
+                    Err(e) => log::error!(
+                        "Failed to export the slice field to {}: {}",
+                        path.display(),
+                        e
+                    ),
+                }
+            }
+
+            if let Some(path) = state.export_png.take()
+                && emulator.initialized()
+            {
+                match renderer.export_slice_png(&path).and_then(|_| {
+                    SamplingParams::new(state, renderer.slice_resolution()).save_beside(&path)
+                }) {
+                    Ok(()) => log::info!("Exported the slice image to {}", path.display()),
+                    Err(e) => log::error!(
+                        "Failed to export the slice image to {}: {}",
+                        path.display(),
+                        e
+                    ),
+                }
+            }
+
+            #[cfg(feature = "gltf")]
+            if let Some(path) = state.export_gltf.take()
+                && emulator.initialized()
+            {
+                Self::export_gltf(&path, renderer, state, emulator);
+            }
+
+            if let Some(pick) = state.slice_pick
+                && pick.pressure.is_none()
+                && emulator.initialized()
+            {
+                state.slice_pick = match renderer.probe_slice(pick.uv) {
+                    Ok(pressure) => pressure.map(|pressure| SlicePick {
+                        pressure: Some(pressure),
+                        ..pick
+                    }),
+                    Err(e) => {
+                        log::error!("Failed to probe the slice: {}", e);
+                        None
+                    }
+                };
+            }
+
+            if fading {
+                update_flag.set(UpdateFlag::UPDATE_TRANS_ALPHA, true);
+            }
+
+            if fading || (emulator.initialized() && (state.auto_play || state.recording.is_some()))
+            {
+                if let Some(next_paint_time) = self.next_paint_time() {
+                    return Ok(EventResult::RepaintAt(next_paint_time));
+                } else if cfg!(target_os = "windows") {
                     window.request_redraw();
                 } else {
                     return Ok(EventResult::RepaintNow);
@@ -224,6 +496,65 @@ impl Simulator {
         }
     }
 
+    /// Writes the scene as the `frame`-th image of the recording and advances the system time
+    /// by the recording step, so that the captured clock does not depend on the render speed.
+    fn record_frame(
+        renderer: &mut Renderer,
+        state: &mut State,
+        emulator: &EmulatorWrapper,
+        window: &Window,
+        update_flag: &mut UpdateFlag,
+        frame: u64,
+    ) {
+        let size = window.inner_size();
+        let (width, height) = (size.width.max(1), size.height.max(1));
+        let result = renderer
+            .capture_frame(state, emulator, width, height)
+            .and_then(|data| save_frame(&state.record.dir, frame, width, height, &data))
+            .and_then(|_| {
+                if frame == 0 {
+                    SamplingParams::new(state, renderer.slice_resolution())
+                        .save(state.record.dir.join("params.json"))
+                } else {
+                    Ok(())
+                }
+            });
+        if let Err(e) = result {
+            log::error!(
+                "Failed to write frame {} to {}: {}",
+                frame,
+                state.record.dir.display(),
+                e
+            );
+            state.recording = None;
+            return;
+        }
+
+        if frame + 1 >= state.record.frames() {
+            log::info!(
+                "Recorded {} frames to {}",
+                frame + 1,
+                state.record.dir.display()
+            );
+            state.recording = None;
+        } else {
+            state.real_time = state.real_time.wrapping_add(state.record.step);
+            state.recording = Some(frame + 1);
+            update_flag.set(UpdateFlag::UPDATE_TRANS_STATE, true);
+        }
+    }
+
+    /// Writes the visible transducers and the colored slice to `path` as a binary glTF.
+    #[cfg(feature = "gltf")]
+    fn export_gltf(path: &Path, renderer: &Renderer, state: &State, emulator: &EmulatorWrapper) {
+        match renderer.read_slice().and_then(|(size, data)| {
+            crate::gltf::save_glb(path, state, emulator, Some((size, &data)))
+        }) {
+            Ok(()) => log::info!("Exported the scene to {}", path.display()),
+            Err(e) => log::error!("Failed to export the scene to {}: {}", path.display(), e),
+        }
+    }
+
     fn on_resumed(&mut self, event_loop: &ActiveEventLoop) -> Result<EventResult> {
         if self.window.is_none() {
             self.initialize(event_loop)?;
@@ -232,6 +563,9 @@ impl Simulator {
     }
 
     fn on_window_event(&mut self, event: winit::event::WindowEvent) -> Result<EventResult> {
+        if event != winit::event::WindowEvent::RedrawRequested {
+            self.last_activity = Instant::now();
+        }
         self.update(None);
         if let Some(window) = self.window.as_ref().cloned() {
             match event {
@@ -261,6 +595,7 @@ impl Simulator {
                 }
             }
             UserEvent::Server(signal) => {
+                self.last_activity = Instant::now();
                 self.update(Some(signal));
                 if self.renderer.is_some() {
                     return Ok(EventResult::RepaintNow);
@@ -293,7 +628,7 @@ impl Simulator {
                 Ok(event_result)
             }
             EventResult::RepaintNow => {
-                if cfg!(target_os = "windows") {
+                if cfg!(target_os = "windows") && self.state.max_fps.is_none() {
                     if let Some(ref window) = self.window.as_ref().cloned() {
                         self.update(None);
                         self.run_ui_and_paint(window)
@@ -334,9 +669,68 @@ impl Simulator {
         self.check_redraw_requests(event_loop);
     }
 
+    /// Earliest time the next frame may be painted when `max_fps` is set.
+    fn next_paint_time(&self) -> Option<Instant> {
+        let max_fps = self.state.max_fps?;
+        let last_paint_time = self.last_paint_time?;
+        Some(last_paint_time + Duration::from_secs_f64(1. / max_fps.max(1) as f64))
+    }
+
+    /// Time at which the simulator exits if it stays idle. `None` while a client is connected.
+    fn idle_deadline(&self) -> Option<Instant> {
+        let idle_timeout = self.state.idle_timeout?;
+        if self.client_connected {
+            return None;
+        }
+        Some(self.last_activity + Duration::from_secs(idle_timeout))
+    }
+
+    fn check_idle_timeout(&self, event_loop: &ActiveEventLoop) {
+        let Some(deadline) = self.idle_deadline() else {
+            return;
+        };
+        if Instant::now() >= deadline {
+            log::info!(
+                "No client connected for {} s, exiting",
+                self.state.idle_timeout.unwrap_or_default()
+            );
+            event_loop.exit();
+            return;
+        }
+        wake_at(event_loop, deadline);
+    }
+
+    /// Steps the emulator every `emulator_update_interval` while the clock runs in real time, so
+    /// that the firmware timing does not depend on how often the window is repainted or receives
+    /// events.
+    fn check_emulator_update(&mut self, event_loop: &ActiveEventLoop) {
+        if !self.emulator.initialized()
+            || !self.state.auto_play
+            || self.state.deterministic
+            || self.state.recording.is_some()
+        {
+            self.next_emulator_update = None;
+            return;
+        }
+        let now = Instant::now();
+        let next = *self.next_emulator_update.get_or_insert(now);
+        let next = if now >= next {
+            self.state.real_time = self.state.clock_time();
+            self.step_emulator();
+            (next + Duration::from_nanos(self.state.emulator_update_interval.max(1))).max(now)
+        } else {
+            next
+        };
+        self.next_emulator_update = Some(next);
+        wake_at(event_loop, next);
+    }
+
     fn check_redraw_requests(&mut self, event_loop: &ActiveEventLoop) {
         let now = Instant::now();
         if let Some(next_repaint_time) = self.windows_next_repaint_time {
+            let next_repaint_time = self
+                .next_paint_time()
+                .map_or(next_repaint_time, |t| t.max(next_repaint_time));
             if now >= next_repaint_time {
                 self.windows_next_repaint_time = None;
                 if let Some(ref window) = self.window {
@@ -346,9 +740,33 @@ impl Simulator {
                 event_loop.set_control_flow(ControlFlow::WaitUntil(next_repaint_time));
             }
         }
+
+        self.check_emulator_update(event_loop);
+        self.check_idle_timeout(event_loop);
+    }
+}
+
+/// Makes the event loop wake up at `deadline` at the latest.
+fn wake_at(event_loop: &ActiveEventLoop, deadline: Instant) {
+    match event_loop.control_flow() {
+        ControlFlow::Poll => {}
+        ControlFlow::WaitUntil(t) if t > Instant::now() && t <= deadline => {}
+        _ => event_loop.set_control_flow(ControlFlow::WaitUntil(deadline)),
     }
 }
 
+fn save_frame(dir: &Path, frame: u64, width: u32, height: u32, data: &[u8]) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    image::save_buffer(
+        dir.join(format!("frame_{frame:05}.png")),
+        data,
+        width,
+        height,
+        image::ExtendedColorType::Rgba8,
+    )?;
+    Ok(())
+}
+
 impl ApplicationHandler<UserEvent> for Simulator {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         let event_result = self.on_resumed(event_loop);
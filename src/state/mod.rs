@@ -1,12 +1,28 @@
-use autd3_driver::{
-    common::mm,
-    ethercat::{DcSysTime, ECAT_DC_SYS_TIME_BASE},
-};
+use autd3_driver::{common::mm, ethercat::DcSysTime};
 
 use glam::EulerRot;
 use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    MIN_WINDOW_SIZE, Quaternion, Vector2, Vector3,
+    common::{color::srgb_to_linear, color_map::ColorMap},
+    error::Result,
+    log::{self, LogLevel},
+};
 
-use crate::{Quaternion, Vector2, Vector3};
+/// System time at startup in deterministic mode.
+pub const DETERMINISTIC_START_TIME: u64 = 0;
+/// Default scrub window, covering one period of modulations down to 100 Hz.
+const DEFAULT_SCRUB_WINDOW: u64 = 10_000_000;
+/// Default interval in ns of the emulator steps during auto play. Every step wakes the event loop
+/// and steps the firmware of every device, so the CPU cost grows with the rate and the number of
+/// devices. 10 ms is still below the period of modulations up to 100 Hz.
+const DEFAULT_EMULATOR_UPDATE_INTERVAL: u64 = 10_000_000;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CameraState {
@@ -16,6 +32,9 @@ pub struct CameraState {
     pub near_clip: f32,
     pub far_clip: f32,
     pub move_speed: f32,
+    /// Distance moved per wheel step, in units of `move_speed`.
+    pub zoom_speed: f32,
+    pub invert_zoom: bool,
 }
 
 impl CameraState {
@@ -29,12 +48,91 @@ impl CameraState {
     }
 }
 
+/// Quantity of the field shown on the slice.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FieldQuantity {
+    /// Pressure amplitude, or its RMS, from zero to the max pressure.
+    #[default]
+    Pressure,
+    /// Phase of the field from 0 to 2π, with the same sign and offset as the transducer colors.
+    Phase,
+    /// Intensity relative to that of a plane wave at the max pressure.
+    Intensity,
+    /// Sound pressure level re 20 µPa, over a fixed range below that of the max pressure.
+    Spl,
+    /// Phase as the hue, matching the transducer colors, with the brightness given by the
+    /// pressure. This does not use the color map.
+    PhasePressure,
+}
+
+/// Range of the resolution of the procedurally generated transducer marker.
+pub const MARKER_RESOLUTION_RANGE: std::ops::RangeInclusive<u32> = 8..=4096;
+
+/// Maximum number of contour levels on the slice, as in shader.wgsl.
+pub const MAX_CONTOURS: usize = 16;
+
+/// Reference pressure of the sound pressure level in Pa, as in shader.wgsl.
+pub const SPL_REFERENCE: f32 = 20e-6;
+/// Range in dB of the sound pressure level shown below that of the max pressure, as in shader.wgsl.
+pub const SPL_RANGE: f32 = 60.;
+/// Lower bound of the normalized range the color map spans, as in shader.wgsl.
+pub const MIN_COLOR_MAP_RANGE: f32 = 1e-6;
+
+impl FieldQuantity {
+    pub const ALL: [Self; 5] = [
+        Self::Pressure,
+        Self::Phase,
+        Self::Intensity,
+        Self::Spl,
+        Self::PhasePressure,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Pressure => "Pressure",
+            Self::Phase => "Phase",
+            Self::Intensity => "Intensity",
+            Self::Spl => "SPL",
+            Self::PhasePressure => "Phase and pressure",
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SliceState {
     pub pos: Vector3,
     pub rot: Vector3,
     pub size: Vector2,
+    /// Texels along the longer side of the slice. The field is sampled once per mm, up to about
+    /// a million texels, if `None`.
+    pub resolution: Option<u32>,
     pub pressure_max: f32,
+    /// Pressure at the low end of the color map, below which the field is not colored apart.
+    pub pressure_min: f32,
+    pub color_map: ColorMap,
+    /// Sample the color maps from the high end to the low end.
+    pub reversed: bool,
+    pub quantity: FieldQuantity,
+    /// Show the right half of the slice with `split_color_map` and `split_pressure_max`,
+    /// to compare two representations of the same field.
+    pub split: bool,
+    pub split_color_map: ColorMap,
+    pub split_pressure_max: f32,
+    /// Exponent applied to the normalized pressure before the color map lookup.
+    pub color_gamma: f32,
+    /// Normalize the pressure and intensity by `log10(1 + x) / log10(1 + max)`, which spreads
+    /// the colors over quiet regions.
+    pub log_scale: bool,
+    /// Show the RMS of the instantaneous pressure, i.e. the amplitude over √2, instead of the amplitude.
+    pub rms: bool,
+    /// Weight of the previous frame blended into each new one, leaving trails of moving foci.
+    /// The slice is overwritten every frame if zero.
+    pub persistence: f32,
+    pub mirror: bool,
+    pub mirror_pos: Vector3,
+    pub mirror_normal: Vector3,
+    /// Pressures in Pa at which contour lines are drawn over the slice, up to [`MAX_CONTOURS`].
+    pub contours: Vec<f32>,
 }
 
 impl SliceState {
@@ -46,6 +144,147 @@ impl SliceState {
             self.rot.z.to_radians(),
         )
     }
+
+    /// `pressure_min`, kept just below the max pressures of both halves of the slice so that
+    /// the color map spans a nonempty range.
+    pub fn clamped_pressure_min(&self) -> f32 {
+        let max = if self.split {
+            self.pressure_max.min(self.split_pressure_max)
+        } else {
+            self.pressure_max
+        };
+        self.pressure_min.min(max.next_down())
+    }
+
+    /// Position of the pressure `p` in the color map, as the slice shader maps it.
+    pub fn color_map_pos(&self, p: f32) -> f32 {
+        let min = self.clamped_pressure_min();
+        let max = self.pressure_max;
+        let t = if self.log_scale {
+            ((1. + p).log10() - (1. + min).log10())
+                / ((1. + max).log10() - (1. + min).log10()).max(MIN_COLOR_MAP_RANGE)
+        } else {
+            (p - min) / (max - min).max(MIN_COLOR_MAP_RANGE)
+        };
+        t.clamp(0., 1.).powf(self.color_gamma)
+    }
+
+    /// Returns the RGB colors of `values` in `[0, 1]` in `color_map`, reversed if `reversed`.
+    pub fn colors(
+        &self,
+        color_map: ColorMap,
+        values: impl IntoIterator<Item = f64>,
+    ) -> Vec<[f32; 3]> {
+        let reversed = self.reversed;
+        color_map.colors(
+            values
+                .into_iter()
+                .map(move |v| if reversed { 1. - v } else { v }),
+        )
+    }
+
+    /// World coordinates of the slice corners, counter-clockwise from the bottom left.
+    pub fn corners(&self) -> [Vector3; 4] {
+        let rotation = self.rotation();
+        let right = rotation * Vector3::X * self.size.x / 2.;
+        let up = rotation * Vector3::Y * self.size.y / 2.;
+        [
+            self.pos - right - up,
+            self.pos + right - up,
+            self.pos + right + up,
+            self.pos - right + up,
+        ]
+    }
+
+    /// Point where the ray from `origin` along `dir` hits the slice, if it does.
+    pub fn intersect(&self, origin: Vector3, dir: Vector3) -> Option<Vector3> {
+        let rotation = self.rotation();
+        let normal = rotation * Vector3::Z;
+        let denom = dir.dot(normal);
+        if denom.abs() < f32::EPSILON {
+            return None;
+        }
+        let t = (self.pos - origin).dot(normal) / denom;
+        if t < 0. {
+            return None;
+        }
+        let hit = origin + dir * t;
+        let local = rotation.inverse() * (hit - self.pos);
+        (local.x.abs() <= self.size.x / 2. && local.y.abs() <= self.size.y / 2.).then_some(hit)
+    }
+
+    /// Position of `point` on the slice from its bottom left corner, in units of its size,
+    /// if the point lies within the slice.
+    pub fn uv(&self, point: Vector3) -> Option<Vector2> {
+        let local = self.rotation().inverse() * (point - self.pos);
+        let uv = Vector2::new(local.x / self.size.x, local.y / self.size.y) + 0.5;
+        ((0.0..=1.0).contains(&uv.x) && (0.0..=1.0).contains(&uv.y)).then_some(uv)
+    }
+}
+
+/// Point of the slice picked with the mouse, and the pressure read back from the slice there.
+#[derive(Debug, Clone, Copy)]
+pub struct SlicePick {
+    pub point: Vector3,
+    /// Position on the slice from its bottom left corner, in units of its size.
+    pub uv: Vector2,
+    /// Complex pressure in Pa, `None` until it has been read back.
+    pub pressure: Option<Vector2>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProbeState {
+    pub pos: Vector3,
+    pub show_grid: bool,
+    pub grid_size: u32,
+    pub grid_spacing: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GridState {
+    pub show: bool,
+    pub spacing: f32,
+    pub extent: f32,
+    pub height: f32,
+}
+
+/// Settings of the frame sequence recording.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordState {
+    /// Folder the numbered PNG frames are written to.
+    pub dir: PathBuf,
+    /// Span of the system time covered by a recording, in ns.
+    pub duration: u64,
+    /// System time advanced between two frames, in ns.
+    pub step: u64,
+}
+
+impl RecordState {
+    /// Number of frames written by a recording.
+    pub fn frames(&self) -> u64 {
+        self.duration / self.step.max(1) + 1
+    }
+}
+
+/// Presentation mode of the surface, used if supported.
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy, Serialize, Deserialize)]
+pub enum PresentMode {
+    /// Chosen by the driver according to `vsync`.
+    #[default]
+    Auto,
+    Fifo,
+    FifoRelaxed,
+    Mailbox,
+    Immediate,
+}
+
+/// Format of the depth buffer.
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy, Serialize, Deserialize)]
+pub enum DepthFormat {
+    #[default]
+    Depth32Float,
+    /// At least 24 bits, which may be cheaper on some GPUs.
+    Depth24Plus,
 }
 
 #[derive(Debug, PartialEq, Default, Clone, Serialize, Deserialize)]
@@ -57,31 +296,186 @@ pub enum Tab {
     Info,
 }
 
+impl std::str::FromStr for Tab {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "slice" => Ok(Self::Slice),
+            "camera" => Ok(Self::Camera),
+            "config" => Ok(Self::Config),
+            "info" => Ok(Self::Info),
+            _ => Err(format!(
+                "Invalid tab: {s} (expected slice, camera, config or info)"
+            )),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct State {
     pub window_size: (u32, u32),
+    pub window_title: Option<String>,
     pub ui_scale: f32,
+    /// Maximum number of layout passes egui may run per frame (at least 1).
+    /// More passes settle layouts that change size in the same frame, at a higher CPU cost.
+    pub max_passes: u32,
+    /// Width of the grid and plot lines in points, scaled with `ui_scale` and the display DPI.
+    pub overlay_line_width: f32,
     pub camera: CameraState,
     pub slice: SliceState,
+    pub probe: ProbeState,
+    pub grid: GridState,
+    pub record: RecordState,
+    /// Index of the next frame to capture while a recording is in progress.
+    #[serde(skip)]
+    pub recording: Option<u64>,
+    /// File the slice field is exported to as a NumPy array on the next frame.
+    #[serde(skip)]
+    pub export_npy: Option<PathBuf>,
+    /// File the colored slice is saved to as a PNG image on the next frame.
+    #[serde(skip)]
+    pub export_png: Option<PathBuf>,
+    /// File the slice field is exported to as CSV on the next frame.
+    #[serde(skip)]
+    pub export_csv: Option<PathBuf>,
+    /// Point of the slice last clicked, whose pressure is read back on the next frame.
+    #[serde(skip)]
+    pub slice_pick: Option<SlicePick>,
+    /// File the scene is exported to as binary glTF on the next frame.
+    #[cfg(feature = "gltf")]
+    #[serde(skip)]
+    pub export_gltf: Option<PathBuf>,
     pub sound_speed: f32,
     pub background: egui::Color32,
     pub mod_enable: bool,
+    pub show_transducers: bool,
+    /// Draw the slice before the transducers, so that translucent transducers in front of it
+    /// blend over it instead of hiding it with their depth.
+    pub slice_first: bool,
+    /// Skip drawing transducers outside the camera frustum.
+    pub frustum_culling: bool,
+    /// Decode the sRGB colors of the slice, transducers and background into linear light before drawing them.
+    ///
+    /// The surface encodes its input into sRGB, so without this they are encoded twice and
+    /// appear lighter on screen than in the color map, the exported slice and the legend.
+    /// Screenshots are read back from the surface and match the screen either way.
+    /// Has no effect if the surface falls back to a format without sRGB encoding.
+    pub srgb_correct: bool,
+    /// Accumulate the slice field in f16 if the GPU supports it. Only the accumulator is in f16,
+    /// so the speedup depends on the GPU, at a relative error in the order of 1e-3 that grows
+    /// with the number of transducers.
+    pub half_precision: bool,
+    /// Format of the depth buffer, applied on startup.
+    pub depth_format: DepthFormat,
+    /// Render without a depth buffer while only the slice is drawn, i.e. the transducers and the
+    /// grid are hidden.
+    pub skip_slice_depth: bool,
+    /// Show connection instructions while no client is connected.
+    pub show_hints: bool,
+    /// Position of the control panel, restored on startup.
+    pub panel_pos: Option<egui::Pos2>,
+    /// Content size of the control panel, restored on startup.
+    pub panel_size: Option<egui::Vec2>,
+    /// Keep the control panel from being moved or resized.
+    pub panel_locked: bool,
+    /// Show the point of the slice under the mouse cursor at the bottom of the control panel.
+    pub show_cursor_pos: bool,
+    /// Show a legend of the slice color map at the bottom right of the window.
+    pub show_legend: bool,
+    /// Draw transducers as arrows pointing in the direction of their phase.
+    pub phase_arrows: bool,
+    /// Keep the current transducer colors while the transducer states change.
+    pub freeze_trans_color: bool,
+    /// Gray out the transducers whose phase and amplitude did not change with the last sent frame.
+    pub highlight_changes: bool,
+    /// Saturation of the transducer colors, whose hue is given by the phase and value by the amplitude.
+    pub trans_saturation: f32,
+    /// Hue of the transducer colors at zero phase, in degrees.
+    pub trans_hue_offset: f32,
+    /// Phase in degrees subtracted from the displayed phase of the transducers and the slice,
+    /// to align its zero with another convention. The field itself is not affected.
+    pub phase_offset: f32,
+    /// Duration in seconds of the fade when the transducers are shown or hidden. Disabled if zero.
+    pub trans_fade_duration: f32,
+    /// Resolution of the procedurally generated transducer marker, within [`MARKER_RESOLUTION_RANGE`].
+    /// The bundled image is used if `None`.
+    pub marker_resolution: Option<u32>,
     pub auto_play: bool,
+    /// Interval in ns at which the firmware emulator is stepped during auto play, independently
+    /// of the repaints. Shorter intervals follow the firmware timing more closely at a higher CPU cost.
+    pub emulator_update_interval: u64,
     pub real_time: u64,
+    /// Offset in ns added to `real_time` to align the simulator with the time base set by the client.
+    #[serde(skip)]
+    pub time_offset: Option<i64>,
     pub time_scale: f32,
     pub port: u16,
+    pub stream_port: Option<u16>,
     pub vsync: bool,
+    /// Explicit present mode. Falls back to the one chosen by `vsync` if not supported by the surface.
+    pub present_mode: PresentMode,
+    /// Desired maximum number of frames queued for presentation (0-3).
+    /// Lower values reduce input latency, higher values may improve throughput on some drivers.
+    pub frame_latency: u32,
+    /// Upper bound of the frame rate. Unlimited if `None`.
+    pub max_fps: Option<u32>,
     pub settings_dir: String,
     pub time_step: i32,
+    /// Whether manual steps are also multiplied by `time_scale`.
+    pub scale_time_step: bool,
+    /// Length in ns of the window the system time is scrubbed over while paused.
+    pub scrub_window: u64,
+    /// Start of the scrub window, set to the system time when the window is reset or left.
+    #[serde(skip)]
+    pub scrub_start: Option<u64>,
     pub debug: bool,
+    /// Enable the CPU-side validation layer of the graphics API. Implied by `debug`.
+    pub validation: bool,
+    /// Additionally enable the GPU-based validation, which is much slower.
+    pub gpu_validation: bool,
+    /// Modulation file pushed through the emulator on the next update. Only offered in debug mode.
+    #[serde(skip)]
+    pub preview_modulation: Option<PathBuf>,
+    pub log_level: LogLevel,
+    /// Start from [`DETERMINISTIC_START_TIME`] and advance the system time by the step per frame
+    /// instead of following the wall clock.
+    pub deterministic: bool,
     pub tab: Tab,
+    /// Exit after this many seconds without a connected client or user input. Disabled if `None`.
+    /// Only set from the command line.
+    #[serde(skip)]
+    pub idle_timeout: Option<u64>,
+    /// Device whose origin the positions in the UI are shown relative to. World origin if `None`.
+    pub origin_device: Option<usize>,
+    pub slice_pos_lock: [bool; 3],
+    /// Distance the slice is moved per arrow key press.
+    pub slice_nudge_step: f32,
+    /// Named slices, the first nine of which are recalled with the number keys.
+    pub slice_presets: Vec<(String, SliceState)>,
+    pub camera_pos_lock: [bool; 3],
+    pub camera_pitch_clamp: bool,
+    /// Device whose firmware info is shown in the Info tab. All devices if `None`.
+    pub info_device: Option<usize>,
+    /// Whether a reference snapshot of the transducer states is held for the slice.
+    #[serde(skip)]
+    pub slice_reference: bool,
+    /// Compute the slice from the reference snapshot instead of the live states.
+    #[serde(skip)]
+    pub slice_show_reference: bool,
+    pub slice_aspect_lock: bool,
+    /// Width to height ratio kept while `slice_aspect_lock` is set.
+    pub slice_aspect: f32,
 }
 
 impl std::default::Default for State {
     fn default() -> Self {
         Self {
             window_size: (800, 600),
+            window_title: None,
             ui_scale: 1.0,
+            max_passes: 2,
+            overlay_line_width: 1.0,
             camera: CameraState {
                 #[cfg(not(feature = "unity"))]
                 pos: Vector3::new(86.6252 * mm, -533.2867 * mm, 150.0 * mm),
@@ -95,6 +489,8 @@ impl std::default::Default for State {
                 near_clip: 0.1 * mm,
                 far_clip: 1000. * mm,
                 move_speed: 1. * mm,
+                zoom_speed: 10.,
+                invert_zoom: false,
             },
             slice: SliceState {
                 #[cfg(not(feature = "unity"))]
@@ -106,52 +502,340 @@ impl std::default::Default for State {
                 #[cfg(feature = "unity")]
                 rot: Vector3::new(0.0, 0., 0.),
                 size: Vector2::new(300.0 * mm, 300.0 * mm),
+                resolution: None,
                 pressure_max: 10000.,
+                pressure_min: 0.,
+                color_map: ColorMap::Inferno,
+                reversed: false,
+                quantity: FieldQuantity::Pressure,
+                split: false,
+                split_color_map: ColorMap::Gray,
+                split_pressure_max: 10000.,
+                color_gamma: 1.0,
+                log_scale: false,
+                rms: false,
+                persistence: 0.,
+                mirror: false,
+                #[cfg(not(feature = "unity"))]
+                mirror_pos: Vector3::new(86.6252 * mm, 66.7133 * mm, 0.),
+                #[cfg(feature = "unity")]
+                mirror_pos: Vector3::new(86.6252 * mm, 0., 66.7133 * mm),
+                mirror_normal: Vector3::X,
+                contours: Vec::new(),
+            },
+            probe: ProbeState {
+                #[cfg(not(feature = "unity"))]
+                pos: Vector3::new(86.6252 * mm, 66.7133 * mm, 150.0 * mm),
+                #[cfg(feature = "unity")]
+                pos: Vector3::new(86.6252 * mm, 150.0 * mm, 66.7133 * mm),
+                show_grid: false,
+                grid_size: 5,
+                grid_spacing: 1. * mm,
+            },
+            grid: GridState {
+                show: false,
+                spacing: 10. * mm,
+                extent: 500. * mm,
+                height: 0.,
             },
+            record: RecordState {
+                dir: PathBuf::from("frames"),
+                duration: 1_000_000_000,
+                step: 10_000_000,
+            },
+            recording: None,
+            export_npy: None,
+            export_png: None,
+            export_csv: None,
+            slice_pick: None,
+            #[cfg(feature = "gltf")]
+            export_gltf: None,
             background: egui::Color32::from_rgb(60, 60, 60),
             sound_speed: 340.0e3 * mm,
             mod_enable: false,
+            show_transducers: true,
+            slice_first: false,
+            frustum_culling: false,
+            srgb_correct: false,
+            half_precision: false,
+            depth_format: DepthFormat::Depth32Float,
+            skip_slice_depth: false,
+            show_hints: true,
+            panel_pos: None,
+            panel_size: None,
+            panel_locked: false,
+            show_cursor_pos: true,
+            show_legend: true,
+            phase_arrows: false,
+            freeze_trans_color: false,
+            highlight_changes: false,
+            trans_saturation: 1.,
+            trans_fade_duration: 0.,
+            trans_hue_offset: 0.,
+            phase_offset: 0.,
+            marker_resolution: None,
             auto_play: true,
+            emulator_update_interval: DEFAULT_EMULATOR_UPDATE_INTERVAL,
             real_time: DcSysTime::now().sys_time(),
+            time_offset: None,
             time_scale: 1.0,
             port: 8080,
+            stream_port: None,
             vsync: true,
+            present_mode: PresentMode::Auto,
+            frame_latency: 0,
+            max_fps: None,
             settings_dir: String::new(),
             time_step: 1000000,
+            scale_time_step: false,
+            scrub_window: DEFAULT_SCRUB_WINDOW,
+            scrub_start: None,
             debug: false,
+            validation: false,
+            gpu_validation: false,
+            preview_modulation: None,
+            log_level: LogLevel::default(),
+            deterministic: false,
             tab: Tab::default(),
+            idle_timeout: None,
+            origin_device: None,
+            slice_pos_lock: [false; 3],
+            slice_nudge_step: 1. * mm,
+            slice_presets: Vec::new(),
+            camera_pos_lock: [false; 3],
+            camera_pitch_clamp: false,
+            info_device: None,
+            slice_reference: false,
+            slice_show_reference: false,
+            slice_aspect_lock: false,
+            slice_aspect: 1.0,
         }
     }
 }
 
 impl State {
+    /// Creates a state with default settings listening on `port`.
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the window size. Each dimension is at least 64.
+    pub fn with_window_size(mut self, width: u32, height: u32) -> Self {
+        self.window_size = (width.max(MIN_WINDOW_SIZE), height.max(MIN_WINDOW_SIZE));
+        self
+    }
+
+    pub fn with_window_title(mut self, title: impl Into<String>) -> Self {
+        self.window_title = Some(title.into());
+        self
+    }
+
+    /// Sets the UI scale, clamped to `1.0..=10.0`.
+    pub fn with_ui_scale(mut self, ui_scale: f32) -> Self {
+        self.ui_scale = ui_scale.clamp(1.0, 10.0);
+        self
+    }
+
+    pub fn with_sound_speed(mut self, sound_speed: f32) -> Self {
+        self.sound_speed = sound_speed;
+        self
+    }
+
+    pub fn with_background(mut self, background: egui::Color32) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn with_auto_play(mut self, auto_play: bool) -> Self {
+        self.auto_play = auto_play;
+        self
+    }
+
+    /// Sets the time scale. Negative values are clamped to zero.
+    pub fn with_time_scale(mut self, time_scale: f32) -> Self {
+        self.time_scale = time_scale.max(0.0);
+        self
+    }
+
+    pub fn with_stream_port(mut self, stream_port: u16) -> Self {
+        self.stream_port = Some(stream_port);
+        self
+    }
+
+    pub fn with_vsync(mut self, vsync: bool) -> Self {
+        self.vsync = vsync;
+        self
+    }
+
+    /// Sets the desired maximum frame latency, clamped to `0..=3`.
+    pub fn with_frame_latency(mut self, frame_latency: u32) -> Self {
+        self.frame_latency = frame_latency.min(3);
+        self
+    }
+
+    pub fn with_settings_dir(mut self, settings_dir: impl Into<String>) -> Self {
+        self.settings_dir = settings_dir.into();
+        self
+    }
+
+    pub fn with_log_level(mut self, log_level: LogLevel) -> Self {
+        self.log_level = log_level;
+        self
+    }
+
+    pub fn with_show_transducers(mut self, show_transducers: bool) -> Self {
+        self.show_transducers = show_transducers;
+        self
+    }
+
+    /// Enables or disables deterministic mode. Enabling it resets the system time to [`DETERMINISTIC_START_TIME`].
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        if deterministic {
+            self.real_time = DETERMINISTIC_START_TIME;
+        }
+        self
+    }
+
+    /// Time advanced by a manual step, or by each frame in deterministic mode, in ns.
+    pub fn effective_time_step(&self) -> u64 {
+        if self.scale_time_step {
+            (self.time_step as f64 * self.time_scale as f64) as u64
+        } else {
+            self.time_step as u64
+        }
+    }
+
+    /// Current time of the system clock scaled by `time_scale`, in ns.
+    pub fn clock_time(&self) -> u64 {
+        (DcSysTime::now().sys_time() as f64 * self.time_scale as f64) as _
+    }
+
     pub fn system_time(&self) -> DcSysTime {
-        DcSysTime::from_utc(ECAT_DC_SYS_TIME_BASE + std::time::Duration::from_nanos(self.real_time))
-            .unwrap()
+        DcSysTime::new(
+            self.real_time
+                .wrapping_add_signed(self.time_offset.unwrap_or_default()),
+        )
+    }
+
+    pub fn title(&self) -> String {
+        self.window_title
+            .clone()
+            .unwrap_or_else(|| format!("AUTD3 Simulator :{}", self.port))
     }
 
-    pub fn background(&self) -> wgpu::Color {
+    /// Clear color of a render target, which encodes its input into sRGB if `srgb_target` is set.
+    pub fn background(&self, srgb_target: bool) -> wgpu::Color {
+        let c = |v: u8| {
+            let v = v as f32 / 255.;
+            (if self.srgb_correct && srgb_target {
+                srgb_to_linear(v)
+            } else {
+                v
+            }) as f64
+        };
         wgpu::Color {
-            r: self.background[0] as f64 / 255.,
-            g: self.background[1] as f64 / 255.,
-            b: self.background[2] as f64 / 255.,
+            r: c(self.background[0]),
+            g: c(self.background[1]),
+            b: c(self.background[2]),
             a: self.background[3] as f64 / 255.,
         }
     }
 
+    /// Writes the state to `path` as pretty-printed JSON.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    /// Reads a state previously written by [`State::save`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// Reads the settings at `path`, or the default settings if the file is missing or invalid.
+    pub fn load_or_default(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        if !path.exists() {
+            log::info!(
+                "Settings file ({}) not found, using default settings.",
+                path.display()
+            );
+            return Default::default();
+        }
+        Self::load(path).unwrap_or_else(|e| {
+            log::error!(
+                "Failed to parse settings file ({}): {}, using default settings.",
+                path.display(),
+                e
+            );
+            Default::default()
+        })
+    }
+
     pub fn merge(&mut self, state: State) {
         self.window_size = state.window_size;
+        self.window_title = state.window_title;
         self.ui_scale = state.ui_scale;
+        self.overlay_line_width = state.overlay_line_width;
+        self.max_passes = state.max_passes;
         self.camera = state.camera;
         self.slice = state.slice;
+        self.slice_presets = state.slice_presets;
+        self.probe = state.probe;
+        self.grid = state.grid;
+        self.record = state.record;
         self.sound_speed = state.sound_speed;
         self.background = state.background;
         self.mod_enable = state.mod_enable;
+        self.show_transducers = state.show_transducers;
+        self.slice_first = state.slice_first;
+        self.frustum_culling = state.frustum_culling;
+        self.srgb_correct = state.srgb_correct;
+        self.half_precision = state.half_precision;
+        self.depth_format = state.depth_format;
+        self.skip_slice_depth = state.skip_slice_depth;
+        self.show_hints = state.show_hints;
+        self.panel_pos = state.panel_pos;
+        self.panel_size = state.panel_size;
+        self.panel_locked = state.panel_locked;
+        self.show_cursor_pos = state.show_cursor_pos;
+        self.show_legend = state.show_legend;
+        self.phase_arrows = state.phase_arrows;
+        self.marker_resolution = state.marker_resolution;
+        self.trans_saturation = state.trans_saturation;
+        self.trans_fade_duration = state.trans_fade_duration;
+        self.trans_hue_offset = state.trans_hue_offset;
+        self.phase_offset = state.phase_offset;
         self.auto_play = state.auto_play;
+        self.emulator_update_interval = state.emulator_update_interval;
         self.time_scale = state.time_scale;
+        self.scale_time_step = state.scale_time_step;
+        self.scrub_window = state.scrub_window;
         self.port = state.port;
+        self.stream_port = state.stream_port;
         self.vsync = state.vsync;
+        self.present_mode = state.present_mode;
+        self.frame_latency = state.frame_latency;
+        self.max_fps = state.max_fps;
         self.settings_dir = state.settings_dir;
         self.debug = state.debug;
+        self.log_level = state.log_level;
+        self.tab = state.tab;
+        self.origin_device = state.origin_device;
+        self.slice_pos_lock = state.slice_pos_lock;
+        self.slice_nudge_step = state.slice_nudge_step;
+        self.camera_pos_lock = state.camera_pos_lock;
+        self.info_device = state.info_device;
+        self.camera_pitch_clamp = state.camera_pitch_clamp;
+        self.freeze_trans_color = state.freeze_trans_color;
+        self.highlight_changes = state.highlight_changes;
+        self.slice_aspect_lock = state.slice_aspect_lock;
+        self.slice_aspect = state.slice_aspect;
     }
 }
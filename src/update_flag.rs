@@ -14,6 +14,14 @@ impl UpdateFlag {
 
     pub const UPDATE_CONFIG: Self = Self(1 << 7);
 
+    pub const UPDATE_GRID: Self = Self(1 << 8);
+
+    pub const UPDATE_SURFACE: Self = Self(1 << 9);
+
+    pub const UPDATE_REFERENCE: Self = Self(1 << 10);
+
+    pub const UPDATE_SLICE_RESOLUTION: Self = Self(1 << 11);
+
     pub const fn empty() -> Self {
         Self(0)
     }
@@ -23,11 +31,14 @@ impl UpdateFlag {
             Self::UPDATE_SLICE_COLOR_MAP.0
                 | Self::UPDATE_SLICE_POS.0
                 | Self::UPDATE_SLICE_SIZE.0
+                | Self::UPDATE_SLICE_RESOLUTION.0
                 | Self::UPDATE_CAMERA.0
                 | Self::UPDATE_TRANS_STATE.0
                 | Self::UPDATE_TRANS_ALPHA.0
                 | Self::UPDATE_TRANS_POS.0
-                | Self::UPDATE_CONFIG.0,
+                | Self::UPDATE_CONFIG.0
+                | Self::UPDATE_GRID.0
+                | Self::UPDATE_SURFACE.0,
         )
     }
 